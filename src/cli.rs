@@ -0,0 +1,486 @@
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use databento::dbn::Schema;
+use time::{macros::format_description, Date};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use databento_toolkit::commands::budget::budget_from_env;
+use databento_toolkit::commands::get_quote::QuoteRequest;
+use databento_toolkit::downloader::decode::default_concurrency;
+use databento_toolkit::downloader::output::{DecodeFormat, DecodeOptions};
+use databento_toolkit::downloader::parquet_format::{ParquetCompression, ParquetOptions};
+use databento_toolkit::downloader::text_format::{CsvOptions, DisplayTimezone};
+use databento_toolkit::processor::continuous::{AdjustmentMode, ContinuousOptions};
+use databento_toolkit::processor::resample::{BarAlignment, BarInterval};
+use databento_toolkit::processor::sessions::SessionFilter;
+use databento_toolkit::processor::validate::ValidationOptions;
+use databento_toolkit::processor::{unique_contract_files, ContractFile};
+use databento_toolkit::ledger::SpendGrouping;
+use databento_toolkit::storage::CatalogQuery;
+use databento_toolkit::{product_registry, PeriodOptions, RollCalendar, RollPolicy, RollSignal};
+
+/// Command line interface. Running without a subcommand starts the GUI.
+#[derive(Parser, Debug)]
+#[command(name = "databento_toolkit", version, about = "Databento Toolkit (Unofficial)")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Estimate the cost of a historical data request without downloading it
+    Quote(QuoteArgs),
+    /// Download contract windows for one or more products, after checking their quoted cost against the budget
+    Download(DownloadCommandArgs),
+    /// Decode downloaded `.dbn.zst` files to NDJSON, CSV, Parquet or Arrow IPC
+    Decode(DecodeArgs),
+    /// Find the day volume or open interest moves to the next contract and write a roll calendar
+    DetectRolls(DetectRollsArgs),
+    /// Stitch the downloaded contracts of a product into one continuous series
+    Continuous(ContinuousArgs),
+    /// Aggregate downloaded 1-minute bars into longer bars
+    Resample(ResampleArgs),
+    /// Keep regular or extended trading hours of downloaded bars, optionally as one bar per session
+    Sessions(SessionsArgs),
+    /// Check downloaded files for gaps, duplicates, bad records and price jumps
+    Validate(ValidateArgs),
+    /// List catalogued files, optionally indexing files downloaded before the catalog existed
+    Catalog(CatalogArgs),
+    /// Summarize what downloads cost, by month, product root and schema
+    Spend(SpendArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct QuoteArgs {
+    /// First date of the request (YYYY-MM-DD)
+    #[arg(long)]
+    pub start: String,
+
+    /// Last date of the request (YYYY-MM-DD)
+    #[arg(long)]
+    pub end: String,
+
+    /// Raw contract symbol to quote
+    #[arg(long, default_value = "CLN3")]
+    pub symbol: String,
+
+    /// Databento dataset code (defaults to the dataset of the symbol's product)
+    #[arg(long)]
+    pub dataset: Option<String>,
+
+    /// Databento schema to quote, e.g. trades, mbp-10 or definition
+    #[arg(long, default_value = "ohlcv-1m")]
+    pub schema: Schema,
+}
+
+#[derive(Args, Debug)]
+pub struct DownloadArgs {
+    /// First date of the request (YYYY-MM-DD)
+    #[arg(long)]
+    pub start: String,
+
+    /// Last date of the request (YYYY-MM-DD)
+    #[arg(long)]
+    pub end: String,
+
+    /// Product roots to download, e.g. CL,ES
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub symbols: Vec<String>,
+
+    /// Folder the per-product directories are created in
+    #[arg(long, default_value = "Hist_Fut_Data")]
+    pub output: String,
+
+    /// Also download micro siblings (MES, MCL, ...) over the same windows
+    #[arg(long)]
+    pub include_micros: bool,
+
+    /// Roll policy override as ROOT=WINDOW[@ANCHOR], e.g. CL=before_expiry:20:0 or ZN=@last_trade.
+    /// May be repeated
+    #[arg(long = "roll", value_name = "ROOT=POLICY")]
+    pub rolls: Vec<String>,
+
+    /// Roll calendar written by `detect-rolls`; moves each window to the detected roll date
+    #[arg(long)]
+    pub roll_calendar: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct DownloadCommandArgs {
+    #[command(flatten)]
+    pub download: DownloadArgs,
+
+    /// Databento schema to download, e.g. trades, tbbo, mbp-1, mbp-10, mbo, ohlcv-1s/1h/1d, definition,
+    /// statistics or status. Schemas other than ohlcv-1m are saved under OUTPUT/ROOT/SCHEMA
+    #[arg(long, default_value = "ohlcv-1m")]
+    pub schema: Schema,
+
+    /// Refuse to download when the quoted cost exceeds this many USD [default: DATABENTO_BUDGET_USD, else no cap]
+    #[arg(long, value_name = "USD")]
+    pub budget: Option<f64>,
+}
+
+#[derive(Args, Debug)]
+pub struct DetectRollsArgs {
+    #[command(flatten)]
+    pub download: DownloadArgs,
+
+    /// `volume` (from downloaded OHLCV files) or `open_interest` (from statistics files, downloaded first
+    /// after their quoted cost is checked against the budget)
+    #[arg(long, default_value = "volume")]
+    pub signal: RollSignal,
+
+    /// Consecutive days the next contract must lead before the roll is confirmed
+    #[arg(long, default_value_t = 1)]
+    pub confirm_days: usize,
+
+    /// Where the roll calendar JSON is written
+    #[arg(long, default_value = "roll_calendar.json")]
+    pub calendar: String,
+
+    /// Refuse to download open interest statistics when their quoted cost exceeds this many USD
+    /// [default: DATABENTO_BUDGET_USD, else no cap]
+    #[arg(long, value_name = "USD")]
+    pub budget: Option<f64>,
+}
+
+#[derive(Args, Debug)]
+pub struct ContinuousArgs {
+    /// Product root, e.g. ES
+    #[arg(long)]
+    pub symbol: String,
+
+    /// Folder holding the per-product directories
+    #[arg(long, default_value = "Hist_Fut_Data")]
+    pub data: String,
+
+    /// `unadjusted`, `difference` (back-adjusted) or `ratio`
+    #[arg(long, default_value = "unadjusted")]
+    pub adjustment: AdjustmentMode,
+
+    /// Roll calendar written by `detect-rolls`; scheduled roll dates are used without it
+    #[arg(long)]
+    pub roll_calendar: Option<String>,
+
+    /// Roll policy override as ROOT=WINDOW[@ANCHOR] for contracts the roll calendar does not cover,
+    /// e.g. ZN=@last_trade. May be repeated
+    #[arg(long = "roll", value_name = "ROOT=POLICY")]
+    pub rolls: Vec<String>,
+
+    /// Output file (defaults to `{data}/{symbol}/{symbol}_continuous_{adjustment}.json`)
+    #[arg(long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ResampleArgs {
+    /// A downloaded contract file, or a folder whose contract files are all resampled
+    #[arg(long)]
+    pub input: String,
+
+    /// Bar length, e.g. 5m, 15m, 1h or 1d
+    #[arg(long)]
+    pub interval: BarInterval,
+
+    /// Anchor bars at `midnight` UTC or at the Globex `session` open
+    #[arg(long, default_value = "midnight")]
+    pub align: BarAlignment,
+}
+
+#[derive(Args, Debug)]
+pub struct SessionsArgs {
+    /// A downloaded contract file, or a folder whose contract files are all processed
+    #[arg(long)]
+    pub input: String,
+
+    /// Part of each session to keep: `rth`, `eth` or `all`
+    #[arg(long, default_value = "rth")]
+    pub filter: SessionFilter,
+
+    /// Write one bar per trading session instead of tagged 1-minute bars
+    #[arg(long)]
+    pub daily: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DecodeArgs {
+    /// Download folder to decode, searched recursively
+    #[arg(long, default_value = "Hist_Fut_Data")]
+    pub data: String,
+
+    /// Files decoded at once [default: one per CPU core]
+    #[arg(long, default_value_t = default_concurrency())]
+    pub jobs: usize,
+
+    /// Output format: ndjson, csv, parquet or arrow (Arrow IPC, also read as Feather)
+    #[arg(long, default_value = "ndjson")]
+    pub format: DecodeFormat,
+
+    /// Write NDJSON with decimal prices and RFC 3339 timestamps to `*_pretty.json`
+    #[arg(long)]
+    pub pretty: bool,
+
+    /// Timezone of CSV and pretty JSON timestamps: utc, america/chicago, america/new_york or an offset like +02:00
+    #[arg(long, default_value = "utc")]
+    pub timezone: DisplayTimezone,
+
+    /// CSV columns to write, in order, e.g. ts_event,price,size [default: every column]
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Leave out the CSV header line
+    #[arg(long)]
+    pub no_header: bool,
+
+    /// CSV field delimiter
+    #[arg(long, default_value_t = ',')]
+    pub delimiter: char,
+
+    /// Parquet column compression: none, snappy, gzip, lz4 or zstd
+    #[arg(long, default_value = "zstd")]
+    pub compression: ParquetCompression,
+
+    /// Most records per Parquet row group
+    #[arg(long, default_value_t = 1_000_000)]
+    pub row_group_size: usize,
+
+    /// Write Parquet as DATA/parquet/SCHEMA/root=ROOT/contract=CONTRACT/date=YYYY-MM-DD/part-START_END.parquet
+    /// instead of one file next to each raw file
+    #[arg(long)]
+    pub partition: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+    /// A contract file, a symbol folder, or the download folder holding one folder per symbol
+    #[arg(long, default_value = "Hist_Fut_Data")]
+    pub input: String,
+
+    /// Trading minutes without a bar before a hole inside a session is reported as a gap
+    #[arg(long, default_value_t = 30)]
+    pub min_gap: i64,
+
+    /// Close-to-close move between consecutive bars reported as a jump, as a fraction (0.03 = 3%)
+    #[arg(long, default_value_t = 0.03)]
+    pub jump: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct CatalogArgs {
+    /// Download folder holding `catalog.json`
+    #[arg(long, default_value = "Hist_Fut_Data")]
+    pub data: String,
+
+    /// Add uncatalogued contract files and drop entries whose file is gone
+    #[arg(long)]
+    pub index: bool,
+
+    /// Product root to list, e.g. ES
+    #[arg(long)]
+    pub root: Option<String>,
+
+    /// Databento schema, e.g. ohlcv-1m
+    #[arg(long)]
+    pub schema: Option<Schema>,
+
+    /// Only files whose window overlaps this date or later (YYYY-MM-DD)
+    #[arg(long)]
+    pub start: Option<String>,
+
+    /// Only files whose window overlaps this date or earlier (YYYY-MM-DD)
+    #[arg(long)]
+    pub end: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct SpendArgs {
+    /// Download folder holding `spend_ledger.jsonl`
+    #[arg(long, default_value = "Hist_Fut_Data")]
+    pub data: String,
+
+    /// Groupings to total by: month, root, schema
+    #[arg(long, value_delimiter = ',', default_value = "month,root,schema")]
+    pub by: Vec<SpendGrouping>,
+}
+
+impl CatalogArgs {
+    pub fn query(&self) -> Result<CatalogQuery> {
+        Ok(CatalogQuery {
+            root: self.root.clone(),
+            schema: self.schema,
+            start: self.start.as_deref().map(parse_date).transpose().context("Invalid --start date")?,
+            end: self.end.as_deref().map(parse_date).transpose().context("Invalid --end date")?,
+            ..CatalogQuery::default()
+        })
+    }
+}
+
+impl DecodeArgs {
+    pub fn options(&self) -> DecodeOptions {
+        DecodeOptions {
+            format: self.format,
+            pretty: self.pretty,
+            timezone: self.timezone,
+            csv: CsvOptions { columns: self.columns.clone(), header: !self.no_header, delimiter: self.delimiter },
+            parquet: ParquetOptions {
+                compression: self.compression,
+                row_group_size: self.row_group_size,
+                partition_dir: self.partition.then(|| Path::new(&self.data).join("parquet")),
+            },
+        }
+    }
+}
+
+impl ValidateArgs {
+    pub fn options(&self) -> ValidationOptions {
+        ValidationOptions { min_gap_minutes: self.min_gap, jump_threshold: self.jump }
+    }
+
+    /// Contract files under `--input`, including one level of symbol folders.
+    pub fn contract_files(&self) -> Result<Vec<ContractFile>> {
+        let mut files = input_contract_files(&self.input)?;
+        let input = Path::new(&self.input);
+        if input.is_dir() {
+            // Symbol folders only; schema folders such as `ES/trades` hold other record types than bars
+            let mut folders = std::fs::read_dir(input)?
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| path.is_dir())
+                .filter(|path| path.file_name().and_then(|name| name.to_str()).is_none_or(|name| name.parse::<Schema>().is_err()))
+                .collect::<Vec<_>>();
+            folders.sort();
+            for folder in folders {
+                files.extend(unique_contract_files(folder)?);
+            }
+        }
+        Ok(files)
+    }
+}
+
+impl ResampleArgs {
+    pub fn contract_files(&self) -> Result<Vec<ContractFile>> {
+        input_contract_files(&self.input)
+    }
+}
+
+impl SessionsArgs {
+    pub fn contract_files(&self) -> Result<Vec<ContractFile>> {
+        input_contract_files(&self.input)
+    }
+}
+
+/// Contract files named by an `--input` path, preferring raw `.dbn.zst` over decoded JSON of the same window.
+fn input_contract_files(input: &str) -> Result<Vec<ContractFile>> {
+    let path = Path::new(input);
+    if !path.is_dir() {
+        let file = ContractFile::from_path(path)
+            .with_context(|| format!("'{input}' is not a downloaded contract file"))?;
+        return Ok(vec![file]);
+    }
+
+    unique_contract_files(path)
+}
+
+impl ContinuousArgs {
+    pub fn options(&self) -> Result<ContinuousOptions> {
+        let roll_calendar = self.roll_calendar.as_deref().map(RollCalendar::load).transpose()?;
+        let roll_overrides = self
+            .rolls
+            .iter()
+            .map(|value| parse_roll_override(value))
+            .collect::<Result<HashMap<_, _>>>()?;
+        Ok(ContinuousOptions { adjustment: self.adjustment, roll_calendar, roll_overrides })
+    }
+
+    pub fn symbol_dir(&self) -> String {
+        format!("{}/{}", self.data, self.symbol)
+    }
+
+    pub fn output_path(&self) -> String {
+        self.output.clone().unwrap_or_else(|| {
+            format!("{}/{}_continuous_{}.json", self.symbol_dir(), self.symbol, self.adjustment)
+        })
+    }
+}
+
+impl DownloadCommandArgs {
+    /// `--budget`, falling back to `DATABENTO_BUDGET_USD`.
+    pub fn budget(&self) -> Result<Option<f64>> {
+        match self.budget {
+            Some(budget) => Ok(Some(budget)),
+            None => budget_from_env(),
+        }
+    }
+}
+
+impl DetectRollsArgs {
+    /// `--budget`, falling back to `DATABENTO_BUDGET_USD`.
+    pub fn budget(&self) -> Result<Option<f64>> {
+        match self.budget {
+            Some(budget) => Ok(Some(budget)),
+            None => budget_from_env(),
+        }
+    }
+}
+
+impl DownloadArgs {
+    pub fn dates(&self) -> Result<(Date, Date)> {
+        let start = parse_date(&self.start).context("Invalid --start date")?;
+        let end = parse_date(&self.end).context("Invalid --end date")?;
+        anyhow::ensure!(start <= end, "--start must not be after --end");
+        Ok((start, end))
+    }
+
+    pub fn period_options(&self) -> Result<PeriodOptions> {
+        let roll_overrides = self
+            .rolls
+            .iter()
+            .map(|value| parse_roll_override(value))
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let roll_calendar = self.roll_calendar.as_deref().map(RollCalendar::load).transpose()?;
+
+        Ok(PeriodOptions { include_siblings: self.include_micros, roll_overrides, roll_calendar })
+    }
+}
+
+impl QuoteArgs {
+    pub fn into_request(self) -> Result<QuoteRequest> {
+        let start = parse_date(&self.start).context("Invalid --start date")?;
+        let end = parse_date(&self.end).context("Invalid --end date")?;
+        anyhow::ensure!(start <= end, "--start must not be after --end");
+
+        let dataset = match self.dataset {
+            Some(dataset) => dataset,
+            None => product_registry()?
+                .find_by_contract(&self.symbol)
+                .map(|product| product.dataset.clone())
+                .with_context(|| format!("Unknown product for '{}', pass --dataset", self.symbol))?,
+        };
+
+        Ok(QuoteRequest::new(dataset, self.symbol, self.schema, start, end))
+    }
+}
+
+/// Parses `ROOT=WINDOW[@ANCHOR]` on top of the product's registry policy.
+fn parse_roll_override(value: &str) -> Result<(String, RollPolicy)> {
+    let (root, policy) = value
+        .split_once('=')
+        .with_context(|| format!("Expected ROOT=POLICY for --roll, got '{value}'"))?;
+    let root = root.trim().to_ascii_uppercase();
+    let product = product_registry()?
+        .get(&root)
+        .with_context(|| format!("Unknown product '{root}' in --roll"))?;
+    let policy = product
+        .roll
+        .with_override(policy)
+        .with_context(|| format!("Invalid --roll policy for {root}"))?;
+    Ok((root, policy))
+}
+
+fn parse_date(value: &str) -> Result<Date> {
+    Date::parse(value, format_description!("[year]-[month]-[day]"))
+        .with_context(|| format!("Expected YYYY-MM-DD, got '{value}'"))
+}
@@ -24,3 +24,9 @@ impl DBClient {
         &mut self.client
     }
 }
+
+impl Default for DBClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
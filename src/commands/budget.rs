@@ -0,0 +1,262 @@
+use anyhow::{Context, Result};
+use databento::dbn::Schema;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use time::{Date, OffsetDateTime};
+
+use crate::commands::get_quote::{ContractCost, HistoryQuoteEstimate};
+
+/// Environment variable (or `.env` entry) holding the default spending cap in USD.
+pub const BUDGET_ENV: &str = "DATABENTO_BUDGET_USD";
+/// JSON lines file in the download folder recording every estimate a download was started under.
+pub const APPROVALS_FILE: &str = "approved_estimates.jsonl";
+
+/// Why a download was not approved.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetError {
+    /// The estimate is above the cap.
+    OverBudget { estimate_usd: f64, budget_usd: f64 },
+    /// Some contracts could not be quoted, so the estimate is incomplete.
+    UnquotedContracts { contracts: Vec<String> },
+}
+
+impl fmt::Display for BudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetError::OverBudget { estimate_usd, budget_usd } => {
+                write!(f, "Estimated cost ${estimate_usd:.4} exceeds the budget of ${budget_usd:.4}")
+            }
+            BudgetError::UnquotedContracts { contracts } => {
+                write!(f, "Could not quote {}; the cost is unknown", contracts.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for BudgetError {}
+
+/// Spending cap from `DATABENTO_BUDGET_USD`, if set.
+pub fn budget_from_env() -> Result<Option<f64>> {
+    match std::env::var(BUDGET_ENV) {
+        Ok(value) => {
+            let budget = value.trim().parse::<f64>().with_context(|| format!("Invalid {BUDGET_ENV}: {value}"))?;
+            Ok(Some(budget))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Approves a download whose every contract was quoted and whose total is within `budget_usd`
+/// (no cap when `None`).
+pub fn check_budget(estimate: &HistoryQuoteEstimate, budget_usd: Option<f64>) -> Result<(), BudgetError> {
+    if !estimate.failed_contracts.is_empty() {
+        let contracts = estimate.failed_contracts.iter().map(|failure| failure.contract_symbol.clone()).collect();
+        return Err(BudgetError::UnquotedContracts { contracts });
+    }
+    match budget_usd {
+        Some(budget_usd) if estimate.total_cost_usd > budget_usd => {
+            Err(BudgetError::OverBudget { estimate_usd: estimate.total_cost_usd, budget_usd })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// An estimate a download was started under. `download_history` only fetches contract windows
+/// an approval covers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovedEstimate {
+    #[serde(with = "time::serde::rfc3339")]
+    pub approved_at: OffsetDateTime,
+    pub start: Date,
+    pub end: Date,
+    pub symbols: Vec<String>,
+    /// Databento schema name the estimate was quoted for, e.g. `ohlcv-1m`.
+    #[serde(default)]
+    pub schema: String,
+    pub total_cost_usd: f64,
+    pub budget_usd: Option<f64>,
+    /// Started although the budget check failed, after the user confirmed.
+    pub overridden: bool,
+    pub contracts: Vec<ContractCost>,
+    /// Contracts that could not be quoted and were confirmed anyway.
+    #[serde(default)]
+    pub unquoted: Vec<String>,
+}
+
+impl ApprovedEstimate {
+    /// Approves `estimate` if it passes `check_budget`.
+    pub fn approve(
+        start: Date,
+        end: Date,
+        symbols: &[&str],
+        schema: Schema,
+        estimate: &HistoryQuoteEstimate,
+        budget_usd: Option<f64>,
+    ) -> Result<Self, BudgetError> {
+        check_budget(estimate, budget_usd)?;
+        Ok(Self::new(start, end, symbols, schema, estimate, budget_usd, false))
+    }
+
+    /// Approves `estimate` although it failed the budget check, after the user confirmed.
+    pub fn overridden(
+        start: Date,
+        end: Date,
+        symbols: &[&str],
+        schema: Schema,
+        estimate: &HistoryQuoteEstimate,
+        budget_usd: Option<f64>,
+    ) -> Self {
+        Self::new(start, end, symbols, schema, estimate, budget_usd, true)
+    }
+
+    fn new(
+        start: Date,
+        end: Date,
+        symbols: &[&str],
+        schema: Schema,
+        estimate: &HistoryQuoteEstimate,
+        budget_usd: Option<f64>,
+        overridden: bool,
+    ) -> Self {
+        Self {
+            approved_at: OffsetDateTime::now_utc(),
+            start,
+            end,
+            symbols: symbols.iter().map(|symbol| symbol.to_string()).collect(),
+            schema: schema.as_str().to_string(),
+            total_cost_usd: estimate.total_cost_usd,
+            budget_usd,
+            overridden,
+            contracts: estimate.contract_costs.clone(),
+            unquoted: if overridden {
+                estimate.failed_contracts.iter().map(|failure| failure.contract_symbol.clone()).collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Whether `symbol` (raw, e.g. `ESH4`) was quoted in `schema` over a window containing `start..=end`,
+    /// or confirmed without a quote.
+    pub fn covers(&self, symbol: &str, schema: Schema, start: Date, end: Date) -> bool {
+        let quoted = |contract: &ContractCost| {
+            contract.contract_symbol == symbol && contract.start <= start && contract.end >= end
+        };
+        self.schema == schema.as_str()
+            && (self.contracts.iter().any(quoted) || self.unquoted.iter().any(|unquoted| unquoted == symbol))
+    }
+
+    /// Appends the approval to `approved_estimates.jsonl` in `base_path`.
+    pub fn record(&self, base_path: impl AsRef<Path>) -> Result<PathBuf> {
+        let base_path = base_path.as_ref();
+        fs::create_dir_all(base_path)?;
+        let path = base_path.join(APPROVALS_FILE);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(self)?).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// Every approval recorded in `base_path`, oldest first.
+pub fn load_approved_estimates(base_path: impl AsRef<Path>) -> Result<Vec<ApprovedEstimate>> {
+    let path = base_path.as_ref().join(APPROVALS_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    fs::read_to_string(&path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).with_context(|| format!("Invalid approval in {}", path.display())))
+        .collect()
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::get_quote::FailedContractEstimate;
+    use time::macros::date;
+
+    fn estimate(costs: &[(&str, f64)], failed: &[&str]) -> HistoryQuoteEstimate {
+        let contract_costs = costs
+            .iter()
+            .map(|&(symbol, cost_usd)| ContractCost {
+                contract_symbol: symbol.to_string(),
+                start: date!(2024 - 01 - 05),
+                end: date!(2024 - 03 - 15),
+                cost_usd,
+            })
+            .collect::<Vec<_>>();
+        HistoryQuoteEstimate {
+            total_cost_usd: costs.iter().map(|&(_, cost)| cost).fold(0.0, |total, cost| total + cost),
+            successful_count: costs.len(),
+            total_count: costs.len() + failed.len(),
+            contract_costs,
+            failed_contracts: failed
+                .iter()
+                .map(|symbol| FailedContractEstimate {
+                    contract_symbol: symbol.to_string(),
+                    start: date!(2024 - 03 - 05),
+                    end: date!(2024 - 06 - 14),
+                    api_request: String::new(),
+                    error_message: "timeout".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_check_budget() {
+        let quoted = estimate(&[("ESH4", 4.0), ("ESM4", 3.5)], &[]);
+        assert_eq!(check_budget(&quoted, None), Ok(()));
+        assert_eq!(check_budget(&quoted, Some(10.0)), Ok(()));
+        assert_eq!(
+            check_budget(&quoted, Some(5.0)),
+            Err(BudgetError::OverBudget { estimate_usd: 7.5, budget_usd: 5.0 })
+        );
+
+        let partial = estimate(&[("ESH4", 4.0)], &["ESM4"]);
+        let error = check_budget(&partial, None).unwrap_err();
+        assert_eq!(error, BudgetError::UnquotedContracts { contracts: vec!["ESM4".to_string()] });
+        assert_eq!(error.to_string(), "Could not quote ESM4; the cost is unknown");
+    }
+
+    #[test]
+    fn test_record_approved_estimates() {
+        let dir = "test_output_approvals";
+        let _ = fs::remove_dir_all(dir);
+
+        let quoted = estimate(&[("ESH4", 4.0)], &[]);
+        let (start, end) = (date!(2024 - 01 - 01), date!(2024 - 03 - 31));
+        let approved = ApprovedEstimate::approve(start, end, &["ES"], Schema::Ohlcv1M, &quoted, Some(5.0)).unwrap();
+        approved.record(dir).unwrap();
+        assert!(ApprovedEstimate::approve(start, end, &["ES"], Schema::Ohlcv1M, &quoted, Some(3.0)).is_err());
+        ApprovedEstimate::overridden(start, end, &["ES"], Schema::Ohlcv1M, &quoted, Some(3.0)).record(dir).unwrap();
+
+        let approvals = load_approved_estimates(dir).unwrap();
+        assert_eq!(approvals.len(), 2);
+        assert_eq!(approvals[0].contracts, quoted.contract_costs);
+        assert!(approvals[1].overridden);
+
+        assert!(approved.covers("ESH4", Schema::Ohlcv1M, date!(2024 - 01 - 10), date!(2024 - 03 - 15)));
+        assert!(!approved.covers("ESH4", Schema::Ohlcv1M, date!(2024 - 01 - 01), date!(2024 - 03 - 15)));
+        assert!(!approved.covers("ESH4", Schema::Trades, date!(2024 - 01 - 10), date!(2024 - 03 - 15)));
+        assert!(!approved.covers("ESM4", Schema::Ohlcv1M, date!(2024 - 01 - 10), date!(2024 - 03 - 15)));
+
+        let partial = estimate(&[("ESH4", 4.0)], &["ESM4"]);
+        let confirmed = ApprovedEstimate::overridden(start, end, &["ES"], Schema::Ohlcv1M, &partial, None);
+        assert!(confirmed.covers("ESM4", Schema::Ohlcv1M, date!(2024 - 03 - 05), date!(2024 - 06 - 14)));
+        assert!(load_approved_estimates("test_output_missing_approvals").unwrap().is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
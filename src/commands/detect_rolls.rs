@@ -0,0 +1,61 @@
+use anyhow::Result;
+use databento::dbn::Schema;
+use time::Date;
+
+use crate::downloader::contracts::{generate_product_periods, PeriodOptions};
+use crate::downloader::roll_calendar::{
+    build_roll_calendar, load_open_interest_activity, load_volume_activity, RollCalendar, RollSignal,
+};
+
+/// Schema the open interest signal is read from. It is downloaded like any other schema, so the statistics
+/// windows are quoted, approved against the budget and written to the spend ledger before detection.
+pub const OPEN_INTEREST_SCHEMA: Schema = Schema::Statistics;
+
+/// Builds a roll calendar for the contract periods `download_history` would fetch, from files already
+/// under `base_path`: `Volume` reads the OHLCV files, `OpenInterest` the `statistics` files downloaded in
+/// `OPEN_INTEREST_SCHEMA`. Nothing is requested from Databento.
+pub async fn detect_roll_calendar(
+    start_date: Date,
+    end_date: Date,
+    symbols: &[&str],
+    base_path: &str,
+    options: &PeriodOptions,
+    signal: RollSignal,
+    confirm_days: usize,
+) -> Result<RollCalendar> {
+    // Detect against the scheduled windows, not a previously detected calendar
+    let options = PeriodOptions { roll_calendar: None, ..options.clone() };
+    let periods = generate_product_periods(symbols, start_date, end_date, &options)?;
+
+    let activity = match signal {
+        RollSignal::Volume => load_volume_activity(base_path, &periods).await?,
+        RollSignal::OpenInterest => load_open_interest_activity(base_path, &periods)?,
+    };
+
+    Ok(build_roll_calendar(&periods, &activity, signal, confirm_days))
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    #[tokio::test]
+    async fn test_volume_calendar_without_files_keeps_schedule() {
+        let calendar = detect_roll_calendar(
+            date!(2024 - 01 - 01),
+            date!(2024 - 12 - 31),
+            &["ES"],
+            "test_output_detect_rolls_missing",
+            &PeriodOptions::default(),
+            RollSignal::Volume,
+            1,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(calendar.entries.len(), 3);
+        assert!(calendar.entries.iter().all(|entry| !entry.detected && entry.roll_date == entry.scheduled));
+    }
+}
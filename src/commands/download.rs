@@ -1,83 +1,466 @@
+use std::collections::BTreeSet;
+use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::sync::Arc;
-use anyhow::Result;
-use tokio::sync::Semaphore;
-use time::Date;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use anyhow::{bail, Context, Result};
+use databento::dbn::Schema;
+use tokio::sync::{Mutex, Semaphore};
+use tokio_util::sync::CancellationToken;
+use time::{Date, OffsetDateTime};
 use crate::client::DBClient;
-use crate::downloader::contracts::generate_contract_periods;
-use crate::downloader::fetch::download_data;
+use crate::commands::budget::ApprovedEstimate;
+use crate::commands::get_quote::{estimate_billable_size, estimate_quote_cost, QuoteRequest};
+use crate::downloader::products::product_dataset;
+use crate::downloader::contracts::{generate_product_periods, parse_full_contract_symbol, PeriodOptions};
+use crate::downloader::fetch::{download_path, download_range, FetchedRange};
+use crate::downloader::resume::{
+    merge_downloads, plan_download, remove_contract_partials, remove_partial_downloads, DownloadPlan,
+};
+use crate::downloader::retry::{ErrorClass, FetchError, RetryPolicy};
+use crate::downloader::schemas::schema_dir;
+use crate::ledger::{append_entry, current_user, LedgerEntry};
+use crate::progress::{is_cancelled, Cancelled, NoProgress, ProgressEvent, ProgressSink};
+pub use crate::progress::TaskStatus;
+use crate::storage::{describe_file, Catalog, FileDetails};
 use crate::types::DownloadTask;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractOutcome {
+    pub contract: String,
+    pub start: Date,
+    pub end: Date,
+    pub status: TaskStatus,
+    /// Tries of the slowest request, 0 when nothing was fetched.
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractFailure {
+    pub contract: String,
+    pub start: Date,
+    pub end: Date,
+    pub class: ErrorClass,
+    pub attempts: u32,
+    pub error: String,
+    /// Billed download kept after its records failed validation.
+    pub rejected: Option<PathBuf>,
+}
+
+/// Result of a `download_history` run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DownloadSummary {
+    pub completed: Vec<ContractOutcome>,
+    pub failed: Vec<ContractFailure>,
+    /// Windows stopped or never started because the run was cancelled.
+    pub cancelled: usize,
+}
+
+impl DownloadSummary {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.cancelled == 0
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled > 0
+    }
+
+    /// Windows that succeeded only after transient failures.
+    pub fn retried(&self) -> impl Iterator<Item = &ContractOutcome> {
+        self.completed.iter().filter(|outcome| outcome.attempts > 1)
+    }
+
+    fn count(&self, status: TaskStatus) -> usize {
+        self.completed.iter().filter(|outcome| outcome.status == status).count()
+    }
+}
+
+impl fmt::Display for DownloadSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} downloaded, {} extended, {} already on disk, {} failed",
+            self.count(TaskStatus::Downloaded),
+            self.count(TaskStatus::Extended),
+            self.count(TaskStatus::Skipped),
+            self.failed.len()
+        )?;
+        if self.cancelled > 0 {
+            write!(f, ", {} cancelled", self.cancelled)?;
+        }
+        for outcome in self.retried() {
+            write!(f, "\n  retried: {} {} to {} ({} attempts)", outcome.contract, outcome.start, outcome.end, outcome.attempts)?;
+        }
+        for failure in &self.failed {
+            write!(f, "\n  failed:  {} {} to {}: {}", failure.contract, failure.start, failure.end, failure.error)?;
+            if let Some(rejected) = &failure.rejected {
+                write!(f, "\n           rejected records kept in {}", rejected.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Downloads every contract window of the symbols and dates of `approval` in `schema`. `options` picks the
+/// roll policy per product and whether micro siblings are downloaded over the same windows into their own
+/// directories. One-minute bars go to `{base_path}/{root}`, other schemas to `{base_path}/{root}/{schema}`.
+/// The approval is appended to `approved_estimates.jsonl` in `base_path`, and nothing is fetched unless it
+/// quoted, in `schema`, every range `pending_quote_requests` lists.
+/// Windows already on disk are skipped and partly covered ones only fetch the missing head or tail.
+/// Every downloaded file is recorded in the catalog of `base_path`. Transient failures are retried;
+/// the returned summary lists which windows succeeded, needed retries or failed for good.
 pub async fn download_history(
+    approval: &ApprovedEstimate,
+    schema: Schema,
+    base_path: &str,
+    options: &PeriodOptions,
+) -> Result<DownloadSummary> {
+    let progress = Arc::new(NoProgress);
+    let cancel = CancellationToken::new();
+    download_history_with_progress(approval, schema, base_path, options, progress, cancel).await
+}
+
+/// `download_history`, reporting every contract as it is queued, started, receives bytes, retries,
+/// finishes, fails or is cancelled, along with the running cost of the finished downloads.
+/// Cancelling `cancel` stops queued and running windows, removes their partial files and returns
+/// the summary of what completed before.
+pub async fn download_history_with_progress(
+    approval: &ApprovedEstimate,
+    schema: Schema,
+    base_path: &str,
+    options: &PeriodOptions,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
+) -> Result<DownloadSummary> {
+    let symbols = approval.symbols.iter().map(String::as_str).collect::<Vec<_>>();
+    let tasks = generate_tasks(approval.start, approval.end, &symbols, schema, base_path, options)?;
+    let mut unapproved = Vec::new();
+    for task in &tasks {
+        for (start, end) in missing_ranges(&task.base_path, &task.contract, task.start, task.end)? {
+            if !approval.covers(&task.symbol, schema, start, end) {
+                unapproved.push(format!("{} {start} to {end}", task.contract));
+            }
+        }
+    }
+    if !unapproved.is_empty() {
+        bail!("The approved estimate does not cover {} in {schema}", unapproved.join(", "));
+    }
+
+    approval.record(base_path)?;
+    run_download_tasks(tasks, base_path, progress, cancel).await
+}
+
+/// What a download of `symbols` over `start_date..=end_date` into `base_path` would fetch, as one quote
+/// request per range: whole contract windows, or the head or tail a file on disk does not cover yet.
+/// Windows already on disk are left out, so approving a quote of these only pays for new data.
+pub fn pending_quote_requests(
     start_date: Date,
     end_date: Date,
     symbols: &[&str],
+    schema: Schema,
     base_path: &str,
-) -> Result<()> {
-    let tasks = generate_tasks(start_date, end_date, symbols, base_path)?;
-    run_download_tasks(tasks).await
+    options: &PeriodOptions,
+) -> Result<Vec<QuoteRequest>> {
+    let mut requests = Vec::new();
+    for period in generate_product_periods(symbols, start_date, end_date, options)? {
+        let dataset = product_dataset(&period.root)?;
+        let dir = schema_dir(base_path, &period.root, schema);
+        for (start, end) in missing_ranges(&dir, &period.contract_name(), period.start, period.end)? {
+            requests.push(QuoteRequest::new(dataset.clone(), period.symbol.clone(), schema, start, end));
+        }
+    }
+    Ok(requests)
+}
+
+/// Ranges of `start..=end` of `contract` that `download_task` would fetch into `dir`.
+fn missing_ranges(dir: impl AsRef<Path>, contract: &str, start: Date, end: Date) -> Result<Vec<(Date, Date)>> {
+    Ok(match plan_download(dir, contract, start, end)? {
+        DownloadPlan::Covered(_) => Vec::new(),
+        DownloadPlan::Full => vec![(start, end)],
+        DownloadPlan::Extend { missing, .. } => missing,
+    })
 }
 
 fn generate_tasks(
     start_date: Date,
     end_date: Date,
     symbols: &[&str],
+    schema: Schema,
     base_path: &str,
+    options: &PeriodOptions,
 ) -> Result<Vec<DownloadTask>> {
+    let periods = generate_product_periods(symbols, start_date, end_date, options)?;
     let mut tasks = Vec::new();
 
-    for &base_symbol in symbols {
-        let periods = generate_contract_periods(base_symbol, start_date, end_date);
-        let symbol_dir = format!("{}/{}", base_path, base_symbol);
+    for period in periods {
+        let dataset = product_dataset(&period.root)?;
+        let symbol_dir = schema_dir(base_path, &period.root, schema);
 
         if !Path::new(&symbol_dir).exists() {
             fs::create_dir_all(&symbol_dir)?;
         }
 
-        for (contract_symbol, start, end) in periods {
-            let task = DownloadTask {
-                client: DBClient::new(),
-                symbol: contract_symbol,
-                base_path: symbol_dir.clone(),
-                start,
-                end,
-            };
+        let task = DownloadTask {
+            client: DBClient::new(),
+            dataset,
+            contract: period.contract_name(),
+            symbol: period.symbol,
+            schema,
+            base_path: symbol_dir,
+            start: period.start,
+            end: period.end,
+        };
 
-            tasks.push(task);
-        }
+        tasks.push(task);
     }
 
     Ok(tasks)
 }
 
-async fn run_download_tasks(tasks: Vec<DownloadTask>) -> Result<()> {
+async fn run_download_tasks(
+    tasks: Vec<DownloadTask>,
+    base_path: &str,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
+) -> Result<DownloadSummary> {
     let semaphore = Arc::new(Semaphore::new(10));
+    let catalog = Arc::new(Mutex::new(Catalog::open(base_path)?));
+    let policy = RetryPolicy::default();
+    let total_cost = Arc::new(StdMutex::new(0.0));
+
+    // Partial files of an interrupted run are never complete; their windows are fetched again
+    for dir in tasks.iter().map(|task| task.base_path.clone()).collect::<BTreeSet<_>>() {
+        remove_partial_downloads(dir)?;
+    }
 
     let handles = tasks
         .into_iter()
         .map(|task| {
             let semaphore = Arc::clone(&semaphore);
-            tokio::spawn(async move {
-                let _permit = semaphore.acquire().await;
-                download_data(task).await
-            })
+            let catalog = Arc::clone(&catalog);
+            let progress = Arc::clone(&progress);
+            let total_cost = Arc::clone(&total_cost);
+            let cancel = cancel.clone();
+            let window = (task.contract.clone(), task.start, task.end);
+            progress.emit(ProgressEvent::Queued { task: task.contract.clone() });
+            let handle = tokio::spawn(async move {
+                let contract = task.contract.clone();
+                let dir = task.base_path.clone();
+                let _permit = tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => {
+                        progress.emit(ProgressEvent::Cancelled { task: contract });
+                        return Err(Cancelled.into());
+                    }
+                    permit = semaphore.acquire() => permit,
+                };
+                progress.emit(ProgressEvent::Started { task: contract.clone() });
+                let result = tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => {
+                        // Dropping the download leaves its `.part` files half written. Files that cannot be
+                        // removed are reported as a failure so the leftovers do not go unnoticed.
+                        match remove_contract_partials(&dir, &contract) {
+                            Ok(_) => Err(Cancelled.into()),
+                            Err(e) => Err(e.context("Cancelled, but failed to remove the partial download")),
+                        }
+                    }
+                    result = download_task(task, &catalog, &policy, progress.as_ref()) => result,
+                };
+                match &result {
+                    Ok((status, _, cost_usd)) => {
+                        let status = Some(*status);
+                        progress.emit(ProgressEvent::Finished { task: contract, cost_usd: *cost_usd, status });
+                        if let Some(cost) = cost_usd {
+                            let mut total = total_cost.lock().unwrap();
+                            *total += cost;
+                            progress.emit(ProgressEvent::CostUpdated { total_usd: *total });
+                        }
+                    }
+                    Err(e) if is_cancelled(e) => progress.emit(ProgressEvent::Cancelled { task: contract }),
+                    Err(e) => progress.emit(ProgressEvent::Failed { task: contract, error: format!("{e:#}") }),
+                }
+                result
+            });
+            (window, handle)
         })
         .collect::<Vec<_>>();
 
-    for handle in handles {
-        handle.await??;
+    // Every task runs to completion or cancellation; failures are collected instead of aborting the others
+    let mut summary = DownloadSummary::default();
+    for ((contract, start, end), handle) in handles {
+        match handle.await.map_err(anyhow::Error::from).and_then(|result| result) {
+            Ok((status, attempts, _)) => summary.completed.push(ContractOutcome { contract, start, end, status, attempts }),
+            Err(e) if is_cancelled(&e) => summary.cancelled += 1,
+            Err(e) => {
+                let (class, attempts, rejected) = e.downcast_ref::<FetchError>().map_or(
+                    (ErrorClass::Permanent, 1, None),
+                    |fetch| (fetch.class, fetch.attempts, fetch.rejected.clone()),
+                );
+                let error = format!("{e:#}");
+                summary.failed.push(ContractFailure { contract, start, end, class, attempts, error, rejected });
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Downloads whatever part of the task's window is not on disk yet and records the result in the
+/// catalog, which is saved after every task so an interrupted run keeps its progress. Returns the
+/// status, the tries of the slowest request and the quoted cost of what was fetched.
+async fn download_task(
+    mut task: DownloadTask,
+    catalog: &Mutex<Catalog>,
+    policy: &RetryPolicy,
+    progress: &dyn ProgressSink,
+) -> Result<(TaskStatus, u32, Option<f64>)> {
+    let catalog_base = catalog.lock().await.base().to_path_buf();
+    let plan = plan_download(&task.base_path, &task.contract, task.start, task.end)?;
+
+    let (path, replaced, quotes, attempts) = match plan {
+        DownloadPlan::Covered(file) => {
+            let mut catalog = catalog.lock().await;
+            let relative = file.path.strip_prefix(&catalog_base).unwrap_or(&file.path);
+            if !catalog.entries().iter().any(|entry| entry.path == relative) {
+                catalog.record(&file.path, task_details(&task, None)).await?;
+                catalog.save()?;
+            }
+            return Ok((TaskStatus::Skipped, 0, None));
+        }
+        DownloadPlan::Full => {
+            let (start, end) = (task.start, task.end);
+            let path = download_path(&task, start, end);
+            let (fetched, quote) =
+                fetch_billed_range(&mut task, start, end, &path, &catalog_base, policy, progress).await?;
+            fs::rename(&fetched.path, &path)?;
+            (path, None, vec![quote], fetched.attempts)
+        }
+        DownloadPlan::Extend { existing, missing } => {
+            let path = download_path(&task, task.start.min(existing.start), task.end.max(existing.end));
+            let mut pieces = vec![existing.path.clone()];
+            let mut quotes = Vec::new();
+            let mut attempts = 0;
+            for &(start, end) in &missing {
+                let (fetched, quote) =
+                    fetch_billed_range(&mut task, start, end, &path, &catalog_base, policy, progress).await?;
+                attempts = attempts.max(fetched.attempts);
+                pieces.push(fetched.path);
+                quotes.push(quote);
+            }
+
+            merge_downloads(&pieces, &path)?;
+            for piece in pieces.iter().skip(1) {
+                fs::remove_file(piece)?;
+            }
+            if existing.path != path {
+                fs::remove_file(&existing.path)?;
+            }
+            (path, Some(existing.path), quotes, attempts)
+        }
+    };
+    let status = if replaced.is_some() { TaskStatus::Extended } else { TaskStatus::Downloaded };
+    let cost_usd = quotes.iter().try_fold(0.0, |total, quote| quote.cost_usd.map(|cost| total + cost));
+
+    let mut entry = describe_file(&catalog_base, &path, task_details(&task, cost_usd)).await?;
+    let mut catalog = catalog.lock().await;
+    if let Some(replaced) = replaced {
+        // The merged file carries what was paid for the file it replaces
+        let replaced = catalog.remove(replaced.strip_prefix(&catalog_base).unwrap_or(&replaced));
+        entry.cost_usd = entry.cost_usd.zip(replaced.and_then(|old| old.cost_usd)).map(|(new, old)| new + old);
+    }
+    catalog.upsert(entry);
+    catalog.save()?;
+    Ok((status, attempts, cost_usd))
+}
+
+fn task_details(task: &DownloadTask, cost_usd: Option<f64>) -> FileDetails {
+    FileDetails { dataset: Some(task.dataset.clone()), symbol: task.symbol.clone(), cost_usd }
+}
+
+/// Quotes and downloads `start..=end` of the task's contract, appending a ledger entry for every attempt
+/// Databento delivered data for. `file` is where the data will end up; a failed range is recorded against
+/// its `.rejected` file when one was kept, or against `file` otherwise.
+async fn fetch_billed_range(
+    task: &mut DownloadTask,
+    start: Date,
+    end: Date,
+    file: &Path,
+    base: &Path,
+    policy: &RetryPolicy,
+    progress: &dyn ProgressSink,
+) -> Result<(FetchedRange, RangeQuote)> {
+    let quote = quote_range(task, start, end).await;
+    match download_range(task, start, end, policy, progress).await {
+        Ok(fetched) => {
+            record_spend(base, task, &quote, fetched.billed, file, None)?;
+            Ok((fetched, quote))
+        }
+        Err(e) => {
+            let file = e.rejected.clone().unwrap_or_else(|| file.to_path_buf());
+            record_spend(base, task, &quote, e.billed, &file, Some(format!("{:#}", e.source)))?;
+            Err(e.into())
+        }
     }
+}
 
+/// Appends `billed` ledger entries for `quote`, one per delivered attempt.
+fn record_spend(
+    base: &Path,
+    task: &DownloadTask,
+    quote: &RangeQuote,
+    billed: u32,
+    file: &Path,
+    error: Option<String>,
+) -> Result<()> {
+    let (root, _, _) = parse_full_contract_symbol(&task.contract)
+        .with_context(|| format!("Invalid contract name: {}", task.contract))?;
+    for _ in 0..billed {
+        let entry = LedgerEntry {
+            timestamp: OffsetDateTime::now_utc(),
+            user: current_user(),
+            dataset: task.dataset.clone(),
+            schema: task.schema.to_string(),
+            root: root.to_string(),
+            contract: task.contract.clone(),
+            symbol: task.symbol.clone(),
+            start: quote.start,
+            end: quote.end,
+            estimated_cost_usd: quote.cost_usd,
+            billable_size: quote.billable_size,
+            file: file.strip_prefix(base).unwrap_or(file).to_path_buf(),
+            error: error.clone(),
+        };
+        append_entry(base, &entry)?;
+    }
     Ok(())
 }
 
+/// What Databento quotes for one range of a contract; either part is `None` when its quote failed.
+struct RangeQuote {
+    start: Date,
+    end: Date,
+    cost_usd: Option<f64>,
+    billable_size: Option<u64>,
+}
+
+/// Cost and billable size of `start..=end` of the task's contract.
+async fn quote_range(task: &DownloadTask, start: Date, end: Date) -> RangeQuote {
+    let quote = QuoteRequest::new(task.dataset.clone(), task.symbol.clone(), task.schema, start, end);
+    let (cost, size) = tokio::join!(estimate_quote_cost(&quote), estimate_billable_size(&quote));
+    RangeQuote { start, end, cost_usd: cost.ok(), billable_size: size.ok() }
+}
+
 //-----------------------------------------------------------------------------------------------------------------//
 #[cfg(test)]
 mod tests {
     use super::*;
     use time::macros::date;
     use std::fs;
+    use crate::commands::get_quote::{ContractCost, HistoryQuoteEstimate};
+    use crate::downloader::resume::rejected_path;
+    use crate::ledger::load_ledger;
+    use crate::processor::list_contract_files;
 
     fn cleanup_test_dir(base_path: &str) {
         if Path::new(base_path).exists() {
@@ -85,6 +468,23 @@ mod tests {
         }
     }
 
+    /// Approval of every contract window of `symbols`, quoted at no cost.
+    fn approval(start: Date, end: Date, symbols: &[&str]) -> ApprovedEstimate {
+        let contract_costs = generate_product_periods(symbols, start, end, &PeriodOptions::default())
+            .unwrap()
+            .into_iter()
+            .map(|period| ContractCost { contract_symbol: period.symbol, start: period.start, end: period.end, cost_usd: 0.0 })
+            .collect::<Vec<_>>();
+        let estimate = HistoryQuoteEstimate {
+            total_cost_usd: 0.0,
+            successful_count: contract_costs.len(),
+            total_count: contract_costs.len(),
+            contract_costs,
+            failed_contracts: Vec::new(),
+        };
+        ApprovedEstimate::approve(start, end, symbols, Schema::Ohlcv1M, &estimate, None).unwrap()
+    }
+
     #[tokio::test]
     async fn test_generate_tasks_creates_expected_structure() {
         let base_path = "test_output_generate";
@@ -92,7 +492,7 @@ mod tests {
 
         let start = date!(2023 - 01 - 01);
         let end = date!(2023 - 12 - 31);
-        let tasks = generate_tasks(start, end, &["CL", "NG"], base_path).expect("Should create tasks");
+        let tasks = generate_tasks(start, end, &["CL", "NG"], Schema::Ohlcv1M, base_path, &PeriodOptions::default()).expect("Should create tasks");
 
         assert!(!tasks.is_empty());
 
@@ -105,6 +505,11 @@ mod tests {
             );
         }
 
+        // Other schemas get their own folder under the product
+        let trades = generate_tasks(start, end, &["CL"], Schema::Trades, base_path, &PeriodOptions::default()).unwrap();
+        assert!(trades.iter().all(|task| task.schema == Schema::Trades && task.base_path == format!("{base_path}/CL/trades")));
+        assert!(Path::new(&format!("{base_path}/CL/trades")).exists());
+
         cleanup_test_dir(base_path);
     }
 
@@ -115,7 +520,7 @@ mod tests {
 
         let start = date!(2023 - 01 - 01);
         let end = date!(2023 - 12 - 31);
-        let tasks = generate_tasks(start, end, &["CL"], base_path).expect("Should create tasks");
+        let tasks = generate_tasks(start, end, &["CL"], Schema::Ohlcv1M, base_path, &PeriodOptions::default()).expect("Should create tasks");
 
         for task in &tasks {
             let file = format!("{}/{}_{}_{}.mock", task.base_path, task.symbol, task.start, task.end);
@@ -138,23 +543,193 @@ mod tests {
 
         let start = date!(2023 - 01 - 01);
         let end = date!(2023 - 01 - 15);
-        let result = download_history(start, end, &["NG"], base_path).await;
+        let approved = approval(start, end, &["NG"]);
+        let result = download_history(&approved, Schema::Ohlcv1M, base_path, &PeriodOptions::default()).await;
+
+        // No NG window falls in the first half of January, so nothing is fetched
+        assert_eq!(result.unwrap(), DownloadSummary::default());
+        assert_eq!(crate::commands::budget::load_approved_estimates(base_path).unwrap(), vec![approved]);
+
+        cleanup_test_dir(base_path);
+    }
+
+    #[tokio::test]
+    async fn test_download_refuses_windows_without_approval() {
+        let base_path = "test_output_unapproved";
+        cleanup_test_dir(base_path);
+
+        let (start, end) = (date!(2024 - 01 - 01), date!(2024 - 06 - 30));
+        let mut approved = approval(start, end, &["CL"]);
+        approved.contracts.pop();
+        let error = download_history(&approved, Schema::Ohlcv1M, base_path, &PeriodOptions::default()).await.unwrap_err();
+        assert!(error.to_string().starts_with("The approved estimate does not cover CL"));
+
+        // Quoted for bars, not for trades
+        let approved = approval(start, end, &["CL"]);
+        assert!(download_history(&approved, Schema::Trades, base_path, &PeriodOptions::default()).await.is_err());
+        assert!(crate::commands::budget::load_approved_estimates(base_path).unwrap().is_empty());
+
+        cleanup_test_dir(base_path);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_download_starts_nothing() {
+        let base_path = "test_output_cancelled";
+        cleanup_test_dir(base_path);
+
+        let tracker = Arc::new(StdMutex::new(crate::progress::ProgressTracker::new()));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let summary = download_history_with_progress(
+            &approval(date!(2024 - 01 - 01), date!(2024 - 06 - 30), &["CL"]),
+            Schema::Ohlcv1M,
+            base_path,
+            &PeriodOptions::default(),
+            tracker.clone(),
+            cancel,
+        )
+        .await
+        .unwrap();
 
-        assert!(result.is_ok());
+        assert!(summary.is_cancelled());
+        assert!(summary.completed.is_empty() && summary.failed.is_empty());
+        let tracker = tracker.lock().unwrap();
+        assert_eq!(summary.cancelled, tracker.tasks().len());
+        assert_eq!(tracker.count(crate::progress::TaskState::Cancelled), summary.cancelled);
+        assert!(list_contract_files(format!("{base_path}/CL")).unwrap().is_empty());
 
-        let ng_dir = format!("{}/NG", base_path);
-        assert!(Path::new(&ng_dir).exists(), "Expected directory for NG not found");
+        cleanup_test_dir(base_path);
+    }
+
+    #[tokio::test]
+    async fn test_generate_tasks_includes_micros_in_own_directory() {
+        let base_path = "test_output_micros";
+        cleanup_test_dir(base_path);
+
+        let start = date!(2024 - 01 - 01);
+        let end = date!(2024 - 12 - 31);
+        let micros = PeriodOptions { include_siblings: true, ..Default::default() };
+        let tasks = generate_tasks(start, end, &["ES", "MES"], Schema::Ohlcv1M, base_path, &micros).expect("Should create tasks");
+
+        let es = tasks.iter().filter(|task| task.base_path.ends_with("/ES")).collect::<Vec<_>>();
+        let mes = tasks.iter().filter(|task| task.base_path.ends_with("/MES")).collect::<Vec<_>>();
+        assert_eq!(es.len(), 4);
+        assert_eq!(mes.len(), es.len(), "MES should not be generated twice");
+        for (micro, parent) in mes.iter().zip(&es) {
+            assert_eq!((micro.start, micro.end), (parent.start, parent.end));
+            assert_eq!(micro.contract, format!("M{}", parent.contract));
+        }
+        assert!(Path::new(&format!("{}/MES", base_path)).exists());
 
         cleanup_test_dir(base_path);
     }
 
     #[tokio::test]
-    async fn test_invalid_symbol_panics() {
+    async fn test_invalid_symbol_returns_error() {
         let base_path = "test_output_invalid";
-        let result = std::panic::catch_unwind(|| {
-            generate_tasks(date!(2023 - 01 - 01), date!(2023 - 12 - 31), &["ZZZ"], base_path).unwrap();
-        });
+        let result = generate_tasks(date!(2023 - 01 - 01), date!(2023 - 12 - 31), &["ZZZ"], Schema::Ohlcv1M, base_path, &PeriodOptions::default());
+
+        let error = result.err().expect("Expected error for unsupported symbol");
+        assert!(error.to_string().contains("Unsupported symbol: ZZZ"));
+        assert!(!Path::new(base_path).exists());
+    }
+
+    #[test]
+    fn test_pending_quote_requests_leave_out_what_is_on_disk() {
+        let base_path = "test_output_pending_quotes";
+        cleanup_test_dir(base_path);
+
+        let (start, end) = (date!(2024 - 01 - 02), date!(2024 - 06 - 30));
+        let periods = generate_product_periods(&["ES"], start, end, &PeriodOptions::default()).unwrap();
+        let (first, second) = (&periods[0], &periods[1]);
+        // The first window is partly downloaded, the second fully
+        fs::create_dir_all(format!("{base_path}/ES")).unwrap();
+        let head_end = first.start + time::Duration::days(10);
+        fs::write(format!("{base_path}/ES/{}_{head_end}_{}.dbn.zst", first.start, first.contract_name()), b"").unwrap();
+        let covered = format!("{base_path}/ES/{}_{}_{}.dbn.zst", second.start, second.end, second.contract_name());
+        fs::write(covered, b"").unwrap();
+
+        let options = PeriodOptions::default();
+        let requests = pending_quote_requests(start, end, &["ES"], Schema::Ohlcv1M, base_path, &options).unwrap();
+        let ranges = requests.iter().map(|request| (request.symbol.as_str(), request.start, request.end));
+        let ranges = ranges.collect::<Vec<_>>();
+        let mut expected = vec![(first.symbol.as_str(), head_end + time::Duration::days(1), first.end)];
+        expected.extend(periods[2..].iter().map(|period| (period.symbol.as_str(), period.start, period.end)));
+        assert_eq!(ranges, expected);
 
-        assert!(result.is_err(), "Expected panic for unsupported symbol");
+        cleanup_test_dir(base_path);
+    }
+
+    #[tokio::test]
+    async fn test_record_spend_writes_one_entry_per_billed_attempt() {
+        let base_path = "test_output_record_spend";
+        cleanup_test_dir(base_path);
+
+        let (start, end) = (date!(2024 - 01 - 02), date!(2024 - 03 - 15));
+        let tasks = generate_tasks(start, end, &["ES"], Schema::Ohlcv1M, base_path, &PeriodOptions::default()).unwrap();
+        let task = &tasks[0];
+        let quote = RangeQuote { start: task.start, end: task.end, cost_usd: Some(1.5), billable_size: Some(1_000) };
+        let path = download_path(task, task.start, task.end);
+
+        // Two stored attempts (one retried after a cut-off transfer) and one rejected
+        record_spend(Path::new(base_path), task, &quote, 2, &path, None).unwrap();
+        let rejected = rejected_path(&path);
+        record_spend(Path::new(base_path), task, &quote, 1, &rejected, Some("records out of order".to_string())).unwrap();
+
+        let ledger = load_ledger(base_path).unwrap();
+        assert_eq!(ledger.len(), 3);
+        assert!(ledger.iter().all(|entry| entry.root == "ES" && entry.schema == "ohlcv-1m"));
+        assert_eq!(ledger[0].file, path.strip_prefix(base_path).unwrap());
+        assert_eq!(ledger[2].file, rejected.strip_prefix(base_path).unwrap());
+        assert_eq!(ledger[2].error.as_deref(), Some("records out of order"));
+
+        cleanup_test_dir(base_path);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_summary_lists_retries_and_failures() {
+        let outcome = |contract: &str, status, attempts| ContractOutcome {
+            contract: contract.to_string(),
+            start: date!(2024 - 01 - 05),
+            end: date!(2024 - 03 - 15),
+            status,
+            attempts,
+        };
+        let summary = DownloadSummary {
+            completed: vec![
+                outcome("ESH2024", TaskStatus::Downloaded, 1),
+                outcome("ESM2024", TaskStatus::Downloaded, 3),
+                outcome("ESU2024", TaskStatus::Skipped, 0),
+            ],
+            failed: vec![
+                ContractFailure {
+                    contract: "ESZ2024".to_string(),
+                    start: date!(2024 - 09 - 05),
+                    end: date!(2024 - 12 - 13),
+                    class: ErrorClass::Permanent,
+                    attempts: 1,
+                    error: "symbol not found".to_string(),
+                    rejected: None,
+                },
+                ContractFailure {
+                    contract: "ESH2025".to_string(),
+                    start: date!(2024 - 12 - 05),
+                    end: date!(2025 - 03 - 21),
+                    class: ErrorClass::Permanent,
+                    attempts: 1,
+                    error: "records out of order".to_string(),
+                    rejected: Some(PathBuf::from("ES/2024-12-05_2025-03-21_ESH2025.dbn.zst.rejected")),
+                },
+            ],
+            cancelled: 0,
+        };
+
+        assert!(!summary.is_success());
+        assert_eq!(summary.retried().count(), 1);
+        let text = summary.to_string();
+        assert!(text.starts_with("2 downloaded, 0 extended, 1 already on disk, 2 failed"));
+        assert!(text.contains("retried: ESM2024 2024-01-05 to 2024-03-15 (3 attempts)"));
+        assert!(text.contains("failed:  ESZ2024 2024-09-05 to 2024-12-13: symbol not found"));
+        assert!(text.contains("rejected records kept in ES/2024-12-05_2025-03-21_ESH2025.dbn.zst.rejected"));
+    }
+}
@@ -1,12 +1,16 @@
 use anyhow::{Context, Result};
 use databento::{dbn::Schema, historical::metadata::GetCostParams};
+use serde::{Deserialize, Serialize};
 use std::{fs::File, io::Write, path::Path, sync::Arc};
 use time::Date;
 use tokio::{sync::Semaphore, task::JoinSet};
+use tokio_util::sync::CancellationToken;
 
 use crate::client::DBClient;
-use crate::downloader::contracts::generate_contract_periods;
+use crate::downloader::contracts::{generate_product_periods, ContractError, PeriodOptions};
+use crate::downloader::products::product_dataset;
 use crate::downloader::range::download_time_range;
+use crate::progress::{Cancelled, NoProgress, ProgressEvent, ProgressSink};
 
 #[derive(Debug, Clone)]
 pub struct QuoteRequest {
@@ -40,9 +44,19 @@ pub struct HistoryQuoteEstimate {
     pub total_cost_usd: f64,
     pub successful_count: usize,
     pub total_count: usize,
+    pub contract_costs: Vec<ContractCost>,
     pub failed_contracts: Vec<FailedContractEstimate>,
 }
 
+/// Quoted cost of one contract window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractCost {
+    pub contract_symbol: String,
+    pub start: Date,
+    pub end: Date,
+    pub cost_usd: f64,
+}
+
 #[derive(Debug, Clone)]
 pub struct FailedContractEstimate {
     pub contract_symbol: String,
@@ -52,13 +66,6 @@ pub struct FailedContractEstimate {
     pub error_message: String,
 }
 
-#[derive(Debug, Clone)]
-struct ContractQuoteRequest {
-    symbol: String,
-    start: Date,
-    end: Date,
-}
-
 const ESTIMATE_CONCURRENCY_LIMIT: usize = 10;
 pub const ERROR_REPORT_PATH: &str = "error_response.txt";
 
@@ -66,50 +73,96 @@ pub const ERROR_REPORT_PATH: &str = "error_response.txt";
 /// This is intentionally reusable so the GUI can call it later.
 pub async fn estimate_quote_cost(request: &QuoteRequest) -> databento::Result<f64> {
     let mut client = DBClient::new();
-    let (start_dt, end_dt) = download_time_range(request.start, request.end);
+    client.get_mut().metadata().get_cost(&query_params(request)).await
+}
 
-    client
-        .get_mut()
-        .metadata()
-        .get_cost(
-            &GetCostParams::builder()
-                .dataset(request.dataset.as_str())
-                .date_time_range((start_dt, end_dt))
-                .symbols(request.symbol.as_str())
-                .schema(request.schema)
-                .build(),
-        )
-        .await
+/// Calls Databento metadata.get_billable_size: the uncompressed size in bytes the request is billed for.
+pub async fn estimate_billable_size(request: &QuoteRequest) -> databento::Result<u64> {
+    let mut client = DBClient::new();
+    client.get_mut().metadata().get_billable_size(&query_params(request)).await
+}
+
+fn query_params(request: &QuoteRequest) -> GetCostParams {
+    let (start_dt, end_dt) = download_time_range(request.start, request.end);
+    GetCostParams::builder()
+        .dataset(request.dataset.as_str())
+        .date_time_range((start_dt, end_dt))
+        .symbols(request.symbol.as_str())
+        .schema(request.schema)
+        .build()
 }
 
 /// Estimate total cost for the same contract-period requests used by `download_history`.
 /// This does not download any data; it only queries Databento metadata pricing.
+/// Each contract is quoted against the dataset of its product in the registry.
 pub async fn estimate_download_history_cost(
     start_date: Date,
     end_date: Date,
     base_symbols: &[&str],
-    dataset: &str,
     schema: Schema,
+    options: &PeriodOptions,
+) -> Result<HistoryQuoteEstimate> {
+    let progress = Arc::new(NoProgress);
+    let cancel = CancellationToken::new();
+    estimate_download_history_cost_with_progress(start_date, end_date, base_symbols, schema, options, progress, cancel)
+        .await
+}
+
+/// `estimate_download_history_cost`, reporting each contract quote as it is queued, started, finished
+/// or failed, along with the running total. Cancelling `cancel` abandons the outstanding quotes and
+/// returns a `Cancelled` error.
+pub async fn estimate_download_history_cost_with_progress(
+    start_date: Date,
+    end_date: Date,
+    base_symbols: &[&str],
+    schema: Schema,
+    options: &PeriodOptions,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
+) -> Result<HistoryQuoteEstimate> {
+    let requests = build_contract_quote_requests(start_date, end_date, base_symbols, schema, options)?;
+    estimate_requests_cost_with_progress(requests, progress, cancel).await
+}
+
+/// Quotes each of `requests` like `estimate_download_history_cost_with_progress` quotes contract windows,
+/// e.g. only the ranges of a download that are not on disk yet.
+pub async fn estimate_requests_cost_with_progress(
+    requests: Vec<QuoteRequest>,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
 ) -> Result<HistoryQuoteEstimate> {
-    let requests = build_contract_quote_requests(start_date, end_date, base_symbols);
     let total_count = requests.len();
     let semaphore = Arc::new(Semaphore::new(ESTIMATE_CONCURRENCY_LIMIT));
     let mut join_set = JoinSet::new();
     let mut total_cost_usd = 0.0;
     let mut successful_count = 0usize;
+    let mut contract_costs = Vec::new();
     let mut failed_contracts = Vec::new();
 
     for request in requests {
         let semaphore = Arc::clone(&semaphore);
-        let dataset = dataset.to_string();
+        let progress = Arc::clone(&progress);
+        let cancel = cancel.clone();
+        progress.emit(ProgressEvent::Queued { task: request.symbol.clone() });
         join_set.spawn(async move {
-            let _permit = semaphore
-                .acquire_owned()
-                .await
-                .context("Failed to acquire estimate semaphore permit")?;
-            let api_request = build_api_request_string(&request, &dataset, schema);
-            let estimate_result = estimate_single_contract_cost(&request, &dataset, schema).await;
-            Ok::<(ContractQuoteRequest, String, databento::Result<f64>), anyhow::Error>((
+            let quote = async {
+                let _permit = semaphore
+                    .acquire_owned()
+                    .await
+                    .context("Failed to acquire estimate semaphore permit")?;
+                progress.emit(ProgressEvent::Started { task: request.symbol.clone() });
+                Ok::<_, anyhow::Error>(estimate_quote_cost(&request).await)
+            };
+            let estimate_result = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    progress.emit(ProgressEvent::Cancelled { task: request.symbol.clone() });
+                    return Err(Cancelled.into());
+                }
+                result = quote => result?,
+            };
+            let api_request = build_api_request_string(&request);
+            Ok::<(QuoteRequest, String, databento::Result<f64>), anyhow::Error>((
                 request,
                 api_request,
                 estimate_result,
@@ -124,8 +177,21 @@ pub async fn estimate_download_history_cost(
             Ok(cost) => {
                 total_cost_usd += cost;
                 successful_count += 1;
+                progress.emit(ProgressEvent::Finished {
+                    task: request.symbol.clone(),
+                    cost_usd: Some(cost),
+                    status: None,
+                });
+                progress.emit(ProgressEvent::CostUpdated { total_usd: total_cost_usd });
+                contract_costs.push(ContractCost {
+                    contract_symbol: request.symbol,
+                    start: request.start,
+                    end: request.end,
+                    cost_usd: cost,
+                });
             }
             Err(error) => {
+                progress.emit(ProgressEvent::Failed { task: request.symbol.clone(), error: error.to_string() });
                 failed_contracts.push(FailedContractEstimate {
                     contract_symbol: request.symbol,
                     start: request.start,
@@ -137,10 +203,14 @@ pub async fn estimate_download_history_cost(
         }
     }
 
+    // Quotes finish in any order
+    contract_costs.sort_by(|a, b| (a.start, &a.contract_symbol).cmp(&(b.start, &b.contract_symbol)));
+
     Ok(HistoryQuoteEstimate {
         total_cost_usd,
         successful_count,
         total_count,
+        contract_costs,
         failed_contracts,
     })
 }
@@ -149,44 +219,23 @@ fn build_contract_quote_requests(
     start_date: Date,
     end_date: Date,
     base_symbols: &[&str],
-) -> Vec<ContractQuoteRequest> {
-    let mut requests = Vec::new();
-
-    for &base_symbol in base_symbols {
-        for (contract_symbol, contract_start, contract_end) in
-            generate_contract_periods(base_symbol, start_date, end_date)
-        {
-            requests.push(ContractQuoteRequest {
-                symbol: contract_symbol,
-                start: contract_start,
-                end: contract_end,
-            });
-        }
-    }
-
-    requests
-}
-
-async fn estimate_single_contract_cost(
-    request: &ContractQuoteRequest,
-    dataset: &str,
     schema: Schema,
-) -> databento::Result<f64> {
-    let quote_request = QuoteRequest::new(
-        dataset.to_string(),
-        request.symbol.clone(),
-        schema,
-        request.start,
-        request.end,
-    );
-    estimate_quote_cost(&quote_request).await
+    options: &PeriodOptions,
+) -> Result<Vec<QuoteRequest>, ContractError> {
+    generate_product_periods(base_symbols, start_date, end_date, options)?
+        .into_iter()
+        .map(|period| {
+            let dataset = product_dataset(&period.root)?;
+            Ok(QuoteRequest::new(dataset, period.symbol, schema, period.start, period.end))
+        })
+        .collect()
 }
 
-fn build_api_request_string(request: &ContractQuoteRequest, dataset: &str, schema: Schema) -> String {
+fn build_api_request_string(request: &QuoteRequest) -> String {
     let (start_dt, end_dt) = download_time_range(request.start, request.end);
     format!(
-        "POST metadata.get_cost dataset={dataset} schema={schema} symbols={} stype_in=raw_symbol start={start_dt} end={end_dt}",
-        request.symbol
+        "POST metadata.get_cost dataset={} schema={} symbols={} stype_in=raw_symbol start={start_dt} end={end_dt}",
+        request.dataset, request.schema, request.symbol
     )
 }
 
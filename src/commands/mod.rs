@@ -1,3 +1,5 @@
 
 pub mod download;
-pub mod get_quote;
\ No newline at end of file
+pub mod get_quote;
+pub mod detect_rolls;
+pub mod budget;
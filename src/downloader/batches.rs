@@ -0,0 +1,213 @@
+use anyhow::{bail, Context, Result};
+use arrow::{array::RecordBatch, datatypes::SchemaRef, error::ArrowError, record_batch::RecordBatchReader};
+use databento::dbn::{
+    decode::{AsyncDbnDecoder, DbnMetadata},
+    Schema,
+};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
+use tokio::runtime::Runtime;
+
+use crate::downloader::columnar::{batch_builder, BatchBuilder, BATCH_ROWS};
+use crate::downloader::decode::{open_dbn, DbnReader};
+use crate::downloader::symbology::InstrumentSymbols;
+use crate::processor::{catalog_contract_files, ContractFile};
+use crate::storage::{Catalog, CatalogQuery, FileEncoding};
+
+/// Record batches of one raw `.dbn.zst` file, converted as the async decoder reads it. Batches have the
+/// columns of the Parquet output, with `contract` first when the file name names one and each record's `symbol`.
+pub struct DbnBatchReader {
+    decoder: AsyncDbnDecoder<DbnReader>,
+    schema: Schema,
+    arrow_schema: SchemaRef,
+    builder: Box<dyn BatchBuilder>,
+    symbols: InstrumentSymbols,
+    contract: Option<String>,
+    batch_rows: usize,
+}
+
+impl DbnBatchReader {
+    /// Opens `path` to be read in batches of at most `batch_rows` records.
+    pub async fn open(path: impl AsRef<Path>, batch_rows: usize) -> Result<Self> {
+        let path = path.as_ref();
+        let (decoder, schema) = open_dbn(&path.to_string_lossy()).await?;
+        let contract = ContractFile::from_path(path).map(|file| file.contract);
+        let symbols = InstrumentSymbols::load(decoder.metadata())
+            .await
+            .with_context(|| format!("Failed to map the instruments of {}", path.display()))?;
+        let mut builder = batch_builder(schema)?;
+        let arrow_schema = builder.take(contract.as_deref())?.schema();
+        Ok(Self { decoder, schema, arrow_schema, builder, symbols, contract, batch_rows: batch_rows.max(1) })
+    }
+
+    /// Schema the file's metadata declares.
+    pub fn schema(&self) -> Schema {
+        self.schema
+    }
+
+    pub fn arrow_schema(&self) -> SchemaRef {
+        self.arrow_schema.clone()
+    }
+
+    /// The next batch, `None` once the file is exhausted.
+    pub async fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        while self.builder.buffered() < self.batch_rows {
+            match self.decoder.decode_record_ref().await? {
+                Some(record) => self.builder.push(record, self.symbols.symbol(&record)?)?,
+                None => break,
+            }
+        }
+        if self.builder.buffered() == 0 {
+            return Ok(None);
+        }
+        self.builder.take(self.contract.as_deref()).map(Some)
+    }
+}
+
+/// Record batches of the raw files of a catalog matching a query, one file after the other in window order.
+pub struct CatalogBatches {
+    current: DbnBatchReader,
+    files: VecDeque<PathBuf>,
+}
+
+/// Batches of every raw file in `catalog` matching `query`. Decoded copies are skipped, and all matching files
+/// must share a schema, so queries spanning several schemas fail once they reach a second one.
+pub async fn catalog_batches(catalog: &Catalog, query: &CatalogQuery) -> Result<CatalogBatches> {
+    let query = CatalogQuery { encoding: Some(FileEncoding::Dbn), ..query.clone() };
+    let mut files = catalog_contract_files(catalog, &query).into_iter().map(|file| file.path).collect::<VecDeque<_>>();
+    let first = files.pop_front().context("No raw files in the catalog match the query")?;
+    let current = DbnBatchReader::open(first, BATCH_ROWS).await?;
+    Ok(CatalogBatches { current, files })
+}
+
+/// `catalog_batches` as a blocking iterator, for code outside a Tokio runtime. The iterator runs its own
+/// runtime, so it must not be used from async code.
+pub fn catalog_batch_iter(catalog: &Catalog, query: &CatalogQuery) -> Result<BlockingBatches> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let batches = runtime.block_on(catalog_batches(catalog, query))?;
+    Ok(BlockingBatches { runtime, batches })
+}
+
+impl CatalogBatches {
+    /// Arrow schema shared by every batch.
+    pub fn arrow_schema(&self) -> SchemaRef {
+        self.current.arrow_schema()
+    }
+
+    /// The next batch, `None` once every file is exhausted.
+    pub async fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        loop {
+            if let Some(batch) = self.current.next_batch().await? {
+                return Ok(Some(batch));
+            }
+            let Some(path) = self.files.pop_front() else {
+                return Ok(None);
+            };
+            let next = DbnBatchReader::open(&path, BATCH_ROWS).await?;
+            if next.schema() != self.current.schema() {
+                bail!(
+                    "{} holds {} records, not {}; narrow the query to one schema",
+                    path.display(),
+                    next.schema(),
+                    self.current.schema()
+                );
+            }
+            self.current = next;
+        }
+    }
+}
+
+/// Batches of a catalog query as an iterator and Arrow `RecordBatchReader`, e.g. for a DataFusion table.
+pub struct BlockingBatches {
+    runtime: Runtime,
+    batches: CatalogBatches,
+}
+
+impl Iterator for BlockingBatches {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.runtime.block_on(self.batches.next_batch());
+        batch.map_err(|e| ArrowError::ExternalError(e.into())).transpose()
+    }
+}
+
+impl RecordBatchReader for BlockingBatches {
+    fn schema(&self) -> SchemaRef {
+        self.batches.arrow_schema()
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, Float64Array, StringArray};
+    use crate::downloader::fixtures::{raw_symbol_mapping, write_dbn_with_mappings};
+    use databento::dbn::{rtype, OhlcvMsg, RecordHeader, TradeMsg};
+    use std::fs;
+    use time::macros::date;
+
+    fn trade(ts_event: u64, price: i64) -> TradeMsg {
+        TradeMsg { hd: RecordHeader::new::<TradeMsg>(rtype::MBP_0, 1, 7, ts_event), price, size: 1, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_catalog_batches() {
+        let dir = "test_output_catalog_batches";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{dir}/ES/trades")).unwrap();
+
+        let march = format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024.dbn.zst");
+        let june = format!("{dir}/ES/trades/2024-03-15_2024-06-14_ESM2024.dbn.zst");
+        // Every record is stamped at the epoch, when instrument 7 stood for each file's contract
+        let mapping = |symbol| vec![raw_symbol_mapping(symbol, 7, date!(1970 - 01 - 01), date!(1970 - 01 - 02))];
+        write_dbn_with_mappings(&june, Schema::Trades, mapping("ESM4"), &[trade(30, 4_900_000_000_000)]);
+        let march_trades = [trade(10, 4_800_000_000_000), trade(20, 4_800_250_000_000)];
+        write_dbn_with_mappings(&march, Schema::Trades, mapping("ESH4"), &march_trades);
+        let bars = format!("{dir}/ES/2024-06-14_2024-09-20_ESU2024.dbn.zst");
+        let bar = OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(rtype::OHLCV_1M, 1, 7, 40),
+            open: 1,
+            high: 1,
+            low: 1,
+            close: 1,
+            volume: 1,
+        };
+        write_dbn_with_mappings(&bars, Schema::Ohlcv1M, mapping("ESU4"), &[bar]);
+
+        let mut catalog = Catalog::open(dir).unwrap();
+        for path in [&march, &june, &bars] {
+            catalog.record(path, Default::default()).await.unwrap();
+        }
+
+        // Files are read in window order and their batches share one schema
+        let query = CatalogQuery { root: Some("ES".to_string()), schema: Some(Schema::Trades), ..Default::default() };
+        let mut batches = catalog_batches(&catalog, &query).await.unwrap();
+        let schema = batches.arrow_schema();
+        let mut contracts = Vec::new();
+        let mut prices = Vec::new();
+        while let Some(batch) = batches.next_batch().await.unwrap() {
+            assert_eq!(batch.schema(), schema);
+            let contract = batch.column_by_name("contract").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+            contracts.extend(contract.iter().flatten().map(String::from));
+            let price = batch.column_by_name("price").unwrap().as_any().downcast_ref::<Float64Array>().unwrap();
+            prices.extend(price.values().iter().copied());
+        }
+        assert_eq!(contracts, ["ESH2024", "ESH2024", "ESM2024"]);
+        assert_eq!(prices, [4800.0, 4800.25, 4900.0]);
+
+        // Without a schema the query also reaches the bars and stops there
+        let query = CatalogQuery { root: Some("ES".to_string()), ..Default::default() };
+        let mut batches = catalog_batches(&catalog, &query).await.unwrap();
+        let mut result = Ok(None);
+        for _ in 0..3 {
+            result = batches.next_batch().await;
+        }
+        assert!(result.unwrap_err().to_string().contains("narrow the query to one schema"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
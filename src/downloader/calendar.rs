@@ -0,0 +1,390 @@
+use std::sync::OnceLock;
+use time::{macros::{date, time}, Date, Duration, Month, OffsetDateTime, UtcOffset, Weekday};
+
+/// How a fixed-date holiday is moved when it falls on a weekend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Observance {
+    /// Saturday is observed on Friday, Sunday on Monday.
+    NearestWeekday,
+    /// Sunday is observed on Monday, a Saturday holiday is not observed at all.
+    /// CME follows this for New Year's Day (Dec 31 stays a trading day).
+    SundayToMonday,
+}
+
+/// Rule that yields the date a holiday falls on in a given year.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HolidayRule {
+    /// Same calendar day every year, shifted off weekends by `observance`.
+    Fixed { month: Month, day: u8, observance: Observance },
+    /// The `n`th (1-based) `weekday` of `month`, e.g. the 3rd Monday of January.
+    NthWeekday { month: Month, weekday: Weekday, n: u8 },
+    /// The last `weekday` of `month`, e.g. the last Monday of May.
+    LastWeekday { month: Month, weekday: Weekday },
+    /// Two days before Western Easter Sunday.
+    GoodFriday,
+}
+
+impl HolidayRule {
+    /// Observed date of the holiday in `year`, or `None` when it is not observed that year.
+    pub fn observed_date(&self, year: i32) -> Option<Date> {
+        match *self {
+            HolidayRule::Fixed { month, day, observance } => {
+                let date = Date::from_calendar_date(year, month, day).ok()?;
+                match (date.weekday(), observance) {
+                    (Weekday::Saturday, Observance::NearestWeekday) => date.previous_day(),
+                    (Weekday::Saturday, Observance::SundayToMonday) => None,
+                    (Weekday::Sunday, _) => date.next_day(),
+                    _ => Some(date),
+                }
+            }
+            HolidayRule::NthWeekday { month, weekday, n } => nth_weekday(year, month, weekday, n),
+            HolidayRule::LastWeekday { month, weekday } => last_weekday(year, month, weekday),
+            HolidayRule::GoodFriday => easter_sunday(year).map(|easter| easter - Duration::days(2)),
+        }
+    }
+}
+
+/// A named recurring holiday, observed from `first_year` onwards when set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holiday {
+    pub name: &'static str,
+    pub rule: HolidayRule,
+    pub first_year: Option<i32>,
+}
+
+impl Holiday {
+    pub const fn new(name: &'static str, rule: HolidayRule) -> Self {
+        Self { name, rule, first_year: None }
+    }
+
+    pub const fn since(mut self, year: i32) -> Self {
+        self.first_year = Some(year);
+        self
+    }
+
+    pub fn observed_date(&self, year: i32) -> Option<Date> {
+        if self.first_year.is_some_and(|first| year < first) {
+            return None;
+        }
+        self.rule.observed_date(year)
+    }
+}
+
+/// Exchange holiday calendar: recurring holiday rules plus one-off closures.
+/// A business day is any weekday that is neither a holiday nor a closure.
+#[derive(Debug, Clone, Default)]
+pub struct HolidayCalendar {
+    holidays: Vec<Holiday>,
+    closures: Vec<Date>,
+}
+
+impl HolidayCalendar {
+    pub fn new(holidays: Vec<Holiday>, closures: Vec<Date>) -> Self {
+        Self { holidays, closures }
+    }
+
+    /// CME Globex holidays on which no settlement is published (full closures and
+    /// holiday-halted sessions alike), which is what CME counts as a non-business day.
+    pub fn cme() -> Self {
+        use HolidayRule::*;
+
+        let holidays = vec![
+            Holiday::new("New Year's Day", Fixed { month: Month::January, day: 1, observance: Observance::SundayToMonday }),
+            Holiday::new("Martin Luther King Jr. Day", NthWeekday { month: Month::January, weekday: Weekday::Monday, n: 3 }),
+            Holiday::new("Presidents Day", NthWeekday { month: Month::February, weekday: Weekday::Monday, n: 3 }),
+            Holiday::new("Good Friday", GoodFriday),
+            Holiday::new("Memorial Day", LastWeekday { month: Month::May, weekday: Weekday::Monday }),
+            Holiday::new("Juneteenth", Fixed { month: Month::June, day: 19, observance: Observance::NearestWeekday }).since(2022),
+            Holiday::new("Independence Day", Fixed { month: Month::July, day: 4, observance: Observance::NearestWeekday }),
+            Holiday::new("Labor Day", NthWeekday { month: Month::September, weekday: Weekday::Monday, n: 1 }),
+            Holiday::new("Thanksgiving Day", NthWeekday { month: Month::November, weekday: Weekday::Thursday, n: 4 }),
+            Holiday::new("Christmas Day", Fixed { month: Month::December, day: 25, observance: Observance::NearestWeekday }),
+        ];
+
+        // NYMEX did not reopen until September 17, 2001.
+        let closures = vec![
+            date!(2001 - 09 - 11),
+            date!(2001 - 09 - 12),
+            date!(2001 - 09 - 13),
+            date!(2001 - 09 - 14),
+        ];
+
+        Self::new(holidays, closures)
+    }
+
+    /// Adds a one-off closure, e.g. a national day of mourning.
+    pub fn with_closure(mut self, date: Date) -> Self {
+        if !self.closures.contains(&date) {
+            self.closures.push(date);
+        }
+        self
+    }
+
+    /// Name of the holiday or closure falling on `date`, if any.
+    pub fn holiday_name(&self, date: Date) -> Option<&'static str> {
+        if self.closures.contains(&date) {
+            return Some("Exchange closure");
+        }
+
+        // Observed days can cross a year boundary (e.g. Dec 31 for a Saturday New Year).
+        self.holidays
+            .iter()
+            .find(|holiday| {
+                (date.year() - 1..=date.year() + 1)
+                    .any(|year| holiday.observed_date(year) == Some(date))
+            })
+            .map(|holiday| holiday.name)
+    }
+
+    pub fn is_holiday(&self, date: Date) -> bool {
+        self.holiday_name(date).is_some()
+    }
+
+    pub fn is_business_day(&self, date: Date) -> bool {
+        !is_weekend(date) && !self.is_holiday(date)
+    }
+
+    /// Observed date of the holiday called `name` in `year`.
+    pub fn holiday_date(&self, name: &str, year: i32) -> Option<Date> {
+        self.holidays.iter().find(|holiday| holiday.name == name)?.observed_date(year)
+    }
+
+    /// All holidays and closures observed in `year`, sorted by date.
+    pub fn holidays_in_year(&self, year: i32) -> Vec<(Date, &'static str)> {
+        let mut days = self
+            .holidays
+            .iter()
+            .filter_map(|holiday| holiday.observed_date(year).map(|date| (date, holiday.name)))
+            .filter(|(date, _)| date.year() == year)
+            .chain(
+                self.closures
+                    .iter()
+                    .filter(|date| date.year() == year)
+                    .map(|&date| (date, "Exchange closure")),
+            )
+            .collect::<Vec<_>>();
+        days.sort();
+        days
+    }
+
+    /// First business day strictly after `date`.
+    pub fn next_business_day(&self, date: Date) -> Date {
+        let mut day = date.next_day().expect("Date out of range");
+        while !self.is_business_day(day) {
+            day = day.next_day().expect("Date out of range");
+        }
+        day
+    }
+
+    /// Last business day strictly before `date`.
+    pub fn previous_business_day(&self, date: Date) -> Date {
+        let mut day = date.previous_day().expect("Date out of range");
+        while !self.is_business_day(day) {
+            day = day.previous_day().expect("Date out of range");
+        }
+        day
+    }
+
+    /// `date` itself when it is a business day, otherwise the business day before it.
+    pub fn business_day_on_or_before(&self, date: Date) -> Date {
+        if self.is_business_day(date) {
+            date
+        } else {
+            self.previous_business_day(date)
+        }
+    }
+
+    /// Steps back `count` business days from `date` (exclusive).
+    pub fn business_days_before(&self, date: Date, count: u32) -> Date {
+        (0..count).fold(date, |day, _| self.previous_business_day(day))
+    }
+
+    /// Steps forward `count` business days from `date` (exclusive).
+    pub fn business_days_after(&self, date: Date, count: u32) -> Date {
+        (0..count).fold(date, |day, _| self.next_business_day(day))
+    }
+
+    /// The `n`th-from-last (1-based) business day of a month.
+    pub fn nth_last_business_day(&self, year: i32, month: Month, n: u32) -> Date {
+        let first_of_next = first_of_next_month(year, month);
+        self.business_days_before(first_of_next, n)
+    }
+
+    /// Number of business days in the inclusive range `start..=end`.
+    pub fn business_days_between(&self, start: Date, end: Date) -> usize {
+        let mut count = 0;
+        let mut day = start;
+        while day <= end {
+            if self.is_business_day(day) {
+                count += 1;
+            }
+            day = day.next_day().expect("Date out of range");
+        }
+        count
+    }
+}
+
+/// Shared CME calendar used by the expiry rules.
+pub fn cme_calendar() -> &'static HolidayCalendar {
+    static CALENDAR: OnceLock<HolidayCalendar> = OnceLock::new();
+    CALENDAR.get_or_init(HolidayCalendar::cme)
+}
+
+/// UTC offset of US Central time (the CME's clock) on `date`. Daylight time runs from the second
+/// Sunday of March to the first Sunday of November; the switch happens at 02:00, before any session opens.
+pub fn us_central_offset(date: Date) -> UtcOffset {
+    let hours = if is_us_daylight_time(date) { -5 } else { -6 };
+    UtcOffset::from_hms(hours, 0, 0).expect("Valid offset")
+}
+
+/// Whether US daylight saving time is in effect from 03:00 local time on `date` onwards
+/// (second Sunday of March up to, not including, the first Sunday of November).
+pub fn is_us_daylight_time(date: Date) -> bool {
+    let year = date.year();
+    let dst_start = nth_weekday(year, Month::March, Weekday::Sunday, 2).expect("March has two Sundays");
+    let dst_end = nth_weekday(year, Month::November, Weekday::Sunday, 1).expect("November has a Sunday");
+    date >= dst_start && date < dst_end
+}
+
+/// Start of the CME Globex session trading for `trading_date`: 17:00 Central on the previous calendar day.
+pub fn globex_session_open(trading_date: Date) -> OffsetDateTime {
+    let previous_day = trading_date.previous_day().expect("Date out of range");
+    previous_day.with_time(time!(17:00)).assume_offset(us_central_offset(previous_day))
+}
+
+fn is_weekend(date: Date) -> bool {
+    matches!(date.weekday(), Weekday::Saturday | Weekday::Sunday)
+}
+
+fn first_of_next_month(year: i32, month: Month) -> Date {
+    match month {
+        Month::December => Date::from_calendar_date(year + 1, Month::January, 1),
+        _ => Date::from_calendar_date(year, month.next(), 1),
+    }
+    .expect("Invalid first of month")
+}
+
+/// The `n`th (1-based) `weekday` of a month, or `None` when the month has fewer.
+pub fn nth_weekday(year: i32, month: Month, weekday: Weekday, n: u8) -> Option<Date> {
+    let first = Date::from_calendar_date(year, month, 1).ok()?;
+    let offset = (7 + weekday.number_days_from_monday() as i64
+        - first.weekday().number_days_from_monday() as i64)
+        % 7;
+    let date = first + Duration::days(offset + 7 * (n as i64 - 1));
+    (date.month() == month).then_some(date)
+}
+
+fn last_weekday(year: i32, month: Month, weekday: Weekday) -> Option<Date> {
+    let mut date = first_of_next_month(year, month).previous_day()?;
+    while date.weekday() != weekday {
+        date = date.previous_day()?;
+    }
+    Some(date)
+}
+
+/// Western Easter Sunday (anonymous Gregorian algorithm).
+fn easter_sunday(year: i32) -> Option<Date> {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    Date::from_calendar_date(year, Month::try_from(month as u8).ok()?, day as u8).ok()
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_us_central_offset_follows_dst() {
+        assert_eq!(us_central_offset(date!(2024 - 03 - 09)).whole_hours(), -6);
+        assert_eq!(us_central_offset(date!(2024 - 03 - 10)).whole_hours(), -5);
+        assert_eq!(us_central_offset(date!(2024 - 11 - 02)).whole_hours(), -5);
+        assert_eq!(us_central_offset(date!(2024 - 11 - 03)).whole_hours(), -6);
+    }
+
+    #[test]
+    fn test_globex_session_open() {
+        assert_eq!(globex_session_open(date!(2024 - 07 - 08)), datetime!(2024 - 07 - 07 22:00 UTC));
+        assert_eq!(globex_session_open(date!(2024 - 01 - 08)), datetime!(2024 - 01 - 07 23:00 UTC));
+        // Sunday 2024-03-10 opens on daylight time
+        assert_eq!(globex_session_open(date!(2024 - 03 - 11)), datetime!(2024 - 03 - 10 22:00 UTC));
+    }
+
+    #[test]
+    fn test_easter_sunday_known_years() {
+        assert_eq!(easter_sunday(2019), Some(date!(2019 - 04 - 21)));
+        assert_eq!(easter_sunday(2024), Some(date!(2024 - 03 - 31)));
+        assert_eq!(easter_sunday(2025), Some(date!(2025 - 04 - 20)));
+    }
+
+    #[test]
+    fn test_floating_holidays() {
+        let calendar = HolidayCalendar::cme();
+        assert!(calendar.is_holiday(date!(2024 - 01 - 15))); // MLK
+        assert!(calendar.is_holiday(date!(2024 - 02 - 19))); // Presidents Day
+        assert!(calendar.is_holiday(date!(2024 - 03 - 29))); // Good Friday
+        assert!(calendar.is_holiday(date!(2024 - 05 - 27))); // Memorial Day
+        assert!(calendar.is_holiday(date!(2024 - 09 - 02))); // Labor Day
+        assert!(calendar.is_holiday(date!(2024 - 11 - 28))); // Thanksgiving
+    }
+
+    #[test]
+    fn test_observed_shifts() {
+        let calendar = HolidayCalendar::cme();
+        // Christmas 2021 fell on a Saturday
+        assert!(calendar.is_holiday(date!(2021 - 12 - 24)));
+        // Juneteenth 2022 fell on a Sunday
+        assert!(calendar.is_holiday(date!(2022 - 06 - 20)));
+        // Saturday New Year's Day is not moved onto Dec 31
+        assert!(calendar.is_business_day(date!(2021 - 12 - 31)));
+        // New Year's Day 2023 fell on a Sunday
+        assert!(calendar.is_holiday(date!(2023 - 01 - 02)));
+    }
+
+    #[test]
+    fn test_juneteenth_only_from_2022() {
+        let calendar = HolidayCalendar::cme();
+        assert!(calendar.is_business_day(date!(2020 - 06 - 19)));
+        assert!(calendar.is_holiday(date!(2023 - 06 - 19)));
+    }
+
+    #[test]
+    fn test_one_off_closures() {
+        let calendar = HolidayCalendar::cme().with_closure(date!(2025 - 01 - 09));
+        assert!(calendar.is_holiday(date!(2001 - 09 - 12)));
+        assert!(!calendar.is_business_day(date!(2025 - 01 - 09)));
+        assert_eq!(calendar.holiday_name(date!(2025 - 01 - 09)), Some("Exchange closure"));
+    }
+
+    #[test]
+    fn test_business_day_stepping() {
+        let calendar = HolidayCalendar::cme();
+        // Thursday before Good Friday 2024 steps over the long weekend
+        assert_eq!(calendar.next_business_day(date!(2024 - 03 - 28)), date!(2024 - 04 - 01));
+        assert_eq!(calendar.previous_business_day(date!(2024 - 04 - 01)), date!(2024 - 03 - 28));
+        assert_eq!(calendar.business_days_before(date!(2024 - 11 - 29), 2), date!(2024 - 11 - 26));
+        assert_eq!(calendar.nth_last_business_day(2024, Month::November, 3), date!(2024 - 11 - 26));
+        assert_eq!(calendar.business_days_between(date!(2024 - 12 - 23), date!(2024 - 12 - 27)), 4);
+    }
+
+    #[test]
+    fn test_holidays_in_year_is_sorted() {
+        let holidays = HolidayCalendar::cme().holidays_in_year(2024);
+        assert_eq!(holidays.len(), 10);
+        assert!(holidays.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+}
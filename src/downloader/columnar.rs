@@ -0,0 +1,306 @@
+use anyhow::{bail, Context, Result};
+use arrow::{
+    array::{
+        ArrayRef, Float64Array, PrimitiveArray, RecordBatch, StringArray, StringBuilder, TimestampNanosecondArray,
+    },
+    datatypes::{ArrowPrimitiveType, Int32Type, Int64Type, UInt16Type, UInt32Type, UInt64Type, UInt8Type},
+};
+use databento::dbn::{
+    HasRType, MboMsg, Mbp10Msg, Mbp1Msg, OhlcvMsg, RecordHeader, RecordRef, Schema, StatMsg, StatusMsg, TradeMsg,
+    UNDEF_PRICE, UNDEF_TIMESTAMP,
+};
+use std::{ffi::c_char, sync::Arc};
+
+use crate::downloader::output::RecordSink;
+use crate::downloader::schemas::is_ohlcv;
+use crate::processor::{scale_price, ContractFile};
+
+/// Records buffered before they are converted to columns and written as one batch.
+pub const BATCH_ROWS: usize = 64 * 1024;
+
+/// A DBN record type with a typed column layout. Timestamps are UTC nanosecond timestamps and prices
+/// decimal `f64`s, both null when undefined; every other field keeps its DBN integer type.
+pub trait ColumnarRecord: HasRType + Clone + Send + 'static {
+    /// Columns of `rows`, in file order.
+    fn columns(rows: &[Self]) -> Vec<(String, ArrayRef)>;
+}
+
+/// An output format writing the columns of a record type.
+pub trait ColumnarSinkFactory {
+    /// Opens a sink for the `R` records of the raw file `input`.
+    fn sink<R: ColumnarRecord>(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>>;
+}
+
+/// Work generic over the record type of a schema.
+pub trait RecordTypeVisitor {
+    type Output;
+
+    fn visit<R: ColumnarRecord>(self) -> Result<Self::Output>;
+}
+
+/// Runs `visitor` with the record type of `schema`. Fails for schemas without a column layout.
+pub fn with_record_type<V: RecordTypeVisitor>(schema: Schema, visitor: V) -> Result<V::Output> {
+    match schema {
+        schema if is_ohlcv(schema) => visitor.visit::<OhlcvMsg>(),
+        Schema::Trades => visitor.visit::<TradeMsg>(),
+        Schema::Tbbo | Schema::Mbp1 => visitor.visit::<Mbp1Msg>(),
+        Schema::Mbp10 => visitor.visit::<Mbp10Msg>(),
+        Schema::Mbo => visitor.visit::<MboMsg>(),
+        Schema::Statistics => visitor.visit::<StatMsg>(),
+        Schema::Status => visitor.visit::<StatusMsg>(),
+        other => bail!("{other} records have no column layout; decode them as plain ndjson"),
+    }
+}
+
+/// Opens a sink of `factory` for the record type of `schema`.
+pub fn columnar_sink(factory: &impl ColumnarSinkFactory, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+    struct OpenSink<'a, F> {
+        factory: &'a F,
+        input: &'a ContractFile,
+        schema: Schema,
+    }
+
+    impl<F: ColumnarSinkFactory> RecordTypeVisitor for OpenSink<'_, F> {
+        type Output = Box<dyn RecordSink>;
+
+        fn visit<R: ColumnarRecord>(self) -> Result<Self::Output> {
+            self.factory.sink::<R>(self.input, self.schema)
+        }
+    }
+
+    with_record_type(schema, OpenSink { factory, input, schema })
+}
+
+/// Collects records of one type and hands them out as record batches.
+pub trait BatchBuilder: Send {
+    /// Adds `record`, labelled with the symbol of its instrument.
+    fn push(&mut self, record: RecordRef, symbol: &str) -> Result<()>;
+
+    /// Records collected since the last batch.
+    fn buffered(&self) -> usize;
+
+    /// The collected records as a batch: a `contract` column when `contract` is given, `symbol`, then the
+    /// columns of the record type. Every column is nullable so that batches of one record type share a schema
+    /// whatever their values.
+    fn take(&mut self, contract: Option<&str>) -> Result<RecordBatch>;
+}
+
+/// A batch builder for the records of `schema`.
+pub fn batch_builder(schema: Schema) -> Result<Box<dyn BatchBuilder>> {
+    struct NewBuilder;
+
+    impl RecordTypeVisitor for NewBuilder {
+        type Output = Box<dyn BatchBuilder>;
+
+        fn visit<R: ColumnarRecord>(self) -> Result<Self::Output> {
+            Ok(Box::new(RecordRows::<R>::default()))
+        }
+    }
+
+    with_record_type(schema, NewBuilder)
+}
+
+/// Records of type `R` and their symbols, waiting to become a batch.
+pub struct RecordRows<R> {
+    records: Vec<R>,
+    symbols: StringBuilder,
+}
+
+impl<R> Default for RecordRows<R> {
+    fn default() -> Self {
+        Self { records: Vec::new(), symbols: StringBuilder::new() }
+    }
+}
+
+impl<R: ColumnarRecord> RecordRows<R> {
+    /// Column names of the batches, as `take` lays them out.
+    pub fn column_names(contract: bool) -> Result<Vec<String>> {
+        let batch = Self::default().take(contract.then_some(""))?;
+        Ok(batch.schema().fields().iter().map(|field| field.name().clone()).collect())
+    }
+}
+
+impl<R: ColumnarRecord> BatchBuilder for RecordRows<R> {
+    fn push(&mut self, record: RecordRef, symbol: &str) -> Result<()> {
+        let row = record.get::<R>().context("Unexpected record type")?;
+        self.records.push(row.clone());
+        self.symbols.append_value(symbol);
+        Ok(())
+    }
+
+    fn buffered(&self) -> usize {
+        self.records.len()
+    }
+
+    fn take(&mut self, contract: Option<&str>) -> Result<RecordBatch> {
+        let contract = contract.map(|contract| {
+            let column: ArrayRef = Arc::new(StringArray::from_iter_values(self.records.iter().map(|_| contract)));
+            ("contract".to_string(), column)
+        });
+        let symbols: ArrayRef = Arc::new(self.symbols.finish());
+        let columns = contract
+            .into_iter()
+            .chain([("symbol".to_string(), symbols)])
+            .chain(R::columns(&self.records))
+            .map(|(name, column)| (name, column, true));
+        let batch = RecordBatch::try_from_iter_with_nullable(columns)?;
+        self.records.clear();
+        Ok(batch)
+    }
+}
+
+fn timestamps<R>(rows: &[R], ts: impl Fn(&R) -> u64) -> ArrayRef {
+    let values = rows.iter().map(|row| Some(ts(row)).filter(|&ts| ts != UNDEF_TIMESTAMP).map(|ts| ts as i64));
+    Arc::new(TimestampNanosecondArray::from_iter(values).with_timezone("UTC"))
+}
+
+fn prices<R>(rows: &[R], px: impl Fn(&R) -> i64) -> ArrayRef {
+    Arc::new(Float64Array::from_iter(rows.iter().map(|row| Some(px(row)).filter(|&px| px != UNDEF_PRICE).map(scale_price))))
+}
+
+fn values<R, T: ArrowPrimitiveType>(rows: &[R], value: impl Fn(&R) -> T::Native) -> ArrayRef {
+    Arc::new(PrimitiveArray::<T>::from_iter_values(rows.iter().map(value)))
+}
+
+/// A one-character code such as an action or side, e.g. `T` or `B`.
+fn codes<R>(rows: &[R], code: impl Fn(&R) -> c_char) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(rows.iter().map(|row| char::from(code(row) as u8).to_string())))
+}
+
+fn header_columns<R>(rows: &[R], hd: impl Fn(&R) -> &RecordHeader) -> Vec<(String, ArrayRef)> {
+    vec![
+        ("ts_event".to_string(), timestamps(rows, |row| hd(row).ts_event)),
+        ("publisher_id".to_string(), values::<_, UInt16Type>(rows, |row| hd(row).publisher_id)),
+        ("instrument_id".to_string(), values::<_, UInt32Type>(rows, |row| hd(row).instrument_id)),
+    ]
+}
+
+/// Columns shared by trades, TBBO and the MBP schemas.
+macro_rules! trade_columns {
+    ($rows:expr) => {{
+        let rows = $rows;
+        let mut columns = header_columns(rows, |row| &row.hd);
+        columns.extend([
+            ("ts_recv".to_string(), timestamps(rows, |row| row.ts_recv)),
+            ("action".to_string(), codes(rows, |row| row.action)),
+            ("side".to_string(), codes(rows, |row| row.side)),
+            ("depth".to_string(), values::<_, UInt8Type>(rows, |row| row.depth)),
+            ("price".to_string(), prices(rows, |row| row.price)),
+            ("size".to_string(), values::<_, UInt32Type>(rows, |row| row.size)),
+            ("flags".to_string(), values::<_, UInt8Type>(rows, |row| row.flags.raw())),
+            ("ts_in_delta".to_string(), values::<_, Int32Type>(rows, |row| row.ts_in_delta)),
+            ("sequence".to_string(), values::<_, UInt32Type>(rows, |row| row.sequence)),
+        ]);
+        columns
+    }};
+}
+
+/// `bid_px_00`, `ask_px_00`, ... for every book level of the MBP schemas.
+macro_rules! level_columns {
+    ($rows:expr, $levels:expr) => {{
+        let rows = $rows;
+        let mut columns = Vec::new();
+        for level in 0..$levels {
+            columns.extend([
+                (format!("bid_px_{level:02}"), prices(rows, |row| row.levels[level].bid_px)),
+                (format!("ask_px_{level:02}"), prices(rows, |row| row.levels[level].ask_px)),
+                (format!("bid_sz_{level:02}"), values::<_, UInt32Type>(rows, |row| row.levels[level].bid_sz)),
+                (format!("ask_sz_{level:02}"), values::<_, UInt32Type>(rows, |row| row.levels[level].ask_sz)),
+                (format!("bid_ct_{level:02}"), values::<_, UInt32Type>(rows, |row| row.levels[level].bid_ct)),
+                (format!("ask_ct_{level:02}"), values::<_, UInt32Type>(rows, |row| row.levels[level].ask_ct)),
+            ]);
+        }
+        columns
+    }};
+}
+
+impl ColumnarRecord for OhlcvMsg {
+    fn columns(rows: &[Self]) -> Vec<(String, ArrayRef)> {
+        let mut columns = header_columns(rows, |row| &row.hd);
+        columns.extend([
+            ("open".to_string(), prices(rows, |row| row.open)),
+            ("high".to_string(), prices(rows, |row| row.high)),
+            ("low".to_string(), prices(rows, |row| row.low)),
+            ("close".to_string(), prices(rows, |row| row.close)),
+            ("volume".to_string(), values::<_, UInt64Type>(rows, |row| row.volume)),
+        ]);
+        columns
+    }
+}
+
+impl ColumnarRecord for TradeMsg {
+    fn columns(rows: &[Self]) -> Vec<(String, ArrayRef)> {
+        trade_columns!(rows)
+    }
+}
+
+/// MBP-1 and TBBO.
+impl ColumnarRecord for Mbp1Msg {
+    fn columns(rows: &[Self]) -> Vec<(String, ArrayRef)> {
+        let mut columns = trade_columns!(rows);
+        columns.extend(level_columns!(rows, 1));
+        columns
+    }
+}
+
+impl ColumnarRecord for Mbp10Msg {
+    fn columns(rows: &[Self]) -> Vec<(String, ArrayRef)> {
+        let mut columns = trade_columns!(rows);
+        columns.extend(level_columns!(rows, 10));
+        columns
+    }
+}
+
+impl ColumnarRecord for MboMsg {
+    fn columns(rows: &[Self]) -> Vec<(String, ArrayRef)> {
+        let mut columns = header_columns(rows, |row| &row.hd);
+        columns.extend([
+            ("ts_recv".to_string(), timestamps(rows, |row| row.ts_recv)),
+            ("action".to_string(), codes(rows, |row| row.action)),
+            ("side".to_string(), codes(rows, |row| row.side)),
+            ("price".to_string(), prices(rows, |row| row.price)),
+            ("size".to_string(), values::<_, UInt32Type>(rows, |row| row.size)),
+            ("channel_id".to_string(), values::<_, UInt8Type>(rows, |row| row.channel_id)),
+            ("order_id".to_string(), values::<_, UInt64Type>(rows, |row| row.order_id)),
+            ("flags".to_string(), values::<_, UInt8Type>(rows, |row| row.flags.raw())),
+            ("ts_in_delta".to_string(), values::<_, Int32Type>(rows, |row| row.ts_in_delta)),
+            ("sequence".to_string(), values::<_, UInt32Type>(rows, |row| row.sequence)),
+        ]);
+        columns
+    }
+}
+
+impl ColumnarRecord for StatMsg {
+    fn columns(rows: &[Self]) -> Vec<(String, ArrayRef)> {
+        let mut columns = header_columns(rows, |row| &row.hd);
+        columns.extend([
+            ("ts_recv".to_string(), timestamps(rows, |row| row.ts_recv)),
+            ("ts_ref".to_string(), timestamps(rows, |row| row.ts_ref)),
+            ("price".to_string(), prices(rows, |row| row.price)),
+            ("quantity".to_string(), values::<_, Int64Type>(rows, |row| row.quantity)),
+            ("sequence".to_string(), values::<_, UInt32Type>(rows, |row| row.sequence)),
+            ("ts_in_delta".to_string(), values::<_, Int32Type>(rows, |row| row.ts_in_delta)),
+            ("stat_type".to_string(), values::<_, UInt16Type>(rows, |row| row.stat_type)),
+            ("channel_id".to_string(), values::<_, UInt16Type>(rows, |row| row.channel_id)),
+            ("update_action".to_string(), values::<_, UInt8Type>(rows, |row| row.update_action)),
+            ("stat_flags".to_string(), values::<_, UInt8Type>(rows, |row| row.stat_flags)),
+        ]);
+        columns
+    }
+}
+
+impl ColumnarRecord for StatusMsg {
+    fn columns(rows: &[Self]) -> Vec<(String, ArrayRef)> {
+        let mut columns = header_columns(rows, |row| &row.hd);
+        columns.extend([
+            ("ts_recv".to_string(), timestamps(rows, |row| row.ts_recv)),
+            ("action".to_string(), values::<_, UInt16Type>(rows, |row| row.action)),
+            ("reason".to_string(), values::<_, UInt16Type>(rows, |row| row.reason)),
+            ("trading_event".to_string(), values::<_, UInt16Type>(rows, |row| row.trading_event)),
+            ("is_trading".to_string(), codes(rows, |row| row.is_trading)),
+            ("is_quoting".to_string(), codes(rows, |row| row.is_quoting)),
+            ("is_short_sell_restricted".to_string(), codes(rows, |row| row.is_short_sell_restricted)),
+        ]);
+        columns
+    }
+}
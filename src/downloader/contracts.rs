@@ -1,8 +1,14 @@
+use serde::{Deserialize, Serialize};
 use time::{Date, Duration, Month, Weekday};
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use crate::downloader::calendar::{cme_calendar, nth_weekday};
+use crate::downloader::products::{product_registry, ProductSpec};
+use crate::downloader::roll::{ContractWindow, RollAnchor, RollPolicy};
+use crate::downloader::roll_calendar::RollCalendar;
 
 /// Maps Month enum to Futures month code letter.
-fn futures_month_code(month: Month) -> &'static str {
+pub(crate) fn futures_month_code(month: Month) -> &'static str {
     match month {
         Month::January => "F", Month::February => "G", Month::March => "H", Month::April => "J",
         Month::May => "K", Month::June => "M", Month::July => "N", Month::August => "Q",
@@ -10,6 +16,14 @@ fn futures_month_code(month: Month) -> &'static str {
     }
 }
 
+/// Maps a futures month code letter back to its Month.
+pub(crate) fn month_from_code(code: char) -> Option<Month> {
+    ALL_MONTHS
+        .iter()
+        .copied()
+        .find(|&month| futures_month_code(month).starts_with(code))
+}
+
 const ALL_MONTHS: [Month; 12] = [
     Month::January, Month::February, Month::March, Month::April, Month::May, Month::June,
     Month::July, Month::August, Month::September, Month::October, Month::November, Month::December,
@@ -27,35 +41,90 @@ fn previous_month(month: Month, year: i32) -> (i32, Month) {
     }
 }
 
-fn energy_expiry(year: i32, delivery_month: Month) -> Date {
-    let (exp_year, exp_month) = previous_month(delivery_month, year);
-    let mut date = Date::from_calendar_date(exp_year, exp_month, 25).unwrap();
-    let mut business_days = 0;
+/// Last-trading-day rule used to compute a contract's expiration date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryRule {
+    /// CL: 3 business days before the 25th of the month prior to the contract month.
+    CrudeOil,
+    /// NG: 3rd last business day of the month prior to the contract month.
+    NaturalGas,
+    /// RB, HO: last business day of the month prior to the contract month.
+    LastBusinessDayOfPriorMonth,
+    /// Equity indices: 3rd Friday of the contract month.
+    ThirdFriday,
+    /// Metals (GC, SI, HG, PL): 3rd last business day of the contract month.
+    ThirdLastBusinessDay,
+    /// ZN, ZB: 7th business day before the last business day of the contract month.
+    SeventhBusinessDayBeforeLast,
+    /// ZF, ZT: last business day of the contract month.
+    LastBusinessDay,
+    /// Grains (ZC, ZS, ZW): business day before the 15th of the contract month.
+    #[serde(rename = "business_day_before_15th")]
+    BusinessDayBefore15th,
+    /// FX (6E, 6J, 6B): 2nd business day before the 3rd Wednesday of the contract month.
+    TwoDaysBeforeThirdWednesday,
+}
 
-    while business_days < 3 {
-        date = date.previous_day().unwrap();
-        if !matches!(date.weekday(), Weekday::Saturday | Weekday::Sunday) {
-            business_days += 1;
+impl ExpiryRule {
+    pub fn expiry(&self, year: i32, month: Month) -> Date {
+        match self {
+            ExpiryRule::CrudeOil => energy_expiry(year, month),
+            ExpiryRule::NaturalGas => ng_expiry(year, month),
+            ExpiryRule::LastBusinessDayOfPriorMonth => refined_products_expiry(year, month),
+            ExpiryRule::ThirdFriday => indices_expiry(year, month),
+            ExpiryRule::ThirdLastBusinessDay => metals_expiry(year, month),
+            ExpiryRule::SeventhBusinessDayBeforeLast => long_treasury_expiry(year, month),
+            ExpiryRule::LastBusinessDay => short_treasury_expiry(year, month),
+            ExpiryRule::BusinessDayBefore15th => grains_expiry(year, month),
+            ExpiryRule::TwoDaysBeforeThirdWednesday => fx_expiry(year, month),
         }
     }
 
-    date
+    /// First notice day for physically delivered contracts that have one:
+    /// the last business day of the month prior to the contract month.
+    pub fn first_notice(&self, year: i32, month: Month) -> Option<Date> {
+        match self {
+            ExpiryRule::ThirdLastBusinessDay
+            | ExpiryRule::SeventhBusinessDayBeforeLast
+            | ExpiryRule::LastBusinessDay
+            | ExpiryRule::BusinessDayBefore15th => {
+                let first_of_month = Date::from_calendar_date(year, month, 1).unwrap();
+                Some(cme_calendar().previous_business_day(first_of_month))
+            }
+            ExpiryRule::CrudeOil
+            | ExpiryRule::NaturalGas
+            | ExpiryRule::LastBusinessDayOfPriorMonth
+            | ExpiryRule::ThirdFriday
+            | ExpiryRule::TwoDaysBeforeThirdWednesday => None,
+        }
+    }
 }
 
-fn ng_expiry(year: i32, delivery_month: Month) -> Date {
-    let mut date = Date::from_calendar_date(year, delivery_month, 1).unwrap();
-    let mut business_days = 0;
+/// CL: trading terminates 3 business days before the 25th calendar day of the month prior to
+/// the contract month, or 4 business days before it when the 25th is not a business day.
+fn energy_expiry(year: i32, delivery_month: Month) -> Date {
+    let calendar = cme_calendar();
+    let (exp_year, exp_month) = previous_month(delivery_month, year);
+    let reference = Date::from_calendar_date(exp_year, exp_month, 25).unwrap();
+    let business_days = if calendar.is_business_day(reference) { 3 } else { 4 };
 
-    while business_days < 3 {
-        date = date.previous_day().unwrap();
-        if !matches!(date.weekday(), Weekday::Saturday | Weekday::Sunday) {
-            business_days += 1;
-        }
-    }
+    calendar.business_days_before(reference, business_days)
+}
+
+/// NG: trading terminates on the 3rd last business day of the month prior to the contract month.
+fn ng_expiry(year: i32, delivery_month: Month) -> Date {
+    let first_of_month = Date::from_calendar_date(year, delivery_month, 1).unwrap();
+    cme_calendar().business_days_before(first_of_month, 3)
+}
 
-    date
+/// RB/HO: trading terminates on the last business day of the month prior to the contract month.
+fn refined_products_expiry(year: i32, delivery_month: Month) -> Date {
+    let first_of_month = Date::from_calendar_date(year, delivery_month, 1).unwrap();
+    cme_calendar().previous_business_day(first_of_month)
 }
 
+/// Equity indices: 3rd Friday of the contract month, or the business day before it on a holiday.
 fn indices_expiry(year: i32, month: Month) -> Date {
     let mut count = 0;
     let mut date = Date::from_calendar_date(year, month, 1).unwrap();
@@ -69,76 +138,282 @@ fn indices_expiry(year: i32, month: Month) -> Date {
         }
     }
 
-    date
+    cme_calendar().business_day_on_or_before(date)
+}
+
+/// Metals: trading terminates on the 3rd last business day of the contract month.
+fn metals_expiry(year: i32, month: Month) -> Date {
+    cme_calendar().nth_last_business_day(year, month, 3)
+}
+
+/// ZN/ZB: trading terminates on the 7th business day preceding the last business day of the contract month.
+fn long_treasury_expiry(year: i32, month: Month) -> Date {
+    let calendar = cme_calendar();
+    let last_business_day = calendar.nth_last_business_day(year, month, 1);
+    calendar.business_days_before(last_business_day, 7)
+}
+
+/// ZF/ZT: trading terminates on the last business day of the contract month.
+fn short_treasury_expiry(year: i32, month: Month) -> Date {
+    cme_calendar().nth_last_business_day(year, month, 1)
+}
+
+/// Grains: trading terminates on the business day prior to the 15th calendar day of the contract month.
+fn grains_expiry(year: i32, month: Month) -> Date {
+    let fifteenth = Date::from_calendar_date(year, month, 15).unwrap();
+    cme_calendar().previous_business_day(fifteenth)
+}
+
+/// FX: trading terminates on the 2nd business day immediately preceding the 3rd Wednesday of the contract month.
+fn fx_expiry(year: i32, month: Month) -> Date {
+    let third_wednesday = nth_weekday(year, month, Weekday::Wednesday, 3).unwrap();
+    cme_calendar().business_days_before(third_wednesday, 2)
+}
+
+/// A single contract and the date window downloaded for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractPeriod {
+    /// Raw contract symbol sent to Databento, e.g. `CLZ5`.
+    pub symbol: String,
+    /// Product root from the registry, e.g. `CL`.
+    pub root: String,
+    /// Contract (delivery) month.
+    pub month: Month,
+    /// Four-digit contract (delivery) year.
+    pub year: i32,
+    /// Last trading day of the contract.
+    pub expiry: Date,
+    /// First notice day, for physically delivered contracts that have one.
+    pub first_notice: Option<Date>,
+    /// Date the position is rolled into the next contract under the roll policy in use.
+    pub roll: Date,
+    /// First date of the download window.
+    pub start: Date,
+    /// Last date of the download window (inclusive).
+    pub end: Date,
+}
+
+/// Why contract periods could not be generated for a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContractError {
+    /// The root is not in the product registry.
+    UnsupportedSymbol { symbol: String },
+    /// The configured product registry could not be loaded.
+    Registry { error: String },
+    /// The requested start date is after the requested end date.
+    InvalidDateRange { start: Date, end: Date },
+    /// The window configured for the product does not cover a single day.
+    EmptyWindow { contract: String, start: Date, end: Date },
+    /// The window starts after the contract stopped trading, so the symbol cannot resolve.
+    WindowStartsAfterExpiry { contract: String, start: Date, expiry: Date },
+}
+
+impl ContractPeriod {
+    /// Contract name with a four-digit year, e.g. `CLZ2025`. Used for file names.
+    pub fn contract_name(&self) -> String {
+        full_contract_symbol(&self.root, self.month, self.year)
+    }
+}
+
+impl std::fmt::Display for ContractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContractError::UnsupportedSymbol { symbol } => {
+                write!(f, "Unsupported symbol: {symbol} (not in the product registry)")
+            }
+            ContractError::Registry { error } => write!(f, "Product registry unavailable: {error}"),
+            ContractError::InvalidDateRange { start, end } => {
+                write!(f, "Invalid date range: start {start} is after end {end}")
+            }
+            ContractError::EmptyWindow { contract, start, end } => {
+                write!(f, "Invalid window for {contract}: start {start} is not before end {end}")
+            }
+            ContractError::WindowStartsAfterExpiry { contract, start, expiry } => write!(
+                f,
+                "Invalid window for {contract}: start {start} is after the contract expired on {expiry}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ContractError {}
+
+impl ContractError {
+    pub(crate) fn registry(error: anyhow::Error) -> Self {
+        ContractError::Registry { error: format!("{error:#}") }
+    }
+}
+
+fn product_spec(symbol: &str) -> Result<&'static ProductSpec, ContractError> {
+    product_registry()
+        .map_err(ContractError::registry)?
+        .get(symbol)
+        .ok_or_else(|| ContractError::UnsupportedSymbol { symbol: symbol.to_string() })
+}
+
+pub fn calculate_expiration_date(symbol: &str, year: i32, month: Month) -> Result<Date, ContractError> {
+    let spec = product_spec(symbol)?;
+    Ok(contract_dates(spec, spec.roll.anchor, year, month).expiry)
+}
+
+/// Raw CME Globex symbol of a contract as seen on `as_of`.
+/// Globex symbols carry a single year digit, which Databento resolves against the query date,
+/// so `CLZ5` means 2015 in a 2015 request and 2025 in a 2025 request. Contracts ten or more
+/// years after `as_of` would collide with the current decade and carry two digits instead.
+pub fn raw_contract_symbol(root: &str, month: Month, year: i32, as_of: Date) -> String {
+    let code = futures_month_code(month);
+    if year - as_of.year() >= 10 {
+        format!("{root}{code}{:02}", year.rem_euclid(100))
+    } else {
+        format!("{root}{code}{}", year.rem_euclid(10))
+    }
+}
+
+/// Contract symbol with a four-digit year, e.g. `CLZ2025`. Never ambiguous across decades.
+pub fn full_contract_symbol(root: &str, month: Month, year: i32) -> String {
+    format!("{root}{}{year:04}", futures_month_code(month))
+}
+
+/// Splits a four-digit-year contract name such as `CLZ2025` into root, month and year.
+pub fn parse_full_contract_symbol(contract: &str) -> Option<(&str, Month, i32)> {
+    let split = contract.len().checked_sub(5)?;
+    if !contract.is_char_boundary(split) {
+        return None;
+    }
+    let (root, suffix) = contract.split_at(split);
+    let mut chars = suffix.chars();
+    let month = month_from_code(chars.next()?)?;
+    let digits = chars.as_str();
+    if root.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((root, month, digits.parse().ok()?))
+}
+
+/// Roll date of a contract under its product's registry roll policy.
+pub fn scheduled_roll_date(root: &str, year: i32, month: Month) -> Result<Date, ContractError> {
+    let spec = product_spec(root)?;
+    Ok(contract_dates(spec, spec.roll.anchor, year, month).roll)
+}
+
+/// Roll date of a listed contract when it is rolled on `anchor` instead of the registry's.
+pub fn roll_date_on(root: &str, year: i32, month: Month, anchor: RollAnchor) -> Result<Date, ContractError> {
+    Ok(contract_dates(product_spec(root)?, anchor, year, month).roll)
+}
+
+/// Expiry, first notice and roll dates of one listed contract.
+struct ContractDates {
+    expiry: Date,
+    first_notice: Option<Date>,
+    roll: Date,
 }
 
-fn calculate_expiration_date(symbol: &str, year: i32, month: Month) -> Date {
-    match symbol {
-        "NG" => ng_expiry(year, month),
-        "CL" | "RB" | "HO" => energy_expiry(year, month),
-        "ES" | "NQ" | "RTY" | "YM" => indices_expiry(year, month),
-        _ => panic!("Unsupported symbol: {symbol}"),
+fn contract_dates(spec: &ProductSpec, anchor: RollAnchor, year: i32, month: Month) -> ContractDates {
+    let expiry = cme_calendar().business_days_before(spec.expiry_rule.expiry(year, month), spec.expiry_offset_days);
+    let first_notice = spec.expiry_rule.first_notice(year, month);
+    let roll = match anchor {
+        RollAnchor::LastTrade => expiry,
+        RollAnchor::FirstNotice => first_notice.unwrap_or(expiry),
+    };
+
+    ContractDates { expiry, first_notice, roll }
+}
+
+fn contract_period(spec: &ProductSpec, month: Month, year: i32, dates: &ContractDates, start: Date, end: Date) -> ContractPeriod {
+    ContractPeriod {
+        symbol: raw_contract_symbol(&spec.root, month, year, start),
+        root: spec.root.clone(),
+        month,
+        year,
+        expiry: dates.expiry,
+        first_notice: dates.first_notice,
+        roll: dates.roll,
+        start,
+        end,
+    }
+}
+
+fn validate_period(period: &ContractPeriod) -> Result<(), ContractError> {
+    if period.start >= period.end {
+        return Err(ContractError::EmptyWindow {
+            contract: period.symbol.clone(),
+            start: period.start,
+            end: period.end,
+        });
+    }
+    if period.start > period.expiry {
+        return Err(ContractError::WindowStartsAfterExpiry {
+            contract: period.symbol.clone(),
+            start: period.start,
+            expiry: period.expiry,
+        });
     }
+    Ok(())
 }
 
-fn generate_energy_contracts(symbol: &str, start_date: Date, end_date: Date) -> Vec<(String, Date, Date)> {
+fn generate_energy_contracts(
+    spec: &ProductSpec,
+    anchor: RollAnchor,
+    start_date: Date,
+    end_date: Date,
+    days_before: i64,
+    days_after: i64,
+) -> Vec<ContractPeriod> {
     let mut periods = Vec::new();
-    let mut current = start_date;
-
-    while current <= end_date {
-        for &month in ALL_MONTHS.iter() {
-            let year = current.year();
-            let expiry = calculate_expiration_date(symbol, year, month);
-            if expiry >= start_date && expiry <= end_date {
-                let code = futures_month_code(month);
-                let symbol_code = format!("{}{}{}", symbol, code, year % 10);
-                let start = expiry - Duration::days(40);
-                let end = if symbol == "NG" {
-                    expiry
-                } else {
-                    expiry + Duration::days(3)
-                };
-                periods.push((symbol_code, start, end));
+
+    // Contracts expire in the month before delivery, so the January contract of the
+    // following year can still expire inside the requested range.
+    for year in start_date.year()..=end_date.year() + 1 {
+        for &month in spec.listed_months.iter() {
+            let dates = contract_dates(spec, anchor, year, month);
+            if dates.roll >= start_date && dates.roll <= end_date {
+                let start = dates.roll - Duration::days(days_before);
+                let end = dates.roll + Duration::days(days_after);
+                periods.push(contract_period(spec, month, year, &dates, start, end));
             }
         }
-        current = Date::from_calendar_date(current.year() + 1, Month::January, 1).unwrap();
     }
 
     periods
 }
 
-fn generate_index_contracts(symbol: &str, start_date: Date, end_date: Date) -> Vec<(String, Date, Date)> {
-    let mut periods: Vec<(String, Date, Date)> = Vec::new();
+fn generate_index_contracts(
+    spec: &ProductSpec,
+    anchor: RollAnchor,
+    start_date: Date,
+    end_date: Date,
+    overlap_days: i64,
+    first_span_days: i64,
+) -> Vec<ContractPeriod> {
+    let mut periods = Vec::new();
 
-    // Start from the first contract expiry on or after start_date
+    // Start from the first contract roll on or after start_date
     let mut current_year = start_date.year();
-    let index_months = [Month::March, Month::June, Month::September, Month::December];
 
     // Initialize the first contract
-    let mut previous_expiry = None;
+    let mut previous_roll = None;
 
-    while current_year <= end_date.year() {
-        for &month in &index_months {
-            let expiry = calculate_expiration_date(symbol, current_year, month);
-            if expiry < start_date {
+    // First-notice rolls of January contracts fall in the previous December
+    while current_year <= end_date.year() + 1 {
+        for &month in &spec.listed_months {
+            let dates = contract_dates(spec, anchor, current_year, month);
+            if dates.roll < start_date {
                 continue;
             }
-            if expiry > end_date {
+            if dates.roll > end_date {
                 break;
             }
 
-            let start = if let Some(prev_expiry) = previous_expiry {
-                prev_expiry - Duration::days(10)
+            let start = if let Some(prev_roll) = previous_roll {
+                prev_roll - Duration::days(overlap_days)
             } else {
-                // First contract: assume 3-month default span
-                expiry - Duration::days(90)
+                // First contract: no previous roll to overlap with
+                dates.roll - Duration::days(first_span_days)
             };
 
-            let code = futures_month_code(month);
-            let symbol_code = format!("{}{}{}", symbol, code, current_year % 10);
-            periods.push((symbol_code, start, expiry));
+            periods.push(contract_period(spec, month, current_year, &dates, start, dates.roll));
 
-            previous_expiry = Some(expiry);
+            previous_roll = Some(dates.roll);
         }
 
         current_year += 1;
@@ -147,19 +422,118 @@ fn generate_index_contracts(symbol: &str, start_date: Date, end_date: Date) -> V
     periods
 }
 
+/// Contract periods of `symbol` under its registry roll policy.
+pub fn generate_contract_periods(
+    symbol: &str,
+    start_date: Date,
+    end_date: Date,
+) -> Result<Vec<ContractPeriod>, ContractError> {
+    let spec = product_spec(symbol)?;
+    generate_contract_periods_with_policy(symbol, start_date, end_date, &spec.roll)
+}
 
+/// Contract periods of `symbol` under `policy` instead of the registry default.
+pub fn generate_contract_periods_with_policy(
+    symbol: &str,
+    start_date: Date,
+    end_date: Date,
+    policy: &RollPolicy,
+) -> Result<Vec<ContractPeriod>, ContractError> {
+    if start_date > end_date {
+        return Err(ContractError::InvalidDateRange { start: start_date, end: end_date });
+    }
 
+    let spec = product_spec(symbol)?;
+    let periods = match policy.window {
+        ContractWindow::BeforeExpiry { days_before, days_after } => {
+            generate_energy_contracts(spec, policy.anchor, start_date, end_date, days_before, days_after)
+        }
+        ContractWindow::Overlap { overlap_days, first_span_days } => {
+            generate_index_contracts(spec, policy.anchor, start_date, end_date, overlap_days, first_span_days)
+        }
+    };
 
-pub fn generate_contract_periods(
-    symbol: &str,
+    periods.iter().try_for_each(validate_period)?;
+    Ok(periods)
+}
+
+/// Periods of a sibling product (e.g. `MES` for `ES`) over the same windows as `parent_periods`,
+/// so both contracts cover identical dates. Months the sibling does not list are skipped.
+pub fn sibling_contract_periods(
+    parent_periods: &[ContractPeriod],
+    sibling: &str,
+) -> Result<Vec<ContractPeriod>, ContractError> {
+    let spec = product_spec(sibling)?;
+
+    let periods = parent_periods
+        .iter()
+        .filter(|parent| spec.listed_months.contains(&parent.month))
+        .map(|parent| {
+            // Roll with the parent so both products switch contracts on the same day
+            let dates = ContractDates { roll: parent.roll, ..contract_dates(spec, spec.roll.anchor, parent.year, parent.month) };
+            contract_period(spec, parent.month, parent.year, &dates, parent.start, parent.end)
+        })
+        .collect::<Vec<_>>();
+
+    periods.iter().try_for_each(validate_period)?;
+    Ok(periods)
+}
+
+/// Options used by the download and quote paths to expand products into contract periods.
+#[derive(Debug, Clone, Default)]
+pub struct PeriodOptions {
+    /// Also generate each product's siblings (e.g. micros) over the parent's windows.
+    pub include_siblings: bool,
+    /// Roll policies replacing the registry default, keyed by product root.
+    pub roll_overrides: HashMap<String, RollPolicy>,
+    /// Detected roll dates that move the windows of the contracts they cover.
+    pub roll_calendar: Option<RollCalendar>,
+}
+
+impl PeriodOptions {
+    /// Roll policy used for `spec`: its override if one is set, otherwise the registry default.
+    pub fn roll_policy(&self, spec: &ProductSpec) -> RollPolicy {
+        self.roll_overrides.get(&spec.root).copied().unwrap_or(spec.roll)
+    }
+}
+
+/// Periods for every requested product. With `include_siblings`, each product's siblings are added
+/// over the parent's windows; siblings also requested on their own are then only generated once.
+pub fn generate_product_periods(
+    symbols: &[&str],
     start_date: Date,
     end_date: Date,
-) -> Vec<(String, Date, Date)> {
-    match symbol {
-        "CL" | "NG" | "RB" | "HO" => generate_energy_contracts(symbol, start_date, end_date),
-        "ES" | "NQ" | "RTY" | "YM" => generate_index_contracts(symbol, start_date, end_date),
-        _ => panic!("Unsupported symbol: {symbol}"),
+    options: &PeriodOptions,
+) -> Result<Vec<ContractPeriod>, ContractError> {
+    let registry = product_registry().map_err(ContractError::registry)?;
+    let mut periods = Vec::new();
+
+    for &symbol in symbols {
+        let spec = product_spec(symbol)?;
+        let covered_by_parent = options.include_siblings
+            && spec.parent.as_deref().is_some_and(|parent| symbols.contains(&parent));
+        if covered_by_parent {
+            continue;
+        }
+
+        let policy = options.roll_policy(spec);
+        let parent_periods = generate_contract_periods_with_policy(symbol, start_date, end_date, &policy)?;
+        let mut sibling_periods = Vec::new();
+        if options.include_siblings {
+            for sibling in registry.siblings_of(symbol) {
+                sibling_periods.extend(sibling_contract_periods(&parent_periods, &sibling.root)?);
+            }
+        }
+        periods.extend(parent_periods);
+        periods.extend(sibling_periods);
     }
+
+    if let Some(calendar) = &options.roll_calendar {
+        calendar.apply(&mut periods);
+        periods.iter().try_for_each(validate_period)?;
+    }
+
+    Ok(periods)
 }
 
 //-----------------------------------------------------------------------------------------------------------------//
@@ -174,6 +548,15 @@ mod tests {
         assert_eq!(futures_month_code(Month::December), "Z");
     }
 
+    #[test]
+    fn test_month_from_code_round_trips() {
+        for month in ALL_MONTHS {
+            let code = futures_month_code(month).chars().next().unwrap();
+            assert_eq!(month_from_code(code), Some(month));
+        }
+        assert_eq!(month_from_code('A'), None);
+    }
+
     #[test]
     fn test_previous_month_regular_case() {
         let (year, month) = previous_month(Month::October, 2025);
@@ -188,8 +571,51 @@ mod tests {
 
     #[test]
     fn test_energy_expiry_skips_weekends() {
+        // June 25, 2023 is a Sunday and June 19 is Juneteenth
         let expiry = energy_expiry(2023, Month::July);
-        assert_eq!(expiry, date!(2023 - 06 - 21));
+        assert_eq!(expiry, date!(2023 - 06 - 20));
+    }
+
+    #[test]
+    fn test_cl_expiry_matches_published_dates() {
+        // Christmas on the 25th
+        assert_eq!(energy_expiry(2024, Month::January), date!(2023 - 12 - 19));
+        assert_eq!(energy_expiry(2025, Month::January), date!(2024 - 12 - 19));
+        // Presidents Day inside the count
+        assert_eq!(energy_expiry(2024, Month::March), date!(2024 - 02 - 20));
+        // Thanksgiving on the 25th
+        assert_eq!(energy_expiry(2021, Month::December), date!(2021 - 11 - 19));
+        // Good Friday inside the count
+        assert_eq!(energy_expiry(2011, Month::May), date!(2011 - 04 - 19));
+        // Plain business-day 25th
+        assert_eq!(energy_expiry(2023, Month::October), date!(2023 - 09 - 20));
+        assert_eq!(energy_expiry(2025, Month::December), date!(2025 - 11 - 20));
+    }
+
+    #[test]
+    fn test_ng_expiry_matches_published_dates() {
+        // Good Friday 2024
+        assert_eq!(ng_expiry(2024, Month::April), date!(2024 - 03 - 26));
+        // Thanksgiving 2024
+        assert_eq!(ng_expiry(2024, Month::December), date!(2024 - 11 - 26));
+        // Memorial Day 2021
+        assert_eq!(ng_expiry(2021, Month::June), date!(2021 - 05 - 26));
+        assert_eq!(ng_expiry(2023, Month::December), date!(2023 - 11 - 28));
+        assert_eq!(ng_expiry(2025, Month::January), date!(2024 - 12 - 27));
+        assert_eq!(ng_expiry(2022, Month::January), date!(2021 - 12 - 29));
+    }
+
+    #[test]
+    fn test_refined_products_expiry_matches_published_dates() {
+        assert_eq!(refined_products_expiry(2024, Month::January), date!(2023 - 12 - 29));
+        assert_eq!(refined_products_expiry(2024, Month::March), date!(2024 - 02 - 29));
+        // Good Friday 2024
+        assert_eq!(refined_products_expiry(2024, Month::April), date!(2024 - 03 - 28));
+        // Last day of June 2024 is a Sunday
+        assert_eq!(refined_products_expiry(2024, Month::July), date!(2024 - 06 - 28));
+        // Day after Thanksgiving trades with an early close
+        assert_eq!(refined_products_expiry(2024, Month::December), date!(2024 - 11 - 29));
+        assert_eq!(refined_products_expiry(2025, Month::January), date!(2024 - 12 - 31));
     }
 
     #[test]
@@ -200,8 +626,8 @@ mod tests {
 
     #[test]
     fn test_ng_uses_different_rule_than_cl() {
-        let ng_exp = calculate_expiration_date("NG", 2023, Month::July);
-        let cl_exp = calculate_expiration_date("CL", 2023, Month::July);
+        let ng_exp = calculate_expiration_date("NG", 2023, Month::July).unwrap();
+        let cl_exp = calculate_expiration_date("CL", 2023, Month::July).unwrap();
         assert_ne!(ng_exp, cl_exp);
     }
 
@@ -211,56 +637,344 @@ mod tests {
         assert_eq!(expiry, date!(2024 - 06 - 21));
     }
 
+    #[test]
+    fn test_indices_expiry_moves_off_holiday() {
+        // Third Friday of June 2026 is Juneteenth
+        assert_eq!(indices_expiry(2026, Month::June), date!(2026 - 06 - 18));
+        assert_eq!(indices_expiry(2023, Month::December), date!(2023 - 12 - 15));
+    }
+
+    #[test]
+    fn test_metals_expiry_matches_published_dates() {
+        assert_eq!(metals_expiry(2024, Month::February), date!(2024 - 02 - 27));
+        assert_eq!(metals_expiry(2024, Month::December), date!(2024 - 12 - 27));
+        // Good Friday 2024
+        assert_eq!(metals_expiry(2024, Month::March), date!(2024 - 03 - 26));
+        assert_eq!(metals_expiry(2024, Month::April), date!(2024 - 04 - 26));
+    }
+
+    #[test]
+    fn test_treasury_expiry_matches_published_dates() {
+        assert_eq!(long_treasury_expiry(2024, Month::March), date!(2024 - 03 - 19));
+        assert_eq!(long_treasury_expiry(2023, Month::December), date!(2023 - 12 - 19));
+        // Juneteenth 2024
+        assert_eq!(long_treasury_expiry(2024, Month::June), date!(2024 - 06 - 18));
+        assert_eq!(short_treasury_expiry(2024, Month::March), date!(2024 - 03 - 28));
+        assert_eq!(short_treasury_expiry(2023, Month::September), date!(2023 - 09 - 29));
+    }
+
+    #[test]
+    fn test_grains_expiry_matches_published_dates() {
+        assert_eq!(grains_expiry(2024, Month::March), date!(2024 - 03 - 14));
+        assert_eq!(grains_expiry(2023, Month::December), date!(2023 - 12 - 14));
+        // The 15th of January 2024 is MLK day; the 14th a Sunday
+        assert_eq!(grains_expiry(2024, Month::January), date!(2024 - 01 - 12));
+    }
+
+    #[test]
+    fn test_fx_expiry_matches_published_dates() {
+        assert_eq!(fx_expiry(2024, Month::March), date!(2024 - 03 - 18));
+        assert_eq!(fx_expiry(2023, Month::December), date!(2023 - 12 - 18));
+        // Third Wednesday of June 2024 is Juneteenth
+        assert_eq!(fx_expiry(2024, Month::June), date!(2024 - 06 - 17));
+    }
+
+    #[test]
+    fn test_first_notice_is_last_business_day_of_prior_month() {
+        assert_eq!(
+            ExpiryRule::SeventhBusinessDayBeforeLast.first_notice(2024, Month::March),
+            Some(date!(2024 - 02 - 29))
+        );
+        assert_eq!(
+            ExpiryRule::BusinessDayBefore15th.first_notice(2024, Month::January),
+            Some(date!(2023 - 12 - 29))
+        );
+        assert_eq!(ExpiryRule::TwoDaysBeforeThirdWednesday.first_notice(2024, Month::March), None);
+    }
+
+    #[test]
+    fn test_treasury_windows_roll_before_first_notice() {
+        let periods = generate_contract_periods("ZN", date!(2024 - 01 - 01), date!(2024 - 12 - 31)).unwrap();
+        let symbols = periods.iter().map(|p| p.symbol.as_str()).collect::<Vec<_>>();
+        assert_eq!(symbols, vec!["ZNH4", "ZNM4", "ZNU4", "ZNZ4"]);
+
+        let march = &periods[0];
+        assert_eq!(march.first_notice, Some(date!(2024 - 02 - 29)));
+        assert_eq!(march.expiry, date!(2024 - 03 - 19));
+        assert_eq!(march.end, date!(2024 - 02 - 29));
+        assert_eq!(periods[1].start, date!(2024 - 02 - 19));
+    }
+
+    #[test]
+    fn test_grain_listed_months() {
+        let soybeans = generate_contract_periods("ZS", date!(2024 - 01 - 01), date!(2024 - 12 - 31)).unwrap();
+        let symbols = soybeans.iter().map(|p| p.symbol.as_str()).collect::<Vec<_>>();
+        // ZSF5 first notice is Dec 31, 2024
+        assert_eq!(symbols, vec!["ZSH4", "ZSK4", "ZSN4", "ZSQ4", "ZSU4", "ZSX4", "ZSF5"]);
+
+        let corn = generate_contract_periods("ZC", date!(2024 - 01 - 01), date!(2024 - 12 - 31)).unwrap();
+        let symbols = corn.iter().map(|p| p.symbol.as_str()).collect::<Vec<_>>();
+        assert_eq!(symbols, vec!["ZCH4", "ZCK4", "ZCN4", "ZCU4", "ZCZ4"]);
+    }
+
+    #[test]
+    fn test_new_products_generate_valid_windows() {
+        for root in ["GC", "SI", "HG", "PL", "ZN", "ZB", "ZF", "ZT", "ZC", "ZS", "ZW", "6E", "6J", "6B"] {
+            let periods = generate_contract_periods(root, date!(2020 - 01 - 01), date!(2025 - 12 - 31))
+                .unwrap_or_else(|e| panic!("{root}: {e}"));
+            assert!(!periods.is_empty(), "{root}: no periods");
+            for period in &periods {
+                assert!(period.start < period.end, "{period:?}");
+                assert!(period.end <= period.expiry, "{period:?}");
+                assert!(cme_calendar().is_business_day(period.expiry), "{period:?}");
+            }
+        }
+    }
+
     #[test]
     fn test_generate_contract_periods_energy() {
-        let periods = generate_contract_periods("NG", date!(2023 - 01 - 01), date!(2023 - 12 - 31));
+        let periods = generate_contract_periods("NG", date!(2023 - 01 - 01), date!(2023 - 12 - 31)).unwrap();
         assert!(periods.len() >= 11);
-        assert!(periods.iter().all(|(s, _, _)| s.starts_with("NG")));
+        assert!(periods.iter().all(|p| p.symbol.starts_with("NG") && p.root == "NG"));
     }
 
     #[test]
     fn test_ng_contract_end_is_expiry() {
-        let periods = generate_contract_periods("NG", date!(2025 - 01 - 01), date!(2025 - 12 - 31));
-        let (_, _, end) = periods
+        let periods = generate_contract_periods("NG", date!(2025 - 01 - 01), date!(2025 - 12 - 31)).unwrap();
+        let period = periods
             .iter()
-            .find(|(symbol, _, _)| symbol == "NGZ5")
+            .find(|p| p.symbol == "NGZ5")
             .expect("Expected NGZ5 contract");
 
-        assert_eq!(*end, calculate_expiration_date("NG", 2025, Month::December));
+        assert_eq!(period.end, calculate_expiration_date("NG", 2025, Month::December).unwrap());
+        assert_eq!(period.end, period.expiry);
+    }
+
+    #[test]
+    fn test_ng_periods_match_published_expiries() {
+        let periods = generate_contract_periods("NG", date!(2023 - 12 - 01), date!(2024 - 12 - 31)).unwrap();
+        let expiries = periods.iter().map(|p| (p.symbol.as_str(), p.expiry)).collect::<Vec<_>>();
+
+        assert_eq!(
+            expiries,
+            vec![
+                ("NGF4", date!(2023 - 12 - 27)),
+                ("NGG4", date!(2024 - 01 - 29)),
+                ("NGH4", date!(2024 - 02 - 27)),
+                ("NGJ4", date!(2024 - 03 - 26)),
+                ("NGK4", date!(2024 - 04 - 26)),
+                ("NGM4", date!(2024 - 05 - 29)),
+                ("NGN4", date!(2024 - 06 - 26)),
+                ("NGQ4", date!(2024 - 07 - 29)),
+                ("NGU4", date!(2024 - 08 - 28)),
+                ("NGV4", date!(2024 - 09 - 26)),
+                ("NGX4", date!(2024 - 10 - 29)),
+                ("NGZ4", date!(2024 - 11 - 26)),
+                ("NGF5", date!(2024 - 12 - 27)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_december_expiry_of_next_year_contract_is_included() {
+        // NGF4 and CLF4 expire in December 2023 and used to be dropped from 2023 requests
+        let ng = generate_contract_periods("NG", date!(2023 - 01 - 01), date!(2023 - 12 - 31)).unwrap();
+        assert_eq!(ng.last().map(|p| p.symbol.as_str()), Some("NGF4"));
+
+        let cl = generate_contract_periods("CL", date!(2023 - 01 - 01), date!(2023 - 12 - 31)).unwrap();
+        assert_eq!(cl.len(), 12);
+        assert_eq!(cl.last().map(|p| (p.symbol.as_str(), p.expiry)), Some(("CLF4", date!(2023 - 12 - 19))));
+    }
+
+    #[test]
+    fn test_ng_windows_are_valid() {
+        let periods = generate_contract_periods("NG", date!(2020 - 01 - 01), date!(2025 - 12 - 31)).unwrap();
+        for period in &periods {
+            assert!(period.start < period.end, "{period:?}");
+            assert!(period.start <= period.expiry && period.expiry <= period.end, "{period:?}");
+            assert!(cme_calendar().is_business_day(period.expiry), "{period:?}");
+        }
     }
 
     #[test]
     fn test_cl_contract_keeps_post_expiry_padding() {
-        let periods = generate_contract_periods("CL", date!(2025 - 01 - 01), date!(2025 - 12 - 31));
-        let (_, _, end) = periods
+        let periods = generate_contract_periods("CL", date!(2025 - 01 - 01), date!(2025 - 12 - 31)).unwrap();
+        let period = periods
             .iter()
-            .find(|(symbol, _, _)| symbol == "CLZ5")
+            .find(|p| p.symbol == "CLZ5")
             .expect("Expected CLZ5 contract");
 
         assert_eq!(
-            *end,
-            calculate_expiration_date("CL", 2025, Month::December) + Duration::days(3)
+            period.end,
+            calculate_expiration_date("CL", 2025, Month::December).unwrap() + Duration::days(3)
         );
     }
 
     #[test]
     fn test_generate_contract_periods_index() {
-        let periods = generate_contract_periods("ES", date!(2023 - 01 - 01), date!(2023 - 12 - 31));
+        let periods = generate_contract_periods("ES", date!(2023 - 01 - 01), date!(2023 - 12 - 31)).unwrap();
         assert_eq!(periods.len(), 4);
-        assert!(periods.iter().all(|(s, _, _)| s.starts_with("ES")));
+        assert!(periods.iter().all(|p| p.symbol.starts_with("ES")));
+    }
+
+    #[test]
+    fn test_raw_symbol_resolves_relative_to_query_date() {
+        assert_eq!(raw_contract_symbol("CL", Month::December, 2015, date!(2015 - 11 - 01)), "CLZ5");
+        assert_eq!(raw_contract_symbol("CL", Month::December, 2025, date!(2025 - 11 - 01)), "CLZ5");
+        assert_eq!(raw_contract_symbol("NG", Month::January, 2035, date!(2024 - 06 - 01)), "NGF35");
+        assert_eq!(full_contract_symbol("CL", Month::December, 2015), "CLZ2015");
+    }
+
+    #[test]
+    fn test_multi_decade_request_keeps_contract_years_apart() {
+        let periods = generate_contract_periods("CL", date!(2015 - 01 - 01), date!(2025 - 12 - 31)).unwrap();
+        let z5 = periods.iter().filter(|p| p.symbol == "CLZ5").collect::<Vec<_>>();
+
+        assert_eq!(z5.len(), 2);
+        assert_eq!(z5[0].year, 2015);
+        assert_eq!(z5[1].year, 2025);
+        assert_eq!(z5[0].contract_name(), "CLZ2015");
+        assert_eq!(z5[1].contract_name(), "CLZ2025");
+
+        let mut names = periods.iter().map(ContractPeriod::contract_name).collect::<Vec<_>>();
+        let count = names.len();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn test_january_contract_year_is_delivery_year() {
+        let periods = generate_contract_periods("CL", date!(2023 - 12 - 01), date!(2023 - 12 - 31)).unwrap();
+        assert_eq!(periods[0].symbol, "CLF4");
+        assert_eq!(periods[0].year, 2024);
+        assert_eq!(periods[0].contract_name(), "CLF2024");
     }
 
     #[test]
-    #[should_panic(expected = "Unsupported symbol")]
     fn test_generate_contract_periods_unsupported_symbol() {
-        generate_contract_periods("XYZ", date!(2023 - 01 - 01), date!(2023 - 12 - 31));
+        let result = generate_contract_periods("XYZ", date!(2023 - 01 - 01), date!(2023 - 12 - 31));
+        assert_eq!(result, Err(ContractError::UnsupportedSymbol { symbol: "XYZ".to_string() }));
+        assert!(result.unwrap_err().to_string().contains("Unsupported symbol"));
+    }
+
+    #[test]
+    fn test_generate_contract_periods_rejects_reversed_range() {
+        let result = generate_contract_periods("CL", date!(2024 - 01 - 01), date!(2023 - 01 - 01));
+        assert!(matches!(result, Err(ContractError::InvalidDateRange { .. })));
+    }
+
+    #[test]
+    fn test_validate_period_explains_invalid_windows() {
+        let mut period = ContractPeriod {
+            symbol: "NGZ4".to_string(),
+            root: "NG".to_string(),
+            month: Month::December,
+            year: 2024,
+            expiry: date!(2024 - 11 - 26),
+            first_notice: None,
+            roll: date!(2024 - 11 - 26),
+            start: date!(2024 - 11 - 26),
+            end: date!(2024 - 11 - 26),
+        };
+        assert!(matches!(validate_period(&period), Err(ContractError::EmptyWindow { .. })));
+
+        period.start = date!(2024 - 11 - 27);
+        period.end = date!(2024 - 11 - 30);
+        assert!(matches!(
+            validate_period(&period),
+            Err(ContractError::WindowStartsAfterExpiry { .. })
+        ));
+    }
+
+    #[test]
+    fn test_micro_periods_share_parent_windows() {
+        let es = generate_contract_periods("ES", date!(2024 - 01 - 01), date!(2024 - 12 - 31)).unwrap();
+        let mes = sibling_contract_periods(&es, "MES").unwrap();
+
+        assert_eq!(mes.len(), es.len());
+        for (micro, parent) in mes.iter().zip(&es) {
+            assert_eq!(micro.root, "MES");
+            assert_eq!((micro.start, micro.end), (parent.start, parent.end));
+            assert_eq!(micro.expiry, parent.expiry);
+            assert_eq!(micro.symbol, format!("M{}", parent.symbol));
+        }
+    }
+
+    #[test]
+    fn test_mcl_expires_one_business_day_before_cl() {
+        // CLF5 last traded 2024-12-19, MCLF5 on 2024-12-18
+        assert_eq!(calculate_expiration_date("CL", 2025, Month::January).unwrap(), date!(2024 - 12 - 19));
+        assert_eq!(calculate_expiration_date("MCL", 2025, Month::January).unwrap(), date!(2024 - 12 - 18));
+
+        let cl = generate_contract_periods("CL", date!(2024 - 11 - 01), date!(2024 - 12 - 31)).unwrap();
+        let mcl = sibling_contract_periods(&cl, "MCL").unwrap();
+        assert_eq!(mcl.last().unwrap().contract_name(), "MCLF2025");
+        assert_eq!(mcl.last().unwrap().end, cl.last().unwrap().end);
+    }
+
+    #[test]
+    fn test_roll_policy_override_changes_windows() {
+        let start = date!(2024 - 01 - 01);
+        let end = date!(2024 - 12 - 31);
+        let default = generate_contract_periods("CL", start, end).unwrap();
+
+        let policy = RollPolicy::new(RollAnchor::LastTrade, ContractWindow::BeforeExpiry { days_before: 20, days_after: 0 });
+        let custom = generate_contract_periods_with_policy("CL", start, end, &policy).unwrap();
+
+        assert_eq!(custom.len(), default.len());
+        for period in &custom {
+            assert_eq!(period.end, period.expiry);
+            assert_eq!(period.start, period.expiry - Duration::days(20));
+        }
+    }
+
+    #[test]
+    fn test_product_periods_use_overrides_only_for_named_products() {
+        let start = date!(2024 - 01 - 01);
+        let end = date!(2024 - 12 - 31);
+        let overlap = RollPolicy::new(RollAnchor::LastTrade, ContractWindow::Overlap { overlap_days: 5, first_span_days: 30 });
+        let options = PeriodOptions {
+            include_siblings: false,
+            roll_overrides: HashMap::from([("ES".to_string(), overlap)]),
+            ..Default::default()
+        };
+
+        let periods = generate_product_periods(&["CL", "ES"], start, end, &options).unwrap();
+        let cl = periods.iter().filter(|p| p.root == "CL").cloned().collect::<Vec<_>>();
+        let es = periods.iter().filter(|p| p.root == "ES").collect::<Vec<_>>();
+
+        assert_eq!(cl, generate_contract_periods("CL", start, end).unwrap());
+        assert_eq!(es[0].start, es[0].expiry - Duration::days(30));
+        assert_eq!(es[1].start, es[0].expiry - Duration::days(5));
+    }
+
+    #[test]
+    fn test_first_notice_anchor_moves_roll_before_delivery() {
+        let start = date!(2024 - 01 - 01);
+        let end = date!(2024 - 12 - 31);
+        let base = product_registry().unwrap().get("ZN").unwrap().roll;
+        let last_trade = base.with_override("@last_trade").unwrap();
+
+        let on_notice = generate_contract_periods_with_policy("ZN", start, end, &base).unwrap();
+        let on_expiry = generate_contract_periods_with_policy("ZN", start, end, &last_trade).unwrap();
+
+        assert!(on_notice.iter().all(|p| Some(p.end) == p.first_notice));
+        assert!(on_expiry.iter().all(|p| p.end == p.expiry));
+    }
+
+    #[test]
+    fn test_parse_full_contract_symbol() {
+        assert_eq!(parse_full_contract_symbol("CLZ2025"), Some(("CL", Month::December, 2025)));
+        assert_eq!(parse_full_contract_symbol("M2KH2024"), Some(("M2K", Month::March, 2024)));
+        assert_eq!(parse_full_contract_symbol("CLZ5"), None);
+        assert_eq!(parse_full_contract_symbol("Z2025"), None);
     }
 
     #[test]
     fn test_es_contract_debug() {
-        let periods = generate_contract_periods("ES", date!(2023 - 01 - 01), date!(2023 - 12 - 31));
-        for (sym, start, end) in periods {
-            println!("{}: {} to {}", sym, start, end);
+        let periods = generate_contract_periods("ES", date!(2023 - 01 - 01), date!(2023 - 12 - 31)).unwrap();
+        for period in periods {
+            println!("{}: {} to {}", period.symbol, period.start, period.end);
         }
     }
 }
@@ -1,93 +1,467 @@
+use anyhow::Context;
 use async_compression::tokio::bufread::ZstdDecoder;
-use databento::dbn::{decode::AsyncDbnDecoder, OhlcvMsg};
+use databento::dbn::{
+    decode::{AsyncDbnDecoder, DbnDecoder, DbnMetadata, DecodeRecordRef},
+    Schema,
+};
 use std::{
-    fs::{self, File},
-    io::{BufWriter, Write},
+    fmt, fs,
     path::{Path, PathBuf},
     pin::Pin,
+    sync::Arc,
 };
 use tokio::{
     fs::File as TokioFile,
     io::{AsyncRead, BufReader as AsyncBufReader},
+    sync::{Mutex, Semaphore},
 };
+use tokio_util::sync::CancellationToken;
 
-use crate::types::JsonOhlcv;
+use crate::downloader::output::{DecodedFile, OutputFormat};
+use crate::downloader::symbology::InstrumentSymbols;
+use crate::processor::ContractFile;
+use crate::progress::{ProgressEvent, ProgressSink};
+use crate::storage::{describe_decoded, Catalog, FileDetails, FileEncoding};
 
 const DBN_EXT: &str = ".dbn.zst";
-const JSON_EXT: &str = "_ohlcv1m.json";
 
-/// Recursively decode all `.dbn.zst` files in a directory tree to JSON files.
-pub async fn decode_all_in_dir(root_dir: &str) -> databento::Result<()> {
+/// Raw files decoded at once when no limit is given: one per core.
+pub fn default_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(4, |cores| cores.get())
+}
+
+/// A raw file decoded to the files of `outputs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeOutcome {
+    pub path: PathBuf,
+    pub schema: Schema,
+    pub outputs: Vec<DecodedFile>,
+}
+
+/// A raw file that could not be decoded or whose output could not be catalogued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Result of a `decode_all_in_dir` run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodeSummary {
+    pub decoded: Vec<DecodeOutcome>,
+    pub failed: Vec<DecodeFailure>,
+    /// Files stopped or never started because the run was cancelled.
+    pub cancelled: usize,
+}
+
+impl DecodeSummary {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.cancelled == 0
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled > 0
+    }
+}
+
+impl fmt::Display for DecodeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} decoded, {} failed", self.decoded.len(), self.failed.len())?;
+        if self.cancelled > 0 {
+            write!(f, ", {} cancelled", self.cancelled)?;
+        }
+        for failure in &self.failed {
+            write!(f, "\n  failed:  {}: {}", failure.path.display(), failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Recursively decode all `.dbn.zst` files in a directory tree, whatever their schema, to `format` and record the
+/// decoded files in the catalog of `root_dir`. Up to `concurrency` files are decoded at once. Each file is
+/// reported to `progress`, by its path under `root_dir`, as it is queued, started, finished, failed or cancelled.
+/// Cancelling `cancel` stops every file before its next record and removes its half-written output. Failed files
+/// do not stop the others; the returned summary lists what was decoded, what failed and how many were cancelled.
+pub async fn decode_all_in_dir(
+    root_dir: &str,
+    format: Arc<dyn OutputFormat>,
+    concurrency: usize,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
+) -> anyhow::Result<DecodeSummary> {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let catalog = Arc::new(Mutex::new(Catalog::open(root_dir)?));
+    let files = find_dbn_files(root_dir)?;
+
+    let handles = files
+        .into_iter()
+        .map(|path| {
+            let semaphore = Arc::clone(&semaphore);
+            let catalog = Arc::clone(&catalog);
+            let format = Arc::clone(&format);
+            let progress = Arc::clone(&progress);
+            let cancel = cancel.clone();
+            let task = path.strip_prefix(root_dir).unwrap_or(&path).display().to_string();
+            progress.emit(ProgressEvent::Queued { task: task.clone() });
+            let handle_path = path.clone();
+            let handle = tokio::spawn(async move {
+                let _permit = tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => {
+                        progress.emit(ProgressEvent::Cancelled { task });
+                        return Ok(None);
+                    }
+                    permit = semaphore.acquire() => permit?,
+                };
+                progress.emit(ProgressEvent::Started { task: task.clone() });
+                // Encoding is CPU-bound, so it runs on the blocking pool and leaves the runtime's workers free
+                let decode = {
+                    let (path, format, cancel) = (path.clone(), Arc::clone(&format), cancel.clone());
+                    tokio::task::spawn_blocking(move || decode_file(&path, format.as_ref(), &cancel))
+                };
+                let result = match decode.await? {
+                    Ok(Some((raw, schema, outputs))) => {
+                        catalog_outputs(&mut *catalog.lock().await, &path, &raw, schema, format.encoding(), &outputs)
+                            .map(|()| Some(DecodeOutcome { path: path.clone(), schema, outputs }))
+                    }
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e),
+                };
+                match &result {
+                    Ok(Some(_)) => progress.emit(ProgressEvent::Finished { task, cost_usd: None, status: None }),
+                    Ok(None) => progress.emit(ProgressEvent::Cancelled { task }),
+                    Err(e) => progress.emit(ProgressEvent::Failed { task, error: format!("{e:#}") }),
+                }
+                result
+            });
+            (handle_path, handle)
+        })
+        .collect::<Vec<_>>();
+
+    // Every file runs to completion or cancellation; failures are collected instead of aborting the others
+    let mut summary = DecodeSummary::default();
+    for (path, handle) in handles {
+        match handle.await.map_err(anyhow::Error::from).and_then(|result| result) {
+            Ok(Some(outcome)) => summary.decoded.push(outcome),
+            Ok(None) => summary.cancelled += 1,
+            Err(e) => summary.failed.push(DecodeFailure { path, error: format!("{e:#}") }),
+        }
+    }
+
+    catalog.lock().await.save()?;
+    Ok(summary)
+}
+
+/// Records the files decoded from the raw file at `path`. They inherit the dataset and symbol of the raw download.
+fn catalog_outputs(
+    catalog: &mut Catalog,
+    path: &Path,
+    raw: &ContractFile,
+    schema: Schema,
+    encoding: FileEncoding,
+    outputs: &[DecodedFile],
+) -> anyhow::Result<()> {
+    let relative = path.strip_prefix(catalog.base()).unwrap_or(path);
+    let details = catalog
+        .entries()
+        .iter()
+        .find(|entry| entry.path == relative)
+        .map(|entry| FileDetails { dataset: Some(entry.dataset.clone()), symbol: entry.symbol.clone(), cost_usd: None })
+        .unwrap_or_default();
+    for output in outputs {
+        let entry = describe_decoded(catalog.base(), raw, schema, encoding, output, details.clone())
+            .with_context(|| format!("Failed to catalog {}", output.path.display()))?;
+        catalog.upsert(entry);
+    }
+    Ok(())
+}
+
+/// Decodes the raw file at `path` into a sink of `format`. Returns the raw file, its schema and the files
+/// written, or `None` when `cancel` fired first, in which case nothing is left behind. Blocks until done.
+fn decode_file(
+    path: &Path,
+    format: &dyn OutputFormat,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Option<(ContractFile, Schema, Vec<DecodedFile>)>> {
+    let raw = ContractFile::from_path(path).with_context(|| format!("{} is not a downloaded contract file", path.display()))?;
+    let mut decoder = DbnDecoder::from_zstd_file(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let schema = decoder.metadata().schema.with_context(|| format!("{} mixes several schemas", path.display()))?;
+    // Runs on the blocking pool, where waiting on a symbology resolution does not stall the runtime
+    let symbols = tokio::runtime::Handle::current()
+        .block_on(InstrumentSymbols::load(decoder.metadata()))
+        .with_context(|| format!("Failed to map the instruments of {}", path.display()))?;
+    let mut sink = format.create(&raw, schema)?;
+
+    loop {
+        if cancel.is_cancelled() {
+            sink.abort();
+            return Ok(None);
+        }
+        let record = match decoder.decode_record_ref() {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) => {
+                sink.abort();
+                return Err(e.into());
+            }
+        };
+        if let Err(e) = symbols.symbol(&record).and_then(|symbol| sink.write(record, symbol)) {
+            sink.abort();
+            return Err(e);
+        }
+    }
+    Ok(Some((raw, schema, sink.finish()?)))
+}
+
+/// Every `.dbn.zst` file under `root_dir`.
+fn find_dbn_files(root_dir: &str) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
     let mut stack = vec![PathBuf::from(root_dir)];
 
     while let Some(dir) = stack.pop() {
         for entry in fs::read_dir(&dir)? {
-            let entry = entry?;
-            let path = entry.path();
+            let path = entry?.path();
 
             if path.is_dir() {
                 stack.push(path);
             } else if path.extension().and_then(|e| e.to_str()) == Some("zst")
-                && path.file_name().and_then(|f| f.to_str()).map_or(false, |f| f.ends_with(DBN_EXT))
+                && path.file_name().and_then(|f| f.to_str()).is_some_and(|f| f.ends_with(DBN_EXT))
             {
-                let base_path = path.with_extension(""); // removes only `.zst`
-                let base_path = base_path
-                    .to_str()
-                    .unwrap()
-                    .strip_suffix(".dbn")
-                    .unwrap()
-                    .to_string();
-
-                if let Err(e) = stream_decode_and_write(&base_path).await {
-                    eprintln!("Error decoding file {}: {:?}", path.display(), e);
-                }
+                files.push(path);
             }
         }
     }
 
-    Ok(())
+    Ok(files)
 }
 
-/// Decode a single `.dbn.zst` file to `<base>_ohlcv1m.json`.
-pub async fn stream_decode_and_write(base_path: &str) -> databento::Result<()> {
-    let input_path = format!("{base_path}{DBN_EXT}");
-    let output_path = format!("{base_path}{JSON_EXT}");
-
-    // Get filename without path and extension as instrument name
-    let symbol = Path::new(base_path)
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+pub(crate) type DbnReader = Pin<Box<dyn AsyncRead + Send>>;
 
-    let file = TokioFile::open(&input_path).await?;
+/// Opens a `.dbn.zst` file for decoding, along with the schema its metadata declares.
+pub(crate) async fn open_dbn(input_path: &str) -> anyhow::Result<(AsyncDbnDecoder<DbnReader>, Schema)> {
+    let file = TokioFile::open(input_path).await?;
     let buf_reader = AsyncBufReader::new(file);
     let zstd_decoder = ZstdDecoder::new(buf_reader);
-    let pinned_reader = Box::pin(zstd_decoder) as Pin<Box<dyn AsyncRead + Send>>;
+    let pinned_reader = Box::pin(zstd_decoder) as DbnReader;
 
-    let mut decoder = AsyncDbnDecoder::new(pinned_reader).await?;
+    let decoder = AsyncDbnDecoder::new(pinned_reader).await?;
+    let schema = decoder.metadata().schema.with_context(|| format!("{input_path} mixes several schemas"))?;
+    Ok((decoder, schema))
+}
 
-    let out_file = File::create(&output_path)?;
-    let mut writer = BufWriter::new(out_file);
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::ipc_format::IpcFormat;
+    use crate::downloader::output::NdjsonFormat;
+    use crate::downloader::parquet_format::{ParquetFormat, ParquetOptions};
+    use crate::downloader::sessions::ExchangeTimezone;
+    use crate::downloader::text_format::{CsvFormat, CsvOptions, DisplayTimezone, PrettyJsonFormat};
+    use crate::progress::NoProgress;
+    use arrow::{
+        array::{Array, Float64Array, RecordBatch, StringArray, TimestampNanosecondArray},
+        compute::concat_batches,
+        ipc::reader::FileReader,
+    };
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use crate::downloader::fixtures::{raw_symbol_mapping, write_dbn_with_mappings};
+    use std::fs::File;
+    use time::macros::date;
+    use databento::dbn::{rtype, RecordHeader, TradeMsg};
 
-    println!("Decoding {input_path} → {output_path}");
+    /// Two trades of instrument 7 on 2024-01-05 and one on 2024-01-08, UTC.
+    fn trades() -> Vec<TradeMsg> {
+        [1_704_448_800_000_000_000, 1_704_448_860_000_000_000, 1_704_708_000_000_000_000]
+            .into_iter()
+            .map(|ts_event| TradeMsg {
+                hd: RecordHeader::new::<TradeMsg>(rtype::MBP_0, 1, 7, ts_event),
+                price: 4_800_250_000_000,
+                size: 2,
+                ..Default::default()
+            })
+            .collect()
+    }
 
-    while let Some(msg) = decoder.decode_record::<OhlcvMsg>().await? {
-        let record = JsonOhlcv {
-            instrument_name: symbol.clone(),
-            instrument_id: msg.hd.instrument_id,
-            ts_event: msg.hd.ts_event,
-            open: msg.open,
-            high: msg.high,
-            low: msg.low,
-            close: msg.close,
-            volume: msg.volume,
-        };
+    fn write_trades(dir: &str) {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{dir}/ES/trades")).unwrap();
+        let path = format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024.dbn.zst");
+        let mappings = vec![raw_symbol_mapping("ESH4", 7, date!(2024 - 01 - 05), date!(2024 - 03 - 15))];
+        write_dbn_with_mappings(&path, Schema::Trades, mappings, &trades());
+    }
+
+    #[tokio::test]
+    async fn test_decode_dispatches_on_schema() {
+        let dir = "test_output_decode_schemas";
+        write_trades(dir);
+
+        decode_all_in_dir(dir, Arc::new(NdjsonFormat), 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
 
-        serde_json::to_writer(&mut writer, &record).expect("Could not decode");
-        writeln!(&mut writer)?;
+        let decoded = fs::read_to_string(format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024_trades.json")).unwrap();
+        let lines = decoded.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(r#""price":"4800250000000""#) && lines[0].contains(r#""instrument_id":7"#));
+        // Records are labelled with the raw symbol the symbology mappings give their instrument
+        assert!(lines.iter().all(|line| line.contains(r#""symbol":"ESH4""#)));
+
+        let catalog = Catalog::open(dir).unwrap();
+        let entry = catalog.entries().iter().find(|entry| entry.path.ends_with("2024-01-05_2024-03-15_ESH2024_trades.json")).unwrap();
+        assert_eq!((entry.schema.as_str(), entry.record_count, entry.last_ts), ("trades", 3, Some(1_704_708_000_000_000_000)));
+
+        // A record whose instrument is unmapped on its day fails the file instead of borrowing the file's contract
+        let path = format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESM2024.dbn.zst");
+        let mappings = vec![raw_symbol_mapping("ESM4", 7, date!(2024 - 01 - 05), date!(2024 - 01 - 06))];
+        write_dbn_with_mappings(&path, Schema::Trades, mappings, &trades());
+
+        let format = Arc::new(NdjsonFormat);
+        let summary = decode_all_in_dir(dir, format, 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
+        assert_eq!(summary.failed.len(), 1);
+        assert!(summary.failed[0].path.ends_with("2024-01-05_2024-03-15_ESM2024.dbn.zst"));
+        assert_eq!(summary.failed[0].error, "Instrument 7 has no symbol mapping on 2024-01-08");
+        assert!(!Path::new(&format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESM2024_trades.json")).exists());
+
+        fs::remove_dir_all(dir).unwrap();
     }
 
-    Ok(())
+    fn read_parquet(path: impl AsRef<Path>) -> RecordBatch {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        concat_batches(&batches[0].schema(), &batches).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_decode_to_parquet() {
+        let dir = "test_output_decode_parquet";
+        write_trades(dir);
+
+        let format = ParquetFormat::new(ParquetOptions::default());
+        decode_all_in_dir(dir, Arc::new(format), 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
+
+        let batch = read_parquet(format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024_trades.parquet"));
+        assert_eq!(batch.num_rows(), 3);
+        let contracts = batch.column_by_name("contract").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(contracts.value(2), "ESH2024");
+        let prices = batch.column_by_name("price").unwrap().as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(prices.value(0), 4800.25);
+        let ts = batch.column_by_name("ts_event").unwrap().as_any().downcast_ref::<TimestampNanosecondArray>().unwrap();
+        assert_eq!(ts.value(0), 1_704_448_800_000_000_000);
+
+        let catalog = Catalog::open(dir).unwrap();
+        let entry = catalog.entries().iter().find(|entry| entry.path.ends_with("2024-01-05_2024-03-15_ESH2024_trades.parquet")).unwrap();
+        assert_eq!((entry.encoding, entry.record_count), (FileEncoding::Parquet, 3));
+
+        // Partitioned output holds one file per UTC day, catalogued with that day as its window
+        // A raw file of the same contract with an overlapping window writes its own partition files
+        fs::copy(
+            format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024.dbn.zst"),
+            format!("{dir}/ES/trades/2024-01-01_2024-03-15_ESH2024.dbn.zst"),
+        )
+        .unwrap();
+        let parquet_dir = PathBuf::from(dir).join("parquet");
+        let format = ParquetFormat::new(ParquetOptions { partition_dir: Some(parquet_dir.clone()), ..Default::default() });
+        decode_all_in_dir(dir, Arc::new(format), 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
+
+        let partition = parquet_dir.join("trades/root=ES/contract=ESH2024");
+        for window in ["2024-01-05_2024-03-15", "2024-01-01_2024-03-15"] {
+            assert_eq!(read_parquet(partition.join(format!("date=2024-01-05/part-{window}.parquet"))).num_rows(), 2);
+        }
+        let batch = read_parquet(partition.join("date=2024-01-08/part-2024-01-05_2024-03-15.parquet"));
+        assert_eq!(batch.num_rows(), 1);
+        assert!(batch.column_by_name("contract").is_none());
+
+        let catalog = Catalog::open(dir).unwrap();
+        let entry = catalog
+            .entries()
+            .iter()
+            .find(|entry| entry.path.ends_with("date=2024-01-08/part-2024-01-05_2024-03-15.parquet"))
+            .unwrap();
+        assert_eq!((entry.start, entry.end, entry.record_count), (date!(2024 - 01 - 08), date!(2024 - 01 - 08), 1));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_decode_to_csv_and_pretty_json() {
+        let dir = "test_output_decode_text";
+        write_trades(dir);
+        let chicago = DisplayTimezone::Exchange(ExchangeTimezone::Chicago);
+
+        let columns = ["ts_event", "contract", "price", "side"].map(String::from).to_vec();
+        let format = CsvFormat::new(CsvOptions { columns, ..Default::default() }, chicago);
+        decode_all_in_dir(dir, Arc::new(format), 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
+
+        let csv = fs::read_to_string(format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024_trades.csv")).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "ts_event,contract,price,side");
+        assert_eq!(lines[1], "2024-01-05T04:00:00-06:00,ESH2024,4800.25,N");
+        assert_eq!(lines.len(), 4);
+
+        let format = PrettyJsonFormat::new(DisplayTimezone::Utc);
+        decode_all_in_dir(dir, Arc::new(format), 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
+
+        let json = fs::read_to_string(format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024_trades_pretty.json")).unwrap();
+        let first = serde_json::from_str::<serde_json::Value>(json.lines().next().unwrap()).unwrap();
+        assert_eq!(first["ts_event"], "2024-01-05T10:00:00Z");
+        assert_eq!(first["price"], 4800.25);
+        assert_eq!(first["size"], 2);
+        assert_eq!(first["symbol"], "ESH4");
+        assert!(first["ts_recv"].is_null());
+
+        // Unknown columns are refused before anything is written
+        let format = CsvFormat::new(CsvOptions { columns: vec!["bid".to_string()], ..Default::default() }, chicago);
+        fs::remove_file(format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024_trades.csv")).unwrap();
+        let summary = decode_all_in_dir(dir, Arc::new(format), 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
+        assert!(summary.failed[0].error.starts_with("Unknown column 'bid'"));
+        assert!(!Path::new(&format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024_trades.csv")).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_decode_to_arrow_ipc() {
+        let dir = "test_output_decode_ipc";
+        write_trades(dir);
+
+        decode_all_in_dir(dir, Arc::new(IpcFormat), 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
+
+        let path = format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024_trades.arrow");
+        let reader = FileReader::try_new(File::open(&path).unwrap(), None).unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        let batch = concat_batches(&batches[0].schema(), &batches).unwrap();
+        assert_eq!(batch.num_rows(), 3);
+        let prices = batch.column_by_name("price").unwrap().as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(prices.value(2), 4800.25);
+
+        let catalog = Catalog::open(dir).unwrap();
+        let entry = catalog.entries().iter().find(|entry| entry.path.ends_with("2024-01-05_2024-03-15_ESH2024_trades.arrow")).unwrap();
+        assert_eq!((entry.encoding, entry.record_count), (FileEncoding::Arrow, 3));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_decode_collects_failures() {
+        let dir = "test_output_decode_parallel";
+        write_trades(dir);
+        for contract in ["ESM2024", "ESU2024", "ESZ2024"] {
+            let path = format!("{dir}/ES/trades/2024-01-05_2024-03-15_{contract}.dbn.zst");
+            fs::copy(format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024.dbn.zst"), path).unwrap();
+        }
+        fs::write(format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2025.dbn.zst"), b"not dbn").unwrap();
+
+        let summary = decode_all_in_dir(dir, Arc::new(NdjsonFormat), 2, Arc::new(NoProgress), CancellationToken::new()).await.unwrap();
+        assert_eq!((summary.decoded.len(), summary.failed.len(), summary.cancelled), (4, 1, 0));
+        assert!(summary.failed[0].path.ends_with("2024-01-05_2024-03-15_ESH2025.dbn.zst"));
+        assert!(summary.decoded.iter().all(|outcome| outcome.schema == Schema::Trades && outcome.outputs[0].record_count == 3));
+        assert!(summary.to_string().starts_with("4 decoded, 1 failed\n  failed:  "));
+        assert_eq!(Catalog::open(dir).unwrap().entries().len(), 4);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let summary = decode_all_in_dir(dir, Arc::new(NdjsonFormat), 2, Arc::new(NoProgress), cancel).await.unwrap();
+        assert_eq!((summary.decoded.len(), summary.cancelled), (0, 5));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1,30 +1,148 @@
-use databento::dbn::Schema;
 use databento::historical::timeseries::GetRangeToFileParams;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use time::Date;
+use crate::progress::{ProgressEvent, ProgressSink};
 use crate::types::DownloadTask;
 use crate::downloader::range::download_time_range;
+use crate::downloader::resume::{part_path, rejected_path, validate_download, InvalidDownload};
+use crate::downloader::retry::{classify, ErrorClass, FetchError, RetryPolicy};
 
 // In normal builds, use the real downloader, uncomment below this line
 
+/// How often the size of a running download is reported.
+const BYTES_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
-pub async fn download_data(mut task: DownloadTask) -> databento::Result<()> {
-    let path = format!("{}/{}_{}_{}.dbn.zst", task.base_path, task.start, task.end, task.symbol);
-    let (range_start, range_end) = download_time_range(task.start, task.end);
+/// File a contract window is stored in, `{base}/{start}_{end}_{contract}.dbn.zst`.
+pub fn download_path(task: &DownloadTask, start: Date, end: Date) -> PathBuf {
+    PathBuf::from(format!("{}/{}_{}_{}.dbn.zst", task.base_path, start, end, task.contract))
+}
+
+/// A validated `.part` file and how many tries it took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchedRange {
+    pub path: PathBuf,
+    pub attempts: u32,
+    /// Attempts whose response delivered data, including discarded ones that did not decode.
+    pub billed: u32,
+}
+
+/// Downloads `start..=end` of the task's contract to a `.part` file and validates it, retrying transient
+/// failures (and downloads that do not decode) with backoff. The caller renames or merges the returned
+/// file; nothing under the final name exists until then. A download whose records are invalid was billed
+/// and would come back the same, so it is kept as `.rejected` and not retried.
+/// Bytes received and retries are reported to `progress`.
+pub async fn download_range(
+    task: &mut DownloadTask,
+    start: Date,
+    end: Date,
+    policy: &RetryPolicy,
+    progress: &dyn ProgressSink,
+) -> Result<FetchedRange, FetchError> {
+    let part = part_path(download_path(task, start, end));
+    let contract = task.contract.clone();
+    let mut attempt = 0;
+    let mut billed = 0;
+
+    loop {
+        attempt += 1;
+        let fetched = fetch_reporting_bytes(task, start, end, &part, progress).await;
+        // Delivered data is billed even when the transfer is cut off or the file is rejected
+        if fs::metadata(&part).is_ok_and(|metadata| metadata.len() > 0) {
+            billed += 1;
+        }
+        let (class, delay, error) = match fetched {
+            Ok(()) => match validate_download(&part, start, end) {
+                Ok(_) => return Ok(FetchedRange { path: part, attempts: attempt, billed }),
+                Err(e @ InvalidDownload::Records(_)) => {
+                    let source = anyhow::Error::from(e).context(format!("{} {start} to {end}", task.contract));
+                    let rejected = rejected_path(download_path(task, start, end));
+                    let error =
+                        FetchError { class: ErrorClass::Permanent, attempts: attempt, source, billed, rejected: None };
+                    return Err(reject(&part, rejected, error));
+                }
+                Err(e) => (e.class(), policy.backoff(attempt), anyhow::Error::from(e)),
+            },
+            Err(e) => (classify(&e), policy.delay_for(attempt, &e), anyhow::Error::from(e)),
+        };
+
+        // Never leave a failed attempt behind
+        let _ = fs::remove_file(&part);
+
+        if class == ErrorClass::Permanent || attempt >= policy.max_attempts {
+            let source = error.context(format!("{} {start} to {end}", task.contract));
+            return Err(FetchError { class, attempts: attempt, source, billed, rejected: None });
+        }
+        progress.emit(ProgressEvent::Retrying { task: contract.clone(), attempt, delay, error: format!("{error:#}") });
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Moves a download with invalid records from `part` to `rejected` so it can be inspected.
+fn reject(part: &Path, rejected: PathBuf, error: FetchError) -> FetchError {
+    match fs::rename(part, &rejected) {
+        Ok(()) => FetchError {
+            source: error.source.context(format!("Kept as {}", rejected.display())),
+            rejected: Some(rejected),
+            ..error
+        },
+        Err(e) => FetchError {
+            source: error.source.context(format!("Failed to keep the download as {}: {e}", rejected.display())),
+            ..error
+        },
+    }
+}
+
+/// `fetch_to_file`, reporting the size of the `.part` file while the response is written. The client
+/// streams straight to disk without a callback, so the file is polled.
+async fn fetch_reporting_bytes(
+    task: &mut DownloadTask,
+    start: Date,
+    end: Date,
+    path: &Path,
+    progress: &dyn ProgressSink,
+) -> databento::Result<()> {
+    let contract = task.contract.clone();
+    let report = || async {
+        if let Ok(metadata) = tokio::fs::metadata(path).await {
+            progress.emit(ProgressEvent::BytesReceived { task: contract.clone(), bytes: metadata.len() });
+        }
+    };
+
+    let fetch = fetch_to_file(task, start, end, path);
+    tokio::pin!(fetch);
+    let mut poll = tokio::time::interval(BYTES_POLL_INTERVAL);
+    let result = loop {
+        tokio::select! {
+            result = &mut fetch => break result,
+            _ = poll.tick() => report().await,
+        }
+    };
+    if result.is_ok() {
+        report().await;
+    }
+    result
+}
+
+async fn fetch_to_file(task: &mut DownloadTask, start: Date, end: Date, path: &Path) -> databento::Result<()> {
+    let (range_start, range_end) = download_time_range(start, end);
 
     task.client
         .get_mut()
         .timeseries()
         .get_range_to_file(
             &GetRangeToFileParams::builder()
-                .dataset("GLBX.MDP3")
+                .dataset(task.dataset.clone())
                 .date_time_range((range_start, range_end))
                 .symbols(task.symbol.clone())
-                .schema(Schema::Ohlcv1M)
+                .schema(task.schema)
                 .path(path)
                 .build(),
         )
         .await?;
-
-    println!("Finished downloading {} for period {} to {}", task.symbol, task.start, task.end);
     Ok(())
 }
 
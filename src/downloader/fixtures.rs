@@ -0,0 +1,43 @@
+//! DBN files written by the tests of several downloader modules.
+
+use databento::dbn::{
+    encode::{DbnEncodable, DbnEncoder, EncodeRecord},
+    MappingInterval, Metadata, SType, Schema, SymbolMapping,
+};
+use std::{fs::File, io::BufWriter};
+use time::Date;
+
+/// Writes `records` to a zstd-compressed GLBX.MDP3 file keyed by instrument id.
+pub fn write_dbn<R: DbnEncodable>(path: &str, schema: Schema, records: &[R]) {
+    write_dbn_with_mappings(path, schema, Vec::new(), records);
+}
+
+/// Mapping of `raw_symbol` to `instrument_id` from `start_date` up to, not including, `end_date`.
+pub fn raw_symbol_mapping(raw_symbol: &str, instrument_id: u32, start_date: Date, end_date: Date) -> SymbolMapping {
+    SymbolMapping {
+        raw_symbol: raw_symbol.to_string(),
+        intervals: vec![MappingInterval { start_date, end_date, symbol: instrument_id.to_string() }],
+    }
+}
+
+/// Same as [`write_dbn`], with `mappings` stored in the metadata like a response to a raw-symbol request.
+pub fn write_dbn_with_mappings<R: DbnEncodable>(
+    path: &str,
+    schema: Schema,
+    mappings: Vec<SymbolMapping>,
+    records: &[R],
+) {
+    let metadata = Metadata::builder()
+        .dataset("GLBX.MDP3")
+        .schema(Some(schema))
+        .start(0)
+        .stype_in(Some(SType::RawSymbol))
+        .stype_out(SType::InstrumentId)
+        .mappings(mappings)
+        .build();
+    let mut encoder = DbnEncoder::with_zstd(BufWriter::new(File::create(path).unwrap()), &metadata).unwrap();
+    for record in records {
+        encoder.encode_record(record).unwrap();
+    }
+    encoder.flush().unwrap();
+}
@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use arrow::ipc::writer::FileWriter;
+use databento::dbn::{Record, RecordRef, Schema};
+use std::fs::{self, File};
+
+use crate::downloader::columnar::{
+    columnar_sink, BatchBuilder, ColumnarRecord, ColumnarSinkFactory, RecordRows, BATCH_ROWS,
+};
+use crate::downloader::output::{DecodedFile, OutputFormat, RecordSink};
+use crate::downloader::schemas::schema_slug;
+use crate::processor::ContractFile;
+use crate::storage::FileEncoding;
+
+/// Arrow IPC files (Feather v2) in `{base}_{schema}.arrow`, with the columns of the Parquet output including
+/// `contract`. Written uncompressed so readers can memory-map the batches instead of copying them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IpcFormat;
+
+impl ColumnarSinkFactory for IpcFormat {
+    fn sink<R: ColumnarRecord>(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        let output = DecodedFile::new(format!("{}_{}.arrow", input.base_path(), schema_slug(schema)), input.start, input.end);
+        Ok(Box::new(IpcSink { contract: input.contract.clone(), rows: RecordRows::<R>::default(), writer: None, output }))
+    }
+}
+
+impl OutputFormat for IpcFormat {
+    fn encoding(&self) -> FileEncoding {
+        FileEncoding::Arrow
+    }
+
+    fn create(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        columnar_sink(self, input, schema)
+    }
+}
+
+struct IpcSink<R> {
+    contract: String,
+    rows: RecordRows<R>,
+    /// Opened with the schema of the first batch.
+    writer: Option<FileWriter<File>>,
+    output: DecodedFile,
+}
+
+impl<R: ColumnarRecord> IpcSink<R> {
+    fn flush(&mut self) -> Result<()> {
+        let batch = self.rows.take(Some(&self.contract))?;
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            writer => {
+                let path = &self.output.path;
+                let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+                writer.insert(FileWriter::try_new(file, &batch.schema())?)
+            }
+        };
+        if batch.num_rows() > 0 {
+            writer.write(&batch)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: ColumnarRecord> RecordSink for IpcSink<R> {
+    fn write(&mut self, record: RecordRef, symbol: &str) -> Result<()> {
+        self.rows.push(record, symbol)?;
+        self.output.add(record.header().ts_event);
+        if self.rows.buffered() >= BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<Vec<DecodedFile>> {
+        // An empty raw file still gets a file holding just the schema
+        self.flush()?;
+        if let Some(mut writer) = self.writer.take() {
+            writer.finish()?;
+        }
+        Ok(vec![self.output])
+    }
+
+    fn abort(self: Box<Self>) {
+        let path = self.output.path.clone();
+        drop(self);
+        let _ = fs::remove_file(path);
+    }
+}
@@ -1,4 +1,21 @@
 pub(crate) mod fetch;
 pub(crate) mod contracts;
-pub(crate) mod decode;
+pub mod calendar;
+pub mod products;
+pub mod roll;
+pub mod roll_calendar;
+pub mod sessions;
+pub mod batches;
+pub mod columnar;
+pub mod decode;
+#[cfg(test)]
+pub(crate) mod fixtures;
 pub(crate) mod range;
+pub(crate) mod resume;
+pub mod ipc_format;
+pub mod output;
+pub mod parquet_format;
+pub mod retry;
+pub mod schemas;
+pub mod symbology;
+pub mod text_format;
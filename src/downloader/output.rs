@@ -0,0 +1,199 @@
+use anyhow::{bail, Context, Result};
+use databento::dbn::{
+    encode::{json::Encoder as JsonEncoder, EncodeRecordTextExt},
+    OhlcvMsg, Record, RecordRef, Schema,
+};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
+use time::Date;
+
+use crate::downloader::ipc_format::IpcFormat;
+use crate::downloader::parquet_format::{ParquetFormat, ParquetOptions};
+use crate::downloader::schemas::{decoded_suffix, is_ohlcv};
+use crate::downloader::text_format::{CsvFormat, CsvOptions, DisplayTimezone, PrettyJsonFormat};
+use crate::processor::ContractFile;
+use crate::storage::FileEncoding;
+use crate::types::JsonOhlcv;
+
+/// A file format the decoder writes. Picks where the records of a raw contract file go and opens
+/// the sink that writes them.
+pub trait OutputFormat: Send + Sync {
+    /// How the catalog records the files this format writes.
+    fn encoding(&self) -> FileEncoding;
+
+    /// Opens a sink for the records of the raw file `input`, whose metadata declares `schema`.
+    /// Fails for schemas the format cannot hold.
+    fn create(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>>;
+}
+
+/// Receives the records of one raw file, in file order.
+pub trait RecordSink: Send {
+    /// Writes `record`, whose instrument was `symbol` at the time of the record.
+    fn write(&mut self, record: RecordRef, symbol: &str) -> Result<()>;
+
+    /// Flushes and closes the output. Returns every file written.
+    fn finish(self: Box<Self>) -> Result<Vec<DecodedFile>>;
+
+    /// Removes whatever was written, after a failure or cancellation.
+    fn abort(self: Box<Self>);
+}
+
+/// A file written by a sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedFile {
+    pub path: PathBuf,
+    /// Window the file covers: the raw file's window, or one day for a date partition.
+    pub start: Date,
+    pub end: Date,
+    pub record_count: u64,
+    /// `ts_event` of the first and last record, `None` for an empty file.
+    pub first_ts: Option<u64>,
+    pub last_ts: Option<u64>,
+}
+
+impl DecodedFile {
+    pub fn new(path: impl Into<PathBuf>, start: Date, end: Date) -> Self {
+        Self { path: path.into(), start, end, record_count: 0, first_ts: None, last_ts: None }
+    }
+
+    /// Counts a written record.
+    pub fn add(&mut self, ts_event: u64) {
+        self.first_ts = self.first_ts.or(Some(ts_event));
+        self.last_ts = Some(ts_event);
+        self.record_count += 1;
+    }
+}
+
+/// Format picked on the command line or in the GUI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeFormat {
+    /// One JSON object per line, next to the raw file.
+    #[default]
+    Ndjson,
+    Csv,
+    Parquet,
+    /// Arrow IPC, also known as Feather.
+    Arrow,
+}
+
+impl DecodeFormat {
+    pub const ALL: [DecodeFormat; 4] = [DecodeFormat::Ndjson, DecodeFormat::Csv, DecodeFormat::Parquet, DecodeFormat::Arrow];
+}
+
+/// Decoder output settings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodeOptions {
+    pub format: DecodeFormat,
+    /// Writes NDJSON with decimal prices and RFC 3339 timestamps instead of raw DBN values.
+    pub pretty: bool,
+    /// Clock of CSV and pretty JSON timestamps.
+    pub timezone: DisplayTimezone,
+    /// Used when `format` is CSV.
+    pub csv: CsvOptions,
+    /// Used when `format` is Parquet.
+    pub parquet: ParquetOptions,
+}
+
+impl DecodeOptions {
+    pub fn output_format(&self) -> Arc<dyn OutputFormat> {
+        match self.format {
+            DecodeFormat::Ndjson if self.pretty => Arc::new(PrettyJsonFormat::new(self.timezone)),
+            DecodeFormat::Ndjson => Arc::new(NdjsonFormat),
+            DecodeFormat::Csv => Arc::new(CsvFormat::new(self.csv.clone(), self.timezone)),
+            DecodeFormat::Parquet => Arc::new(ParquetFormat::new(self.parquet.clone())),
+            DecodeFormat::Arrow => Arc::new(IpcFormat),
+        }
+    }
+}
+
+/// JSON lines in `{base}_{schema}.json`. Bars are written as `JsonOhlcv`, the layout the processors read;
+/// every other schema in Databento's JSON for its record type.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NdjsonFormat;
+
+impl OutputFormat for NdjsonFormat {
+    fn encoding(&self) -> FileEncoding {
+        FileEncoding::Json
+    }
+
+    fn create(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        let base_path = input.base_path();
+        let output = DecodedFile::new(format!("{base_path}{}", decoded_suffix(schema)), input.start, input.end);
+        let writer = BufWriter::new(
+            File::create(&output.path).with_context(|| format!("Failed to create {}", output.path.display()))?,
+        );
+        Ok(Box::new(NdjsonSink { writer, bars: is_ohlcv(schema), output }))
+    }
+}
+
+struct NdjsonSink {
+    writer: BufWriter<File>,
+    bars: bool,
+    output: DecodedFile,
+}
+
+impl RecordSink for NdjsonSink {
+    fn write(&mut self, record: RecordRef, symbol: &str) -> Result<()> {
+        if self.bars {
+            let msg = record.get::<OhlcvMsg>().context("Expected an OHLCV record")?;
+            let bar = JsonOhlcv {
+                instrument_name: symbol.to_string(),
+                instrument_id: msg.hd.instrument_id,
+                ts_event: msg.hd.ts_event,
+                open: msg.open,
+                high: msg.high,
+                low: msg.low,
+                close: msg.close,
+                volume: msg.volume,
+            };
+            serde_json::to_writer(&mut self.writer, &bar)?;
+            writeln!(self.writer)?;
+        } else {
+            JsonEncoder::new(&mut self.writer, false, false, false).encode_ref_with_sym(record, Some(symbol))?;
+        }
+        self.output.add(record.header().ts_event);
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<Vec<DecodedFile>> {
+        self.writer.flush()?;
+        Ok(vec![self.output])
+    }
+
+    fn abort(self: Box<Self>) {
+        let path = self.output.path.clone();
+        drop(self);
+        let _ = fs::remove_file(path);
+    }
+}
+
+impl FromStr for DecodeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "ndjson" | "json" => Ok(DecodeFormat::Ndjson),
+            "csv" => Ok(DecodeFormat::Csv),
+            "parquet" => Ok(DecodeFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Ok(DecodeFormat::Arrow),
+            other => bail!("Unknown output format '{other}' (expected ndjson, csv, parquet or arrow)"),
+        }
+    }
+}
+
+impl fmt::Display for DecodeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeFormat::Ndjson => write!(f, "ndjson"),
+            DecodeFormat::Csv => write!(f, "csv"),
+            DecodeFormat::Parquet => write!(f, "parquet"),
+            DecodeFormat::Arrow => write!(f, "arrow"),
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::{bail, Context, Result};
+use databento::dbn::{Record, RecordRef, Schema};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, GzipLevel, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    path::PathBuf,
+    str::FromStr,
+};
+use time::{Date, OffsetDateTime};
+
+use crate::downloader::columnar::{
+    columnar_sink, BatchBuilder, ColumnarRecord, ColumnarSinkFactory, RecordRows, BATCH_ROWS,
+};
+use crate::downloader::contracts::parse_full_contract_symbol;
+use crate::downloader::output::{DecodedFile, OutputFormat, RecordSink};
+use crate::downloader::schemas::schema_slug;
+use crate::processor::ContractFile;
+use crate::storage::FileEncoding;
+
+/// Column compression of Parquet output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParquetCompression {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Lz4,
+    #[default]
+    Zstd,
+}
+
+impl ParquetCompression {
+    pub const ALL: [ParquetCompression; 5] = [
+        ParquetCompression::Uncompressed,
+        ParquetCompression::Snappy,
+        ParquetCompression::Gzip,
+        ParquetCompression::Lz4,
+        ParquetCompression::Zstd,
+    ];
+
+    fn codec(&self) -> Compression {
+        match self {
+            ParquetCompression::Uncompressed => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Gzip => Compression::GZIP(GzipLevel::default()),
+            ParquetCompression::Lz4 => Compression::LZ4_RAW,
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetOptions {
+    pub compression: ParquetCompression,
+    /// Most records per row group.
+    pub row_group_size: usize,
+    /// Writes Hive-style partitions instead of one file next to each raw file:
+    /// `{dir}/{schema}/root=ES/contract=ESH2024/date=2024-01-05/part-{start}_{end}.parquet`, one per contract,
+    /// UTC day and raw file window, so raw files of one contract with overlapping windows never share a file.
+    pub partition_dir: Option<PathBuf>,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self { compression: ParquetCompression::default(), row_group_size: 1_000_000, partition_dir: None }
+    }
+}
+
+/// Typed columns in `{base}_{schema}.parquet`, or in date partitions when `partition_dir` is set.
+/// Unpartitioned files carry a `contract` column; partitioned ones have it in their path.
+#[derive(Debug, Clone, Default)]
+pub struct ParquetFormat {
+    options: ParquetOptions,
+}
+
+impl ParquetFormat {
+    pub fn new(options: ParquetOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl ColumnarSinkFactory for ParquetFormat {
+    fn sink<R: ColumnarRecord>(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        let properties = WriterProperties::builder()
+            .set_compression(self.options.compression.codec())
+            .set_max_row_group_size(self.options.row_group_size.max(1))
+            .build();
+        Ok(Box::new(ParquetSink::<R> {
+            input: input.clone(),
+            schema,
+            partition_dir: self.options.partition_dir.clone(),
+            batch_rows: self.options.row_group_size.clamp(1, BATCH_ROWS),
+            properties,
+            partitions: BTreeMap::new(),
+            closed: Vec::new(),
+            closed_through: None,
+        }))
+    }
+}
+
+impl OutputFormat for ParquetFormat {
+    fn encoding(&self) -> FileEncoding {
+        FileEncoding::Parquet
+    }
+
+    fn create(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        columnar_sink(self, input, schema)
+    }
+}
+
+/// One output file and the records waiting to be written to it.
+struct Partition<R> {
+    output: DecodedFile,
+    rows: RecordRows<R>,
+    writer: Option<ArrowWriter<File>>,
+}
+
+struct ParquetSink<R> {
+    input: ContractFile,
+    schema: Schema,
+    partition_dir: Option<PathBuf>,
+    batch_rows: usize,
+    properties: WriterProperties,
+    /// Keyed by UTC day when partitioned; a single `None` entry otherwise.
+    partitions: BTreeMap<Option<Date>, Partition<R>>,
+    /// Partitions already written and closed, and the last day among them.
+    closed: Vec<DecodedFile>,
+    closed_through: Option<Date>,
+}
+
+impl<R: ColumnarRecord> ParquetSink<R> {
+    fn partition_path(&self, day: Option<Date>) -> PathBuf {
+        match (&self.partition_dir, day) {
+            (Some(dir), Some(day)) => {
+                let root = parse_full_contract_symbol(&self.input.contract).map_or("", |(root, _, _)| root);
+                dir.join(self.schema.as_str())
+                    .join(format!("root={root}"))
+                    .join(format!("contract={}", self.input.contract))
+                    .join(format!("date={day}"))
+                    .join(format!("part-{}_{}.parquet", self.input.start, self.input.end))
+            }
+            _ => PathBuf::from(format!("{}_{}.parquet", self.input.base_path(), schema_slug(self.schema))),
+        }
+    }
+
+    fn flush(&mut self, day: Option<Date>) -> Result<()> {
+        let contract = self.partition_dir.is_none().then_some(self.input.contract.as_str());
+        let properties = self.properties.clone();
+        let partition = self.partitions.get_mut(&day).expect("Partition exists");
+        if partition.rows.buffered() == 0 {
+            return Ok(());
+        }
+
+        let batch = partition.rows.take(contract)?;
+        if partition.writer.is_none() {
+            let path = &partition.output.path;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+            partition.writer = Some(ArrowWriter::try_new(file, batch.schema(), Some(properties))?);
+        }
+        partition.writer.as_mut().expect("Writer was just opened").write(&batch)?;
+        Ok(())
+    }
+
+    /// Writes out the rest of the partition of `day` and closes its file.
+    fn close(&mut self, day: Option<Date>) -> Result<DecodedFile> {
+        self.flush(day)?;
+        let partition = self.partitions.remove(&day).expect("Partition exists");
+        if let Some(writer) = partition.writer {
+            writer.close()?;
+        }
+        Ok(partition.output)
+    }
+
+    /// Closes the day partitions before `day`, so a long file does not keep a writer open for every day.
+    fn close_before(&mut self, day: Date) -> Result<()> {
+        while let Some((&Some(first), _)) = self.partitions.first_key_value()
+            && first < day
+        {
+            let output = self.close(Some(first))?;
+            self.closed.push(output);
+            self.closed_through = Some(first);
+        }
+        Ok(())
+    }
+}
+
+impl<R: ColumnarRecord> RecordSink for ParquetSink<R> {
+    fn write(&mut self, record: RecordRef, symbol: &str) -> Result<()> {
+        let ts_event = record.header().ts_event;
+        let day = match self.partition_dir {
+            Some(_) => Some(OffsetDateTime::from_unix_timestamp_nanos(ts_event as i128)?.date()),
+            None => None,
+        };
+        if let Some(day) = day {
+            // Records come in index-timestamp (`ts_recv`) order and `ts_event` trails it by well under a day,
+            // so once the index is two days past a partition nothing more is written to it
+            let indexed = record.index_ts().map_or(day, |index_ts| index_ts.date());
+            self.close_before(indexed.previous_day().unwrap_or(indexed))?;
+            if self.closed_through.is_some_and(|closed| day <= closed) {
+                bail!("Record of {day} arrived after its partition of {} was closed", self.input.contract);
+            }
+        }
+
+        if !self.partitions.contains_key(&day) {
+            let (start, end) = day.map_or((self.input.start, self.input.end), |day| (day, day));
+            let output = DecodedFile::new(self.partition_path(day), start, end);
+            self.partitions.insert(day, Partition { output, rows: RecordRows::default(), writer: None });
+        }
+        let partition = self.partitions.get_mut(&day).expect("Partition was just inserted");
+        partition.rows.push(record, symbol).with_context(|| format!("Unexpected record in {} data", self.schema))?;
+        partition.output.add(ts_event);
+        if partition.rows.buffered() >= self.batch_rows {
+            self.flush(day)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<Vec<DecodedFile>> {
+        let days = self.partitions.keys().copied().collect::<Vec<_>>();
+        let mut written = std::mem::take(&mut self.closed);
+        for day in days {
+            written.push(self.close(day)?);
+        }
+        Ok(written)
+    }
+
+    fn abort(self: Box<Self>) {
+        for output in &self.closed {
+            let _ = fs::remove_file(&output.path);
+        }
+        for partition in self.partitions.into_values() {
+            drop(partition.writer);
+            let _ = fs::remove_file(&partition.output.path);
+        }
+    }
+}
+
+impl FromStr for ParquetCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "uncompressed" => Ok(ParquetCompression::Uncompressed),
+            "snappy" => Ok(ParquetCompression::Snappy),
+            "gzip" => Ok(ParquetCompression::Gzip),
+            "lz4" => Ok(ParquetCompression::Lz4),
+            "zstd" => Ok(ParquetCompression::Zstd),
+            other => bail!("Unknown compression '{other}' (expected none, snappy, gzip, lz4 or zstd)"),
+        }
+    }
+}
+
+impl fmt::Display for ParquetCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParquetCompression::Uncompressed => write!(f, "none"),
+            ParquetCompression::Snappy => write!(f, "snappy"),
+            ParquetCompression::Gzip => write!(f, "gzip"),
+            ParquetCompression::Lz4 => write!(f, "lz4"),
+            ParquetCompression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use databento::dbn::{rtype, RecordHeader, TradeMsg};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::path::Path;
+    use time::macros::datetime;
+
+    fn trade(ts_event: OffsetDateTime, ts_recv: OffsetDateTime) -> TradeMsg {
+        let ts_event = ts_event.unix_timestamp_nanos() as u64;
+        TradeMsg {
+            hd: RecordHeader::new::<TradeMsg>(rtype::MBP_0, 1, 7, ts_event),
+            ts_recv: ts_recv.unix_timestamp_nanos() as u64,
+            price: 4_800_250_000_000,
+            size: 1,
+            ..Default::default()
+        }
+    }
+
+    fn parquet_rows(path: &Path) -> usize {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+        reader.map(|batch| batch.unwrap().num_rows()).sum()
+    }
+
+    #[test]
+    fn test_day_partitions_close_once_records_move_past_them() {
+        let dir = PathBuf::from("test_output_parquet_close_days");
+        let _ = fs::remove_dir_all(&dir);
+        let input = ContractFile::from_path(Path::new("ES/2024-01-05_2024-03-15_ESH2024.dbn.zst")).unwrap();
+        let format = ParquetFormat::new(ParquetOptions { partition_dir: Some(dir.clone()), ..Default::default() });
+        let mut sink = format.create(&input, Schema::Trades).unwrap();
+
+        let friday = datetime!(2024 - 01 - 05 15:00 UTC);
+        let saturday = datetime!(2024 - 01 - 06 00:01 UTC);
+        let monday = datetime!(2024 - 01 - 08 15:00 UTC);
+        sink.write((&trade(friday, friday)).into(), "ESH4").unwrap();
+        // Stamped on Friday but received on Saturday
+        sink.write((&trade(friday, saturday)).into(), "ESH4").unwrap();
+        sink.write((&trade(saturday, saturday)).into(), "ESH4").unwrap();
+        sink.write((&trade(monday, monday)).into(), "ESH4").unwrap();
+
+        // Friday and Saturday are complete files while Monday is still being written
+        let partition = dir.join("trades/root=ES/contract=ESH2024");
+        let day = |date: &str| partition.join(format!("date={date}/part-2024-01-05_2024-03-15.parquet"));
+        assert_eq!(parquet_rows(&day("2024-01-05")), 2);
+        assert_eq!(parquet_rows(&day("2024-01-06")), 1);
+        assert!(sink.write((&trade(saturday, monday)).into(), "ESH4").is_err());
+
+        let written = sink.finish().unwrap();
+        let paths = written.into_iter().map(|output| output.path).collect::<Vec<_>>();
+        assert_eq!(paths, [day("2024-01-05"), day("2024-01-06"), day("2024-01-08")]);
+        assert_eq!(parquet_rows(&day("2024-01-08")), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, path::Path, sync::OnceLock};
+use time::Month;
+
+use crate::downloader::contracts::{futures_month_code, month_from_code, ContractError, ExpiryRule};
+use crate::downloader::roll::{ContractWindow, RollAnchor, RollPolicy};
+use crate::downloader::sessions::{session_template, SessionTemplate, DEFAULT_SESSION};
+
+/// Environment variable that points at a product registry file (`.toml` or `.json`).
+pub const PRODUCTS_FILE_ENV: &str = "DATABENTO_PRODUCTS_FILE";
+/// Registry file picked up from the working directory when the environment variable is unset.
+pub const DEFAULT_PRODUCTS_FILE: &str = "products.toml";
+
+const BUILTIN_PRODUCTS: &str = include_str!("../../products.toml");
+
+/// Static description of a futures product.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProductSpec {
+    pub root: String,
+    #[serde(default)]
+    pub name: String,
+    /// Full-size product a micro contract inherits its rules from, e.g. `ES` for `MES`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    pub dataset: String,
+    #[serde(with = "month_codes")]
+    pub listed_months: Vec<Month>,
+    pub expiry_rule: ExpiryRule,
+    /// Business days the last trading day falls before the one given by `expiry_rule`.
+    #[serde(default)]
+    pub expiry_offset_days: u32,
+    /// Default roll policy, stored as the `roll_on` and `window` keys.
+    #[serde(flatten)]
+    pub roll: RollPolicy,
+    /// Name of the trading-hours template in `sessions::SESSION_TEMPLATES`.
+    #[serde(default = "default_session")]
+    pub session: String,
+    pub tick_size: f64,
+    pub price_multiplier: f64,
+}
+
+fn default_session() -> String {
+    DEFAULT_SESSION.to_string()
+}
+
+impl ProductSpec {
+    /// Trading hours of the product.
+    pub fn session_template(&self) -> &'static SessionTemplate {
+        session_template(&self.session).expect("Session template is validated by the registry")
+    }
+}
+
+/// Set of products known to the toolkit, in file order.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProductRegistry {
+    products: Vec<ProductSpec>,
+}
+
+/// Registry file layout.
+#[derive(Deserialize)]
+struct RegistryFile {
+    products: Vec<ProductEntry>,
+}
+
+/// A product as written in the registry file. Entries with a `parent` may leave out
+/// everything they share with it (dataset, months, expiry and roll rules, window).
+#[derive(Deserialize)]
+struct ProductEntry {
+    root: String,
+    #[serde(default)]
+    name: String,
+    parent: Option<String>,
+    dataset: Option<String>,
+    listed_months: Option<String>,
+    expiry_rule: Option<ExpiryRule>,
+    expiry_offset_days: Option<u32>,
+    roll_on: Option<RollAnchor>,
+    window: Option<ContractWindow>,
+    session: Option<String>,
+    tick_size: f64,
+    price_multiplier: f64,
+}
+
+impl ProductEntry {
+    /// Fills missing fields from `parent` and turns the entry into a `ProductSpec`.
+    fn resolve(self, parent: Option<&ProductSpec>) -> Result<ProductSpec> {
+        let root = self.root;
+        let missing = |field: &str| anyhow::anyhow!("{root}: missing {field} and no parent to inherit it from");
+
+        let listed_months = match self.listed_months {
+            Some(codes) => month_codes::parse(&codes).with_context(|| format!("{root}: invalid listed_months"))?,
+            None => parent.map(|p| p.listed_months.clone()).ok_or_else(|| missing("listed_months"))?,
+        };
+
+        Ok(ProductSpec {
+            dataset: self.dataset.or_else(|| parent.map(|p| p.dataset.clone())).ok_or_else(|| missing("dataset"))?,
+            expiry_rule: self.expiry_rule.or_else(|| parent.map(|p| p.expiry_rule)).ok_or_else(|| missing("expiry_rule"))?,
+            expiry_offset_days: self
+                .expiry_offset_days
+                .or_else(|| parent.map(|p| p.expiry_offset_days))
+                .unwrap_or_default(),
+            roll: RollPolicy {
+                anchor: self.roll_on.or_else(|| parent.map(|p| p.roll.anchor)).unwrap_or_default(),
+                window: self.window.or_else(|| parent.map(|p| p.roll.window)).ok_or_else(|| missing("window"))?,
+            },
+            session: self
+                .session
+                .or_else(|| parent.map(|p| p.session.clone()))
+                .unwrap_or_else(default_session),
+            listed_months,
+            name: self.name,
+            parent: self.parent,
+            tick_size: self.tick_size,
+            price_multiplier: self.price_multiplier,
+            root,
+        })
+    }
+}
+
+impl RegistryFile {
+    /// Resolves parent products first so siblings can inherit from them.
+    fn into_products(self) -> Result<Vec<ProductSpec>> {
+        let (children, parents): (Vec<_>, Vec<_>) =
+            self.products.into_iter().enumerate().partition(|(_, entry)| entry.parent.is_some());
+
+        let mut resolved = parents
+            .into_iter()
+            .map(|(index, entry)| Ok((index, entry.resolve(None)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        for (index, entry) in children {
+            let parent_root = entry.parent.clone().unwrap_or_default();
+            let parent = resolved
+                .iter()
+                .map(|(_, spec)| spec)
+                .find(|spec| spec.root == parent_root)
+                .with_context(|| format!("{}: unknown parent product {parent_root}", entry.root))?;
+            let spec = entry.resolve(Some(parent))?;
+            resolved.push((index, spec));
+        }
+
+        // Keep file order
+        resolved.sort_by_key(|(index, _)| *index);
+        Ok(resolved.into_iter().map(|(_, spec)| spec).collect())
+    }
+}
+
+impl ProductRegistry {
+    pub fn new(mut products: Vec<ProductSpec>) -> Result<Self> {
+        let mut roots = HashSet::new();
+        for product in &mut products {
+            if product.root.is_empty() {
+                bail!("Product root must not be empty");
+            }
+            if !roots.insert(product.root.clone()) {
+                bail!("Duplicate product root: {}", product.root);
+            }
+            if product.listed_months.is_empty() {
+                bail!("{}: listed_months must not be empty", product.root);
+            }
+            if product.tick_size <= 0.0 || product.price_multiplier <= 0.0 {
+                bail!("{}: tick_size and price_multiplier must be positive", product.root);
+            }
+            product.roll.validate().with_context(|| format!("{}: invalid roll policy", product.root))?;
+            if session_template(&product.session).is_none() {
+                bail!("{}: unknown session template {}", product.root, product.session);
+            }
+            product.listed_months.sort_by_key(|&month| month as u8);
+            product.listed_months.dedup();
+        }
+
+        Ok(Self { products })
+    }
+
+    /// Registry compiled into the binary from `products.toml`.
+    pub fn builtin() -> Self {
+        Self::from_toml_str(BUILTIN_PRODUCTS).expect("Built-in product registry is invalid")
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self> {
+        let file: RegistryFile = toml::from_str(contents).context("Invalid product registry TOML")?;
+        Self::new(file.into_products()?)
+    }
+
+    pub fn from_json_str(contents: &str) -> Result<Self> {
+        let file: RegistryFile = serde_json::from_str(contents).context("Invalid product registry JSON")?;
+        Self::new(file.into_products()?)
+    }
+
+    /// Loads a registry file, choosing the format from the extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read product registry: {}", path.display()))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::from_json_str(&contents),
+            _ => Self::from_toml_str(&contents),
+        }
+        .with_context(|| format!("Failed to load product registry: {}", path.display()))
+    }
+
+    /// Loads `$DATABENTO_PRODUCTS_FILE`, then `./products.toml`, then the built-in registry.
+    pub fn load_default() -> Result<Self> {
+        if let Ok(path) = dotenvy::var(PRODUCTS_FILE_ENV) {
+            return Self::load(path);
+        }
+        if Path::new(DEFAULT_PRODUCTS_FILE).exists() {
+            return Self::load(DEFAULT_PRODUCTS_FILE);
+        }
+        Ok(Self::builtin())
+    }
+
+    pub fn get(&self, root: &str) -> Option<&ProductSpec> {
+        self.products.iter().find(|product| product.root == root)
+    }
+
+    pub fn products(&self) -> &[ProductSpec] {
+        &self.products
+    }
+
+    pub fn roots(&self) -> Vec<&str> {
+        self.products.iter().map(|product| product.root.as_str()).collect()
+    }
+
+    /// Micro (or other sibling) products that name `root` as their parent.
+    pub fn siblings_of(&self, root: &str) -> Vec<&ProductSpec> {
+        self.products
+            .iter()
+            .filter(|product| product.parent.as_deref() == Some(root))
+            .collect()
+    }
+
+    /// Finds the product of a raw contract symbol such as `CLZ5` or `RTYH24`.
+    pub fn find_by_contract(&self, contract_symbol: &str) -> Option<&ProductSpec> {
+        self.products
+            .iter()
+            .filter(|product| {
+                contract_symbol
+                    .strip_prefix(product.root.as_str())
+                    .is_some_and(is_month_and_year_suffix)
+            })
+            .max_by_key(|product| product.root.len())
+    }
+}
+
+/// Registry shared by the downloader, quote path and GUI, loaded once with `ProductRegistry::load_default`.
+/// A configured file that cannot be loaded is returned as an error on every call rather than replaced by the
+/// built-in registry, whose datasets and expiry rules may not be the ones intended.
+pub fn product_registry() -> Result<&'static ProductRegistry> {
+    static REGISTRY: OnceLock<Result<ProductRegistry, String>> = OnceLock::new();
+    REGISTRY
+        .get_or_init(|| ProductRegistry::load_default().map_err(|e| format!("{e:#}")))
+        .as_ref()
+        .map_err(|e| anyhow!("{e}"))
+}
+
+/// Dataset configured for a product root in the registry.
+pub(crate) fn product_dataset(base_symbol: &str) -> Result<String, ContractError> {
+    product_registry()
+        .map_err(ContractError::registry)?
+        .get(base_symbol)
+        .map(|product| product.dataset.clone())
+        .ok_or_else(|| ContractError::UnsupportedSymbol { symbol: base_symbol.to_string() })
+}
+
+fn is_month_and_year_suffix(suffix: &str) -> bool {
+    let mut chars = suffix.chars();
+    chars.next().is_some_and(|code| month_from_code(code).is_some())
+        && !chars.as_str().is_empty()
+        && chars.all(|c| c.is_ascii_digit())
+}
+
+/// Serializes listed months as a string of futures month codes, e.g. `"HMUZ"`.
+mod month_codes {
+    use super::*;
+    use serde::{de::Error, Deserializer, Serializer};
+    use std::result::Result;
+
+    pub fn serialize<S: Serializer>(months: &[Month], serializer: S) -> Result<S::Ok, S::Error> {
+        let codes = months.iter().map(|&month| futures_month_code(month)).collect::<String>();
+        serializer.serialize_str(&codes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Month>, D::Error> {
+        let codes = String::deserialize(deserializer)?;
+        parse(&codes).map_err(D::Error::custom)
+    }
+
+    pub fn parse(codes: &str) -> anyhow::Result<Vec<Month>> {
+        codes
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|code| {
+                month_from_code(code.to_ascii_uppercase())
+                    .with_context(|| format!("Unknown futures month code: {code}"))
+            })
+            .collect()
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_registry_has_default_roots() {
+        let registry = ProductRegistry::builtin();
+        for root in ["CL", "NG", "RB", "HO", "ES", "NQ", "RTY", "YM"] {
+            assert!(registry.get(root).is_some(), "Missing product {root}");
+        }
+        let es = registry.get("ES").unwrap();
+        assert_eq!(es.listed_months, vec![Month::March, Month::June, Month::September, Month::December]);
+        assert_eq!(es.expiry_rule, ExpiryRule::ThirdFriday);
+        for root in ["RB", "HO"] {
+            assert_eq!(registry.get(root).unwrap().expiry_rule, ExpiryRule::LastBusinessDayOfPriorMonth);
+        }
+    }
+
+    #[test]
+    fn test_registry_from_json() {
+        let json = r#"{"products": [{
+            "root": "GC", "dataset": "GLBX.MDP3", "listed_months": "GJMQVZ",
+            "expiry_rule": "natural_gas",
+            "window": {"kind": "before_expiry", "days_before": 30, "days_after": 0},
+            "tick_size": 0.1, "price_multiplier": 100.0
+        }]}"#;
+        let registry = ProductRegistry::from_json_str(json).unwrap();
+        let gc = registry.get("GC").unwrap();
+        assert_eq!(gc.listed_months.len(), 6);
+        assert_eq!(gc.roll.window, ContractWindow::BeforeExpiry { days_before: 30, days_after: 0 });
+    }
+
+    #[test]
+    fn test_registry_rejects_invalid_products() {
+        let duplicate = r#"
+            [[products]]
+            root = "CL"
+            dataset = "GLBX.MDP3"
+            listed_months = "Z"
+            expiry_rule = "crude_oil"
+            window = { kind = "before_expiry", days_before = 40, days_after = 3 }
+            tick_size = 0.01
+            price_multiplier = 1000.0
+
+            [[products]]
+            root = "CL"
+            dataset = "GLBX.MDP3"
+            listed_months = "Z"
+            expiry_rule = "crude_oil"
+            window = { kind = "before_expiry", days_before = 40, days_after = 3 }
+            tick_size = 0.01
+            price_multiplier = 1000.0
+        "#;
+        assert!(ProductRegistry::from_toml_str(duplicate).is_err());
+
+        let bad_month = r#"
+            [[products]]
+            root = "CL"
+            dataset = "GLBX.MDP3"
+            listed_months = "ZA"
+            expiry_rule = "crude_oil"
+            window = { kind = "before_expiry", days_before = 40, days_after = 3 }
+            tick_size = 0.01
+            price_multiplier = 1000.0
+        "#;
+        assert!(ProductRegistry::from_toml_str(bad_month).is_err());
+    }
+
+    #[test]
+    fn test_micro_products_inherit_from_parent() {
+        let registry = ProductRegistry::builtin();
+        let es = registry.get("ES").unwrap();
+        let mes = registry.get("MES").unwrap();
+
+        assert_eq!(mes.parent.as_deref(), Some("ES"));
+        assert_eq!(mes.expiry_rule, es.expiry_rule);
+        assert_eq!(mes.listed_months, es.listed_months);
+        assert_eq!(mes.roll, es.roll);
+        assert_eq!(mes.price_multiplier, 5.0);
+
+        let siblings = registry.siblings_of("ES").iter().map(|p| p.root.as_str()).collect::<Vec<_>>();
+        assert_eq!(siblings, vec!["MES"]);
+        assert_eq!(registry.get("MCL").unwrap().expiry_offset_days, 1);
+    }
+
+    #[test]
+    fn test_unknown_parent_is_rejected() {
+        let orphan = r#"
+            [[products]]
+            root = "MXX"
+            parent = "XX"
+            tick_size = 0.25
+            price_multiplier = 5.0
+        "#;
+        let error = ProductRegistry::from_toml_str(orphan).unwrap_err();
+        assert!(format!("{error:#}").contains("unknown parent product XX"));
+    }
+
+    #[test]
+    fn test_find_by_contract() {
+        let registry = ProductRegistry::builtin();
+        assert_eq!(registry.find_by_contract("CLZ5").map(|p| p.root.as_str()), Some("CL"));
+        assert_eq!(registry.find_by_contract("RTYH24").map(|p| p.root.as_str()), Some("RTY"));
+        assert_eq!(registry.find_by_contract("M2KH4").map(|p| p.root.as_str()), Some("M2K"));
+        assert!(registry.find_by_contract("CL").is_none());
+        assert!(registry.find_by_contract("ZZZH5").is_none());
+    }
+}
@@ -0,0 +1,28 @@
+use time::{Date, OffsetDateTime, Time};
+
+/// Converts an inclusive `start..=end` date window into the UTC datetime range sent to Databento.
+/// The end is moved to midnight of the following day so the last date is fully covered.
+pub fn download_time_range(start: Date, end: Date) -> (OffsetDateTime, OffsetDateTime) {
+    let range_start = start.with_time(Time::MIDNIGHT).assume_utc();
+    let range_end = end
+        .next_day()
+        .unwrap_or(end)
+        .with_time(Time::MIDNIGHT)
+        .assume_utc();
+
+    (range_start, range_end)
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::{date, datetime};
+
+    #[test]
+    fn test_download_time_range_covers_end_date() {
+        let (start, end) = download_time_range(date!(2023 - 06 - 01), date!(2023 - 06 - 30));
+        assert_eq!(start, datetime!(2023 - 06 - 01 00:00 UTC));
+        assert_eq!(end, datetime!(2023 - 07 - 01 00:00 UTC));
+    }
+}
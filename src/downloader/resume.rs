@@ -0,0 +1,325 @@
+use anyhow::{Context, Result};
+use databento::dbn::{
+    decode::{DbnDecoder, DbnMetadata, DecodeRecordRef, MergeDecoder},
+    encode::{DbnEncoder, EncodeDbn, EncodeRecord},
+    Record,
+};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+use time::{Date, Duration};
+
+use crate::downloader::range::download_time_range;
+use crate::downloader::retry::ErrorClass;
+use crate::processor::{list_contract_files, ContractFile};
+
+/// Suffix of files still being written. They are renamed once complete and validated.
+pub const PART_EXT: &str = ".part";
+
+/// Suffix of complete downloads whose records failed validation, kept because they were billed.
+pub const REJECTED_EXT: &str = ".rejected";
+
+/// What is left to fetch for one contract window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadPlan {
+    /// A downloaded file already covers the window.
+    Covered(ContractFile),
+    /// Nothing usable on disk; fetch the whole window.
+    Full,
+    /// `existing` covers part of the window; fetch `missing` (head and/or tail) and merge.
+    Extend { existing: ContractFile, missing: Vec<(Date, Date)> },
+}
+
+/// Compares the window `start..=end` of `contract` with the raw files already in `dir`.
+pub fn plan_download(dir: impl AsRef<Path>, contract: &str, start: Date, end: Date) -> Result<DownloadPlan> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        return Ok(DownloadPlan::Full);
+    }
+
+    let existing = list_contract_files(dir)?
+        .into_iter()
+        .filter(|file| file.contract == contract && !file.is_decoded())
+        .collect::<Vec<_>>();
+
+    if let Some(covering) = existing.iter().find(|file| file.start <= start && file.end >= end) {
+        return Ok(DownloadPlan::Covered(covering.clone()));
+    }
+
+    // Extend the file sharing the most days with the window
+    let overlap = |file: &ContractFile| (file.end.min(end) - file.start.max(start)).whole_days();
+    let Some(best) = existing.into_iter().filter(|file| overlap(file) >= 0).max_by_key(overlap) else {
+        return Ok(DownloadPlan::Full);
+    };
+
+    let mut missing = Vec::new();
+    if start < best.start {
+        missing.push((start, best.start - Duration::days(1)));
+    }
+    if end > best.end {
+        missing.push((best.end + Duration::days(1), end));
+    }
+    Ok(DownloadPlan::Extend { existing: best, missing })
+}
+
+/// Path a download is written to before it is validated and renamed.
+pub fn part_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut part = path.as_ref().as_os_str().to_owned();
+    part.push(PART_EXT);
+    PathBuf::from(part)
+}
+
+/// Path a download whose records failed validation is kept under.
+pub fn rejected_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut rejected = path.as_ref().as_os_str().to_owned();
+    rejected.push(REJECTED_EXT);
+    PathBuf::from(rejected)
+}
+
+/// Why a finished download was rejected.
+#[derive(Debug)]
+pub enum InvalidDownload {
+    /// The file does not decode to the end, most likely because the transfer was cut off.
+    Corrupt(anyhow::Error),
+    /// The file is complete but its records are out of order or outside the requested window.
+    /// Fetching it again returns the same records.
+    Records(String),
+}
+
+impl InvalidDownload {
+    /// Only a file that failed to decode is worth fetching again.
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            InvalidDownload::Corrupt(_) => ErrorClass::Transient,
+            InvalidDownload::Records(_) => ErrorClass::Permanent,
+        }
+    }
+}
+
+/// Checks that a finished download decodes to the end and that every record is in time order and
+/// inside the requested `start..=end` window. Returns the number of records.
+///
+/// Order and window are checked on the index timestamp Databento sorts and filters by: `ts_recv` for
+/// schemas that carry it, `ts_event` for bars. The `ts_event` of trades and book updates may go backwards.
+pub fn validate_download(path: impl AsRef<Path>, start: Date, end: Date) -> Result<u64, InvalidDownload> {
+    let path = path.as_ref();
+    let (range_start, range_end) = download_time_range(start, end);
+    let (range_start, range_end) = (range_start.unix_timestamp_nanos() as u64, range_end.unix_timestamp_nanos() as u64);
+
+    let mut decoder = DbnDecoder::from_zstd_file(path)
+        .with_context(|| format!("Failed to open download {}", path.display()))
+        .map_err(InvalidDownload::Corrupt)?;
+    let mut count = 0;
+    let mut previous = 0;
+    while let Some(record) = decoder
+        .decode_record_ref()
+        .with_context(|| format!("Truncated or corrupt download {}", path.display()))
+        .map_err(InvalidDownload::Corrupt)?
+    {
+        let ts = record.raw_index_ts();
+        if ts < previous {
+            let message = format!("{}: records out of order at index timestamp {ts}", path.display());
+            return Err(InvalidDownload::Records(message));
+        }
+        if ts < range_start || ts >= range_end {
+            let message = format!("{}: record at index timestamp {ts} is outside {start} to {end}", path.display());
+            return Err(InvalidDownload::Records(message));
+        }
+        previous = ts;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Merges DBN files of one contract into `output` in time order, through a `.part` file renamed on success.
+pub fn merge_downloads(inputs: &[PathBuf], output: impl AsRef<Path>) -> Result<()> {
+    let output = output.as_ref();
+    let decoders = inputs
+        .iter()
+        .map(|path| DbnDecoder::from_zstd_file(path).with_context(|| format!("Failed to open {}", path.display())))
+        .collect::<Result<Vec<_>>>()?;
+    let merged = MergeDecoder::new(decoders).context("Downloads to merge have incompatible metadata")?;
+
+    let part = part_path(output);
+    {
+        let writer = BufWriter::new(File::create(&part).with_context(|| format!("Failed to create {}", part.display()))?);
+        let mut encoder = DbnEncoder::with_zstd(writer, merged.metadata())?;
+        encoder.encode_decoded(merged)?;
+        encoder.flush()?;
+    }
+    fs::rename(&part, output).with_context(|| format!("Failed to move {} into place", output.display()))
+}
+
+/// Removes `.part` files left in `dir` by an interrupted run.
+pub fn remove_partial_downloads(dir: impl AsRef<Path>) -> Result<usize> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.to_str().is_some_and(|path| path.ends_with(PART_EXT)) {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the `.part` files of `contract` in `dir`, e.g. after its download was cancelled.
+pub fn remove_contract_partials(dir: impl AsRef<Path>, contract: &str) -> Result<usize> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+
+    let suffix = format!("_{contract}.dbn.zst{PART_EXT}");
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.ends_with(&suffix)) {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+impl fmt::Display for InvalidDownload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidDownload::Corrupt(error) => write!(f, "{error:#}"),
+            InvalidDownload::Records(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidDownload {}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::fixtures::write_dbn;
+    use databento::dbn::{rtype, OhlcvMsg, RecordHeader, Schema, TradeMsg};
+    use time::macros::{date, datetime};
+
+    fn touch(dir: &str, name: &str) {
+        fs::write(format!("{dir}/{name}"), b"").unwrap();
+    }
+
+    fn write_bars(path: &str, timestamps: &[u64]) {
+        let bars: Vec<_> = timestamps
+            .iter()
+            .map(|&ts_event| OhlcvMsg {
+                hd: RecordHeader::new::<OhlcvMsg>(rtype::OHLCV_1M, 1, 1, ts_event),
+                open: 1,
+                high: 1,
+                low: 1,
+                close: 1,
+                volume: 1,
+            })
+            .collect();
+        write_dbn(path, Schema::Ohlcv1M, &bars);
+    }
+
+    fn write_trades(path: &str, timestamps: &[(u64, u64)]) {
+        let trades: Vec<_> = timestamps
+            .iter()
+            .map(|&(ts_event, ts_recv)| TradeMsg {
+                hd: RecordHeader::new::<TradeMsg>(rtype::MBP_0, 1, 1, ts_event),
+                ts_recv,
+                ..Default::default()
+            })
+            .collect();
+        write_dbn(path, Schema::Trades, &trades);
+    }
+
+    #[test]
+    fn test_validate_download_uses_ts_recv() {
+        let dir = "test_output_validate_ts_recv";
+        fs::create_dir_all(dir).unwrap();
+        let day_start = datetime!(2024 - 01 - 02 00:00 UTC).unix_timestamp_nanos() as u64;
+
+        // The first trade happened before midnight and the second before the first, but both arrived in order
+        let trades = format!("{dir}/trades.dbn.zst");
+        write_trades(&trades, &[(day_start - 5_000, day_start + 1_000), (day_start - 9_000, day_start + 2_000)]);
+        assert_eq!(validate_download(&trades, date!(2024 - 01 - 02), date!(2024 - 01 - 02)).unwrap(), 2);
+
+        let unordered = format!("{dir}/unordered.dbn.zst");
+        write_trades(&unordered, &[(day_start + 1_000, day_start + 2_000), (day_start + 2_000, day_start + 1_000)]);
+        let error = validate_download(&unordered, date!(2024 - 01 - 02), date!(2024 - 01 - 02)).unwrap_err();
+        assert_eq!(error.class(), ErrorClass::Permanent);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_and_merge_downloads() {
+        let dir = "test_output_merge_downloads";
+        fs::create_dir_all(dir).unwrap();
+        let minute = |day: u8| datetime!(2024 - 01 - 01 12:00 UTC).replace_day(day).unwrap().unix_timestamp_nanos() as u64;
+
+        let head = format!("{dir}/head.dbn.zst");
+        let tail = format!("{dir}/tail.dbn.zst");
+        write_bars(&head, &[minute(2), minute(3)]);
+        write_bars(&tail, &[minute(4)]);
+        assert_eq!(validate_download(&head, date!(2024 - 01 - 02), date!(2024 - 01 - 03)).unwrap(), 2);
+        assert!(validate_download(&tail, date!(2024 - 01 - 02), date!(2024 - 01 - 03)).is_err());
+
+        let merged = format!("{dir}/merged.dbn.zst");
+        merge_downloads(&[tail.into(), head.into()], &merged).unwrap();
+        assert_eq!(validate_download(&merged, date!(2024 - 01 - 02), date!(2024 - 01 - 04)).unwrap(), 3);
+        assert!(!part_path(&merged).exists());
+
+        fs::write(format!("{dir}/truncated.dbn.zst"), b"not dbn").unwrap();
+        let error = validate_download(format!("{dir}/truncated.dbn.zst"), date!(2024 - 01 - 02), date!(2024 - 01 - 04));
+        assert_eq!(error.unwrap_err().class(), ErrorClass::Transient);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_plan_download() {
+        let dir = "test_output_plan_download";
+        fs::create_dir_all(dir).unwrap();
+        touch(dir, "2024-01-05_2024-02-15_ESH2024.dbn.zst");
+        touch(dir, "2024-01-05_2024-03-15_ESH2024_ohlcv1m.json");
+
+        let plan = |start, end| plan_download(dir, "ESH2024", start, end).unwrap();
+
+        assert!(matches!(plan(date!(2024 - 01 - 10), date!(2024 - 02 - 15)), DownloadPlan::Covered(_)));
+        assert_eq!(plan(date!(2024 - 02 - 20), date!(2024 - 03 - 15)), DownloadPlan::Full);
+        match plan(date!(2024 - 01 - 01), date!(2024 - 03 - 15)) {
+            DownloadPlan::Extend { existing, missing } => {
+                assert_eq!(existing.end, date!(2024 - 02 - 15));
+                assert_eq!(
+                    missing,
+                    vec![
+                        (date!(2024 - 01 - 01), date!(2024 - 01 - 04)),
+                        (date!(2024 - 02 - 16), date!(2024 - 03 - 15))
+                    ]
+                );
+            }
+            other => panic!("Expected an extension, got {other:?}"),
+        }
+        assert_eq!(plan_download(dir, "ESM2024", date!(2024 - 01 - 10), date!(2024 - 02 - 15)).unwrap(), DownloadPlan::Full);
+
+        touch(dir, "2024-01-05_2024-02-15_ESM2024.dbn.zst.part");
+        touch(dir, "2024-01-05_2024-02-15_MESM2024.dbn.zst.part");
+        assert_eq!(remove_contract_partials(dir, "ESM2024").unwrap(), 1);
+        assert_eq!(remove_partial_downloads(dir).unwrap(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_part_path() {
+        assert_eq!(part_path("ES/a.dbn.zst"), PathBuf::from("ES/a.dbn.zst.part"));
+    }
+}
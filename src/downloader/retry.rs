@@ -0,0 +1,187 @@
+use std::{fmt, io, path::PathBuf, time::Duration};
+
+use databento::error::ApiError;
+
+/// Whether a failed request is worth repeating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Rate limits, server errors, timeouts and dropped connections.
+    Transient,
+    /// Unknown symbols, authentication failures and invalid requests; retrying cannot help.
+    Permanent,
+}
+
+/// A request that failed for good, after `attempts` tries.
+#[derive(Debug)]
+pub struct FetchError {
+    pub class: ErrorClass,
+    pub attempts: u32,
+    pub source: anyhow::Error,
+    /// Attempts whose response delivered data, which Databento bills even though it was not kept.
+    pub billed: u32,
+    /// Where a delivered download that failed validation was kept.
+    pub rejected: Option<PathBuf>,
+}
+
+/// How often and how patiently transient failures are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Tries per request, including the first.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for every further one.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 5, base_delay: Duration::from_secs(1), max_delay: Duration::from_secs(60) }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (1 for the first retry): exponential backoff with equal jitter,
+    /// so concurrent tasks that failed together do not retry together.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self.base_delay.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+        let capped = exponential.min(self.max_delay);
+        let half = capped / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+
+    /// Delay before retry number `retry` after `error`, preferring the delay the server asked for.
+    pub fn delay_for(&self, retry: u32, error: &databento::Error) -> Duration {
+        retry_after(error).map_or_else(|| self.backoff(retry), |delay| delay.min(self.max_delay))
+    }
+}
+
+/// Sorts a Databento client error into transient or permanent.
+pub fn classify(error: &databento::Error) -> ErrorClass {
+    match error {
+        databento::Error::Api(api) => classify_status(api.status_code.as_u16()),
+        databento::Error::Http(http) => {
+            if let Some(status) = http.status() {
+                classify_status(status.as_u16())
+            } else if http.is_builder() {
+                ErrorClass::Permanent
+            } else {
+                // Timeouts, refused or reset connections and bodies cut off mid-transfer
+                ErrorClass::Transient
+            }
+        }
+        databento::Error::Io(io) => classify_io(io),
+        databento::Error::Auth(_) | databento::Error::BadArgument { .. } => ErrorClass::Permanent,
+        // A response that does not decode was most likely truncated
+        databento::Error::Dbn(_) => ErrorClass::Transient,
+        _ => ErrorClass::Permanent,
+    }
+}
+
+fn classify_status(status: u16) -> ErrorClass {
+    match status {
+        408 | 429 | 500..=599 => ErrorClass::Transient,
+        _ => ErrorClass::Permanent,
+    }
+}
+
+fn classify_io(error: &io::Error) -> ErrorClass {
+    match error.kind() {
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::UnexpectedEof
+        | io::ErrorKind::Interrupted => ErrorClass::Transient,
+        _ => ErrorClass::Permanent,
+    }
+}
+
+/// Delay requested by a rate-limit response. The Databento client keeps the body but not the headers
+/// of failed responses, so this reads a `Retry-After: N` or "retry after N seconds" hint from the message.
+pub fn retry_after(error: &databento::Error) -> Option<Duration> {
+    let databento::Error::Api(ApiError { status_code, message, .. }) = error else {
+        return None;
+    };
+    if status_code.as_u16() != 429 && status_code.as_u16() != 503 {
+        return None;
+    }
+
+    let lower = message.to_ascii_lowercase();
+    let hint = lower.find("retry-after").or_else(|| lower.find("retry after"))?;
+    let seconds = lower[hint..]
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .find(|part| !part.is_empty() && part != &".")?
+        .parse::<f64>()
+        .ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorClass::Transient => write!(f, "transient"),
+            ErrorClass::Permanent => write!(f, "permanent"),
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let attempts = if self.attempts == 1 { "1 attempt".to_string() } else { format!("{} attempts", self.attempts) };
+        write!(f, "{} error after {attempts}: {:#}", self.class, self.source)
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(status: u16, message: &str) -> databento::Error {
+        databento::Error::Api(ApiError {
+            request_id: None,
+            status_code: status.try_into().unwrap(),
+            message: message.to_string(),
+            docs_url: None,
+        })
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(&api_error(429, "Too many requests")), ErrorClass::Transient);
+        assert_eq!(classify(&api_error(502, "Bad gateway")), ErrorClass::Transient);
+        assert_eq!(classify(&api_error(422, "Symbol not found")), ErrorClass::Permanent);
+        assert_eq!(classify(&api_error(401, "Unauthorized")), ErrorClass::Permanent);
+        assert_eq!(classify(&databento::Error::Auth("bad key".into())), ErrorClass::Permanent);
+        assert_eq!(
+            classify(&databento::Error::Io(io::Error::from(io::ErrorKind::ConnectionReset))),
+            ErrorClass::Transient
+        );
+        assert_eq!(classify(&databento::Error::Io(io::Error::from(io::ErrorKind::NotFound))), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn test_retry_after_hint() {
+        assert_eq!(retry_after(&api_error(429, "Rate limited, Retry-After: 7")), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&api_error(429, "Please retry after 2.5 seconds")), Some(Duration::from_secs_f64(2.5)));
+        assert_eq!(retry_after(&api_error(429, "retry after 99999999999999999999 seconds")), None);
+        assert_eq!(retry_after(&api_error(429, "Too many requests")), None);
+        assert_eq!(retry_after(&api_error(400, "retry after 3")), None);
+    }
+
+    #[test]
+    fn test_backoff_grows_with_jitter_and_cap() {
+        let policy = RetryPolicy { max_attempts: 5, base_delay: Duration::from_secs(2), max_delay: Duration::from_secs(10) };
+        for _ in 0..20 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_secs(1) && first <= Duration::from_secs(2));
+            let third = policy.backoff(3);
+            assert!(third >= Duration::from_secs(4) && third <= Duration::from_secs(8));
+            assert!(policy.backoff(10) <= Duration::from_secs(10));
+        }
+        assert_eq!(policy.delay_for(1, &api_error(429, "retry after 30")), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Date a contract is rolled out of: its last trading day or its first notice day.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RollAnchor {
+    #[default]
+    LastTrade,
+    /// Falls back to the last trading day for contracts without a first notice day.
+    FirstNotice,
+}
+
+/// How the download window of each contract is laid out around its roll date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ContractWindow {
+    /// Fixed window from `days_before` the roll date to `days_after` it (energy style).
+    BeforeExpiry { days_before: i64, days_after: i64 },
+    /// Each contract starts `overlap_days` before the previous roll date (index style).
+    /// The first contract in a request spans `first_span_days`.
+    Overlap { overlap_days: i64, first_span_days: i64 },
+}
+
+/// When a contract is rolled and which dates are downloaded around the roll.
+///
+/// In the product registry the two halves are the `roll_on` and `window` keys of a product.
+/// On the command line a policy is written as `WINDOW[@ANCHOR]`, for example
+/// `before_expiry:40:3`, `overlap:10:90@first_notice` or just `@first_notice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollPolicy {
+    #[serde(rename = "roll_on", default)]
+    pub anchor: RollAnchor,
+    pub window: ContractWindow,
+}
+
+impl RollPolicy {
+    pub fn new(anchor: RollAnchor, window: ContractWindow) -> Self {
+        Self { anchor, window }
+    }
+
+    /// Applies an override in `WINDOW[@ANCHOR]` form. Parts left out keep their current value.
+    pub fn with_override(&self, value: &str) -> Result<Self> {
+        if value.trim().is_empty() {
+            bail!("Empty roll policy");
+        }
+
+        let (window, anchor) = match value.trim().split_once('@') {
+            Some((window, anchor)) => (window.trim(), Some(anchor.trim())),
+            None => (value.trim(), None),
+        };
+
+        let window = if window.is_empty() {
+            self.window
+        } else {
+            parse_window(window)?
+        };
+        let anchor = match anchor {
+            Some(anchor) => parse_anchor(anchor)?,
+            None => self.anchor,
+        };
+
+        let policy = Self { anchor, window };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Rejects windows that cannot produce a usable download range.
+    pub fn validate(&self) -> Result<()> {
+        match self.window {
+            ContractWindow::BeforeExpiry { days_before, days_after } => {
+                if days_before <= 0 || days_after < 0 {
+                    bail!("before_expiry needs days_before > 0 and days_after >= 0");
+                }
+            }
+            ContractWindow::Overlap { overlap_days, first_span_days } => {
+                if overlap_days < 0 || first_span_days <= 0 {
+                    bail!("overlap needs overlap_days >= 0 and first_span_days > 0");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RollAnchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RollAnchor::LastTrade => write!(f, "last_trade"),
+            RollAnchor::FirstNotice => write!(f, "first_notice"),
+        }
+    }
+}
+
+impl fmt::Display for ContractWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContractWindow::BeforeExpiry { days_before, days_after } => {
+                write!(f, "before_expiry:{days_before}:{days_after}")
+            }
+            ContractWindow::Overlap { overlap_days, first_span_days } => {
+                write!(f, "overlap:{overlap_days}:{first_span_days}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for RollPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.window, self.anchor)
+    }
+}
+
+fn parse_anchor(value: &str) -> Result<RollAnchor> {
+    match value.replace('-', "_").as_str() {
+        "last_trade" => Ok(RollAnchor::LastTrade),
+        "first_notice" => Ok(RollAnchor::FirstNotice),
+        _ => bail!("Unknown roll anchor '{value}', expected last_trade or first_notice"),
+    }
+}
+
+fn parse_window(value: &str) -> Result<ContractWindow> {
+    let parts = value.split(':').map(str::trim).collect::<Vec<_>>();
+    let [kind, first, second] = parts.as_slice() else {
+        bail!("Expected KIND:DAYS:DAYS, got '{value}'");
+    };
+    let first = first.parse::<i64>().with_context(|| format!("Invalid day count '{first}'"))?;
+    let second = second.parse::<i64>().with_context(|| format!("Invalid day count '{second}'"))?;
+
+    match kind.replace('-', "_").as_str() {
+        "before_expiry" => Ok(ContractWindow::BeforeExpiry { days_before: first, days_after: second }),
+        "overlap" => Ok(ContractWindow::Overlap { overlap_days: first, first_span_days: second }),
+        _ => bail!("Unknown window kind '{kind}', expected before_expiry or overlap"),
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENERGY: RollPolicy = RollPolicy {
+        anchor: RollAnchor::LastTrade,
+        window: ContractWindow::BeforeExpiry { days_before: 40, days_after: 3 },
+    };
+
+    #[test]
+    fn test_override_replaces_window_and_keeps_anchor() {
+        let policy = ENERGY.with_override("overlap:5:60").unwrap();
+        assert_eq!(policy.anchor, RollAnchor::LastTrade);
+        assert_eq!(policy.window, ContractWindow::Overlap { overlap_days: 5, first_span_days: 60 });
+    }
+
+    #[test]
+    fn test_override_anchor_only() {
+        let policy = ENERGY.with_override("@first-notice").unwrap();
+        assert_eq!(policy.anchor, RollAnchor::FirstNotice);
+        assert_eq!(policy.window, ENERGY.window);
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let policy = RollPolicy::new(
+            RollAnchor::FirstNotice,
+            ContractWindow::Overlap { overlap_days: 10, first_span_days: 90 },
+        );
+        assert_eq!(policy.to_string(), "overlap:10:90@first_notice");
+        assert_eq!(ENERGY.with_override(&policy.to_string()).unwrap(), policy);
+    }
+
+    #[test]
+    fn test_invalid_overrides_are_rejected() {
+        assert!(ENERGY.with_override("").is_err());
+        assert!(ENERGY.with_override("overlap:10").is_err());
+        assert!(ENERGY.with_override("weekly:1:2").is_err());
+        assert!(ENERGY.with_override("@expiry").is_err());
+        assert!(ENERGY.with_override("before_expiry:0:3").is_err());
+    }
+}
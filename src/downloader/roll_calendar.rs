@@ -0,0 +1,529 @@
+use anyhow::{bail, Context, Result};
+use databento::dbn::{
+    decode::{DbnDecoder, DecodeRecord},
+    enums::StatType,
+    Schema, StatMsg, UNDEF_TIMESTAMP,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, fs, path::Path, str::FromStr};
+use time::{Date, Duration, OffsetDateTime};
+
+use crate::downloader::contracts::ContractPeriod;
+use crate::downloader::schemas::schema_dir;
+use crate::processor::{find_contract_files, list_contract_files, read_bars, ContractFile};
+
+/// CME Globex sessions open at 17:00 Chicago time on the previous calendar day (22:00 or 23:00 UTC).
+/// Shifting UTC timestamps forward by two hours puts every bar of a session on its trading date.
+const TRADING_DATE_SHIFT_HOURS: i64 = 2;
+
+/// Which daily statistic decides that liquidity has moved to the next contract.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RollSignal {
+    /// Summed 1-minute bar volume, read from downloaded OHLCV files.
+    #[default]
+    Volume,
+    /// Open interest from Databento `statistics` records.
+    OpenInterest,
+}
+
+/// Volume and open interest of one contract on one trading date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DailyActivity {
+    pub date: Date,
+    pub volume: u64,
+    pub open_interest: Option<u64>,
+}
+
+impl DailyActivity {
+    fn value(&self, signal: RollSignal) -> Option<u64> {
+        match signal {
+            RollSignal::Volume => Some(self.volume),
+            RollSignal::OpenInterest => self.open_interest,
+        }
+    }
+}
+
+/// Roll from one contract to the next. On `roll_date` the next contract becomes the active one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollEntry {
+    pub root: String,
+    /// Contract rolled out of, e.g. `CLZ2024`.
+    pub from: String,
+    /// Contract rolled into, e.g. `CLF2025`.
+    pub to: String,
+    /// Roll date given by the roll policy.
+    pub scheduled: Date,
+    /// Roll date in use: the detected crossover, or `scheduled` when none was found.
+    pub roll_date: Date,
+    /// Whether `roll_date` comes from the data rather than the calendar.
+    pub detected: bool,
+}
+
+/// Roll dates of consecutive contracts, shared by the downloader and the processor.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollCalendar {
+    pub signal: RollSignal,
+    pub entries: Vec<RollEntry>,
+}
+
+impl RollCalendar {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read roll calendar: {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid roll calendar: {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents)
+            .with_context(|| format!("Failed to write roll calendar: {}", path.display()))
+    }
+
+    /// Roll out of `contract` (four-digit-year name), if the calendar has one.
+    pub fn roll_from(&self, contract: &str) -> Option<&RollEntry> {
+        self.entries.iter().find(|entry| entry.from == contract)
+    }
+
+    /// Contract of `root` held on `date`.
+    pub fn active_contract(&self, root: &str, date: Date) -> Option<&str> {
+        let mut entries = self.entries.iter().filter(|entry| entry.root == root).collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.roll_date);
+
+        match entries.iter().find(|entry| date < entry.roll_date) {
+            Some(entry) => Some(entry.from.as_str()),
+            None => entries.last().map(|entry| entry.to.as_str()),
+        }
+    }
+
+    /// Moves the roll date of each period to the calendar's and shifts the end of its download window
+    /// by the same number of days. The next contract's window is widened to start no later than the roll.
+    pub fn apply(&self, periods: &mut [ContractPeriod]) {
+        for index in 0..periods.len() {
+            let Some(entry) = self.roll_from(&periods[index].contract_name()) else {
+                continue;
+            };
+            let shift = entry.roll_date - periods[index].roll;
+            let period = &mut periods[index];
+            period.roll = entry.roll_date;
+            period.end = (period.end + shift).max(period.roll);
+
+            if let Some(next) = periods.iter_mut().find(|next| next.contract_name() == entry.to) {
+                next.start = next.start.min(entry.roll_date);
+            }
+        }
+    }
+}
+
+impl fmt::Display for RollSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RollSignal::Volume => write!(f, "volume"),
+            RollSignal::OpenInterest => write!(f, "open_interest"),
+        }
+    }
+}
+
+impl FromStr for RollSignal {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.replace('-', "_").to_ascii_lowercase().as_str() {
+            "volume" => Ok(RollSignal::Volume),
+            "open_interest" | "oi" => Ok(RollSignal::OpenInterest),
+            _ => bail!("Unknown roll signal '{value}', expected volume or open_interest"),
+        }
+    }
+}
+
+/// First date on which `next` leads `front` for `confirm_days` consecutive shared trading dates.
+/// Only dates present in both series are compared.
+pub fn detect_crossover(
+    front: &[DailyActivity],
+    next: &[DailyActivity],
+    signal: RollSignal,
+    confirm_days: usize,
+) -> Option<Date> {
+    let front_by_date = front
+        .iter()
+        .filter_map(|day| Some((day.date, day.value(signal)?)))
+        .collect::<HashMap<_, _>>();
+
+    let mut shared = next
+        .iter()
+        .filter_map(|day| Some((day.date, day.value(signal)?, *front_by_date.get(&day.date)?)))
+        .collect::<Vec<_>>();
+    shared.sort_by_key(|&(date, _, _)| date);
+
+    let confirm_days = confirm_days.max(1);
+    let mut streak_start = None;
+    let mut streak = 0;
+    for (date, next_value, front_value) in shared {
+        if next_value > front_value {
+            streak_start.get_or_insert(date);
+            streak += 1;
+            if streak >= confirm_days {
+                return streak_start;
+            }
+        } else {
+            streak_start = None;
+            streak = 0;
+        }
+    }
+
+    None
+}
+
+/// Builds the roll calendar for consecutive periods of each product. Pairs without a crossover in
+/// `activity` (keyed by contract name) keep their scheduled roll date.
+pub fn build_roll_calendar(
+    periods: &[ContractPeriod],
+    activity: &HashMap<String, Vec<DailyActivity>>,
+    signal: RollSignal,
+    confirm_days: usize,
+) -> RollCalendar {
+    let mut by_root: HashMap<&str, Vec<&ContractPeriod>> = HashMap::new();
+    for period in periods {
+        by_root.entry(period.root.as_str()).or_default().push(period);
+    }
+
+    let mut entries = Vec::new();
+    for mut contracts in by_root.into_values() {
+        contracts.sort_by_key(|period| period.expiry);
+
+        for pair in contracts.windows(2) {
+            let (front, next) = (pair[0], pair[1]);
+            let (from, to) = (front.contract_name(), next.contract_name());
+
+            // Liquidity never moves after the front contract stops trading
+            let crossover = match (activity.get(&from), activity.get(&to)) {
+                (Some(front_days), Some(next_days)) => {
+                    detect_crossover(front_days, next_days, signal, confirm_days)
+                        .filter(|&date| date <= front.expiry)
+                }
+                _ => None,
+            };
+
+            entries.push(RollEntry {
+                root: front.root.clone(),
+                from,
+                to,
+                scheduled: front.roll,
+                roll_date: crossover.unwrap_or(front.roll),
+                detected: crossover.is_some(),
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.root.cmp(&b.root).then(a.roll_date.cmp(&b.roll_date)));
+    RollCalendar { signal, entries }
+}
+
+/// Trading date of a bar or statistic timestamped `ts` (nanoseconds since the UNIX epoch).
+pub fn trading_date(ts: u64) -> Option<Date> {
+    let timestamp = OffsetDateTime::from_unix_timestamp_nanos(ts as i128).ok()?;
+    Some((timestamp + Duration::hours(TRADING_DATE_SHIFT_HOURS)).date())
+}
+
+/// Daily volume summed from the 1-minute bars of a downloaded or decoded contract file.
+pub async fn daily_volume_from_file(file: &ContractFile) -> Result<Vec<DailyActivity>> {
+    let mut volume_by_date: HashMap<Date, u64> = HashMap::new();
+    for bar in read_bars(file).await? {
+        if let Some(date) = trading_date(bar.ts_event) {
+            *volume_by_date.entry(date).or_default() += bar.volume;
+        }
+    }
+    Ok(volume_days(volume_by_date))
+}
+
+fn volume_days(volume_by_date: HashMap<Date, u64>) -> Vec<DailyActivity> {
+    let mut days = volume_by_date
+        .into_iter()
+        .map(|(date, volume)| DailyActivity { date, volume, open_interest: None })
+        .collect::<Vec<_>>();
+    days.sort_by_key(|day| day.date);
+    days
+}
+
+/// Daily volume of every period with a one-minute bar file under `{base_path}/{root}`, keyed by contract
+/// name. Files are looked up by contract rather than by window, so downloads that were extended or made
+/// with another roll window are found. Contracts without a file are left out and keep their scheduled roll.
+pub async fn load_volume_activity(
+    base_path: &str,
+    periods: &[ContractPeriod],
+) -> Result<HashMap<String, Vec<DailyActivity>>> {
+    let mut files_by_root: HashMap<&str, Vec<ContractFile>> = HashMap::new();
+    let mut activity = HashMap::new();
+
+    for period in periods {
+        if !files_by_root.contains_key(period.root.as_str()) {
+            let dir = Path::new(base_path).join(&period.root);
+            let files = if dir.is_dir() { find_contract_files(&dir, &period.root)? } else { Vec::new() };
+            files_by_root.insert(&period.root, files);
+        }
+
+        let contract = period.contract_name();
+        let files = files_by_root[period.root.as_str()]
+            .iter()
+            .filter(|file| file.contract == contract)
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            continue;
+        }
+
+        // Overlapping windows hold the same bars, so a date keeps its largest total instead of a sum
+        let mut volume_by_date: HashMap<Date, u64> = HashMap::new();
+        for file in files {
+            for day in daily_volume_from_file(file).await? {
+                let volume = volume_by_date.entry(day.date).or_default();
+                *volume = (*volume).max(day.volume);
+            }
+        }
+        activity.insert(contract, volume_days(volume_by_date));
+    }
+
+    Ok(activity)
+}
+
+/// Daily open interest from the Databento `statistics` records of a downloaded raw file.
+pub fn daily_open_interest_from_file(path: impl AsRef<Path>) -> Result<Vec<DailyActivity>> {
+    let path = path.as_ref();
+    let mut decoder =
+        DbnDecoder::from_zstd_file(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    // The latest open interest published for a trading date wins
+    let mut oi_by_date: HashMap<Date, u64> = HashMap::new();
+    while let Some(stat) = decoder.decode_record::<StatMsg>()? {
+        if stat.stat_type != StatType::OpenInterest as u16 || stat.quantity < 0 {
+            continue;
+        }
+        let ts = if stat.ts_ref != UNDEF_TIMESTAMP { stat.ts_ref } else { stat.ts_recv };
+        if let Some(date) = trading_date(ts) {
+            oi_by_date.insert(date, stat.quantity as u64);
+        }
+    }
+
+    Ok(open_interest_days(oi_by_date))
+}
+
+fn open_interest_days(oi_by_date: HashMap<Date, u64>) -> Vec<DailyActivity> {
+    let mut days = oi_by_date
+        .into_iter()
+        .map(|(date, open_interest)| DailyActivity { date, volume: 0, open_interest: Some(open_interest) })
+        .collect::<Vec<_>>();
+    days.sort_by_key(|day| day.date);
+    days
+}
+
+/// Daily open interest of every period with a `statistics` file under `{base_path}/{root}/statistics`,
+/// keyed by contract name. The files are fetched, quoted and budgeted like any other download first;
+/// contracts without one are left out and keep their scheduled roll.
+pub fn load_open_interest_activity(
+    base_path: &str,
+    periods: &[ContractPeriod],
+) -> Result<HashMap<String, Vec<DailyActivity>>> {
+    let mut files_by_root: HashMap<&str, Vec<ContractFile>> = HashMap::new();
+    let mut activity = HashMap::new();
+
+    for period in periods {
+        if !files_by_root.contains_key(period.root.as_str()) {
+            let dir = schema_dir(base_path, &period.root, Schema::Statistics);
+            let files = if Path::new(&dir).is_dir() { list_contract_files(&dir)? } else { Vec::new() };
+            files_by_root.insert(&period.root, files);
+        }
+
+        let contract = period.contract_name();
+        let files = files_by_root[period.root.as_str()]
+            .iter()
+            .filter(|file| file.contract == contract && !file.is_decoded())
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            continue;
+        }
+
+        // Files are ordered by window; a later window's figure for a date replaces an earlier one
+        let mut oi_by_date: HashMap<Date, u64> = HashMap::new();
+        for file in files {
+            for day in daily_open_interest_from_file(&file.path)? {
+                oi_by_date.extend(day.open_interest.map(|open_interest| (day.date, open_interest)));
+            }
+        }
+        activity.insert(contract, open_interest_days(oi_by_date));
+    }
+
+    Ok(activity)
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::contracts::generate_contract_periods;
+    use crate::downloader::fixtures;
+    use time::macros::{date, datetime};
+
+    fn days(start: Date, volumes: &[u64]) -> Vec<DailyActivity> {
+        volumes
+            .iter()
+            .enumerate()
+            .map(|(i, &volume)| DailyActivity {
+                date: start + Duration::days(i as i64),
+                volume,
+                open_interest: Some(volume * 10),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_crossover_finds_first_leading_day() {
+        let start = date!(2024 - 03 - 01);
+        let front = days(start, &[900, 800, 500, 300, 100]);
+        let next = days(start, &[100, 200, 600, 700, 900]);
+
+        assert_eq!(detect_crossover(&front, &next, RollSignal::Volume, 1), Some(date!(2024 - 03 - 03)));
+        assert_eq!(detect_crossover(&front, &next, RollSignal::OpenInterest, 1), Some(date!(2024 - 03 - 03)));
+    }
+
+    #[test]
+    fn test_detect_crossover_requires_confirmation() {
+        let start = date!(2024 - 03 - 01);
+        let front = days(start, &[900, 100, 800, 300, 100]);
+        let next = days(start, &[100, 200, 600, 700, 900]);
+
+        assert_eq!(detect_crossover(&front, &next, RollSignal::Volume, 1), Some(date!(2024 - 03 - 02)));
+        assert_eq!(detect_crossover(&front, &next, RollSignal::Volume, 2), Some(date!(2024 - 03 - 04)));
+        assert_eq!(detect_crossover(&front, &next, RollSignal::Volume, 5), None);
+    }
+
+    #[test]
+    fn test_calendar_falls_back_to_scheduled_roll() {
+        let periods = generate_contract_periods("ES", date!(2024 - 01 - 01), date!(2024 - 12 - 31)).unwrap();
+        let h = periods[0].contract_name();
+        let m = periods[1].contract_name();
+
+        // ESH4 -> ESM4 crossover a week before expiry; no data for the later pairs
+        let crossover = periods[0].expiry - Duration::days(8);
+        let mut activity = HashMap::new();
+        activity.insert(h.clone(), days(crossover - Duration::days(3), &[900, 900, 900, 100, 100]));
+        activity.insert(m.clone(), days(crossover - Duration::days(3), &[100, 100, 100, 900, 900]));
+
+        let calendar = build_roll_calendar(&periods, &activity, RollSignal::Volume, 1);
+        assert_eq!(calendar.entries.len(), periods.len() - 1);
+
+        let first = calendar.roll_from(&h).unwrap();
+        assert_eq!((first.to.as_str(), first.roll_date, first.detected), (m.as_str(), crossover, true));
+
+        let second = calendar.roll_from(&m).unwrap();
+        assert!(!second.detected);
+        assert_eq!(second.roll_date, periods[1].roll);
+
+        assert_eq!(calendar.active_contract("ES", crossover - Duration::days(1)), Some(h.as_str()));
+        assert_eq!(calendar.active_contract("ES", crossover), Some(m.as_str()));
+    }
+
+    #[test]
+    fn test_apply_moves_window_end_with_roll() {
+        let mut periods = generate_contract_periods("CL", date!(2024 - 01 - 01), date!(2024 - 03 - 31)).unwrap();
+        let original = periods.clone();
+        let roll_date = original[0].roll - Duration::days(5);
+        let calendar = RollCalendar {
+            signal: RollSignal::Volume,
+            entries: vec![RollEntry {
+                root: "CL".to_string(),
+                from: original[0].contract_name(),
+                to: original[1].contract_name(),
+                scheduled: original[0].roll,
+                roll_date,
+                detected: true,
+            }],
+        };
+
+        calendar.apply(&mut periods);
+        assert_eq!(periods[0].roll, roll_date);
+        assert_eq!(periods[0].end, original[0].end - Duration::days(5));
+        assert_eq!(periods[1..], original[1..]);
+    }
+
+    #[tokio::test]
+    async fn test_volume_activity_finds_files_by_contract() {
+        let dir = "test_output_volume_activity";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{dir}/ES")).unwrap();
+        let periods = generate_contract_periods("ES", date!(2024 - 01 - 01), date!(2024 - 06 - 30)).unwrap();
+
+        // Extended by a month at the head, so the name no longer matches the scheduled window
+        let period = &periods[0];
+        let start = period.start - Duration::days(30);
+        let path = format!("{dir}/ES/{start}_{}_{}_ohlcv1m.json", period.end, period.contract_name());
+        let bar = |ts_event: u64, volume: u64| {
+            serde_json::json!({
+                "instrument_name": period.contract_name(), "instrument_id": 1, "ts_event": ts_event,
+                "open": 1, "high": 1, "low": 1, "close": 1, "volume": volume,
+            })
+            .to_string()
+        };
+        let monday = datetime!(2024 - 03 - 11 15:00 UTC).unix_timestamp_nanos() as u64;
+        fs::write(&path, [bar(monday, 5), bar(monday + 60_000_000_000, 7)].join("\n")).unwrap();
+
+        let activity = load_volume_activity(dir, &periods).await.unwrap();
+        assert_eq!(activity.len(), 1);
+        assert_eq!(
+            activity[&period.contract_name()],
+            vec![DailyActivity { date: date!(2024 - 03 - 11), volume: 12, open_interest: None }]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_interest_activity_reads_statistics_files() {
+        let dir = "test_output_open_interest_activity";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{dir}/ES/statistics")).unwrap();
+        let periods = generate_contract_periods("ES", date!(2024 - 01 - 01), date!(2024 - 06 - 30)).unwrap();
+
+        let period = &periods[0];
+        let path = format!("{dir}/ES/statistics/{}_{}_{}.dbn.zst", period.start, period.end, period.contract_name());
+        let monday = datetime!(2024 - 03 - 11 15:00 UTC).unix_timestamp_nanos() as u64;
+        let stat = |stat_type: StatType, ts_ref: u64, quantity| StatMsg {
+            stat_type: stat_type as u16,
+            ts_ref,
+            quantity,
+            ..Default::default()
+        };
+        // A later figure for the same date replaces the earlier one; other statistics are ignored
+        let records = [
+            stat(StatType::OpenInterest, monday, 100),
+            stat(StatType::SettlementPrice, monday, 1),
+            stat(StatType::OpenInterest, monday + 3_600_000_000_000, 120),
+        ];
+        fixtures::write_dbn(&path, Schema::Statistics, &records);
+
+        let activity = load_open_interest_activity(dir, &periods).unwrap();
+        assert_eq!(activity.len(), 1);
+        assert_eq!(
+            activity[&period.contract_name()],
+            vec![DailyActivity { date: date!(2024 - 03 - 11), volume: 0, open_interest: Some(120) }]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_trading_date_puts_evening_session_on_next_day() {
+        let sunday_open = datetime!(2024 - 03 - 10 22:00 UTC).unix_timestamp_nanos() as u64;
+        let monday_close = datetime!(2024 - 03 - 11 20:59 UTC).unix_timestamp_nanos() as u64;
+        assert_eq!(trading_date(sunday_open), Some(date!(2024 - 03 - 11)));
+        assert_eq!(trading_date(monday_close), Some(date!(2024 - 03 - 11)));
+    }
+
+    #[test]
+    fn test_roll_signal_parses() {
+        assert_eq!("open-interest".parse::<RollSignal>().unwrap(), RollSignal::OpenInterest);
+        assert_eq!("Volume".parse::<RollSignal>().unwrap(), RollSignal::Volume);
+        assert!("price".parse::<RollSignal>().is_err());
+    }
+}
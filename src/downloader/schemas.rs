@@ -0,0 +1,63 @@
+use databento::dbn::Schema;
+
+/// Schemas the downloader and decoder are offered for, in the order the GUI lists them.
+pub const SUPPORTED_SCHEMAS: [Schema; 12] = [
+    Schema::Ohlcv1M,
+    Schema::Ohlcv1S,
+    Schema::Ohlcv1H,
+    Schema::Ohlcv1D,
+    Schema::Trades,
+    Schema::Tbbo,
+    Schema::Mbp1,
+    Schema::Mbp10,
+    Schema::Mbo,
+    Schema::Definition,
+    Schema::Statistics,
+    Schema::Status,
+];
+
+/// Bars decode to `JsonOhlcv` lines; every other schema keeps Databento's own JSON layout.
+pub fn is_ohlcv(schema: Schema) -> bool {
+    matches!(schema, Schema::Ohlcv1S | Schema::Ohlcv1M | Schema::Ohlcv1H | Schema::Ohlcv1D)
+}
+
+/// Folder the contract files of `root` in `schema` are stored in. One-minute bars live directly in
+/// `{base}/{root}`, where the processors look for them; other schemas get a `{base}/{root}/{schema}` subfolder.
+pub fn schema_dir(base_path: &str, root: &str, schema: Schema) -> String {
+    match schema {
+        Schema::Ohlcv1M => format!("{base_path}/{root}"),
+        schema => format!("{base_path}/{root}/{schema}"),
+    }
+}
+
+/// Schema name as used in decoded file names, e.g. `ohlcv1m` or `mbp10`.
+pub fn schema_slug(schema: Schema) -> String {
+    schema.as_str().replace('-', "")
+}
+
+/// Suffix of a decoded file, e.g. `_ohlcv1m.json` or `_trades.json`.
+pub fn decoded_suffix(schema: Schema) -> String {
+    format!("_{}.json", schema_slug(schema))
+}
+
+/// Schema of a decoded file name, from its `_{schema}.json` suffix.
+pub fn decoded_schema(file_name: &str) -> Option<Schema> {
+    SUPPORTED_SCHEMAS.into_iter().find(|&schema| file_name.ends_with(&decoded_suffix(schema)))
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_paths() {
+        assert_eq!(schema_dir("Hist_Fut_Data", "ES", Schema::Ohlcv1M), "Hist_Fut_Data/ES");
+        assert_eq!(schema_dir("Hist_Fut_Data", "ES", Schema::Mbp10), "Hist_Fut_Data/ES/mbp-10");
+        assert_eq!(decoded_suffix(Schema::Ohlcv1M), "_ohlcv1m.json");
+        assert_eq!(decoded_suffix(Schema::Mbp1), "_mbp1.json");
+        assert_eq!(decoded_schema("2024-01-05_2024-03-15_ESH2024_mbp10.json"), Some(Schema::Mbp10));
+        assert_eq!(decoded_schema("2024-01-05_2024-03-15_ESH2024_ohlcv1m.json"), Some(Schema::Ohlcv1M));
+        assert_eq!(decoded_schema("2024-01-05_2024-03-15_ESH2024.dbn.zst"), None);
+    }
+}
@@ -0,0 +1,404 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use time::{macros::{date, time}, Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset, Weekday};
+
+use crate::downloader::calendar::{cme_calendar, is_us_daylight_time, us_central_offset};
+
+/// Name of the template products fall back to when the registry does not name one.
+pub const DEFAULT_SESSION: &str = "globex";
+
+/// Holidays on which the exchange shuts every product for the whole day.
+const FULL_CLOSURES: [&str; 3] = ["New Year's Day", "Good Friday", "Christmas Day"];
+
+/// Exchange clock a session template is written in. Both follow US daylight saving rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExchangeTimezone {
+    Chicago,
+    NewYork,
+}
+
+impl ExchangeTimezone {
+    /// UTC offset on `date` (daylight or standard time for the whole trading day).
+    pub fn offset(&self, date: Date) -> UtcOffset {
+        match self {
+            ExchangeTimezone::Chicago => us_central_offset(date),
+            ExchangeTimezone::NewYork => {
+                let hours = if is_us_daylight_time(date) { -4 } else { -5 };
+                UtcOffset::from_hms(hours, 0, 0).expect("Valid offset")
+            }
+        }
+    }
+
+    /// Instant of local `time` on `date`.
+    pub fn at(&self, date: Date, time: Time) -> OffsetDateTime {
+        date.with_time(time).assume_offset(self.offset(date))
+    }
+
+    /// UTC offset in effect at `instant`.
+    pub fn offset_at(&self, instant: OffsetDateTime) -> UtcOffset {
+        // Offsets change at 02:00 local, when no session is open; the standard-time date is close enough
+        let standard = instant.to_offset(self.offset(instant.date()));
+        self.offset(standard.date())
+    }
+
+    /// Local wall-clock time of `instant`.
+    pub fn to_local(&self, instant: OffsetDateTime) -> PrimitiveDateTime {
+        let local = instant.to_offset(self.offset_at(instant));
+        PrimitiveDateTime::new(local.date(), local.time())
+    }
+}
+
+/// What a product does on exchange holidays other than the full closures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HolidayTrading {
+    /// Trades with an early close (CME Globex equity, energy, metals, rates and FX).
+    EarlyClose,
+    /// Does not trade (CBOT grains).
+    Closed,
+}
+
+/// Trading hours of a group of products, in exchange local time. A session trading for date D opens
+/// at `open` on the calendar day before D and closes at `close` on D; `breaks` and `regular_hours`
+/// at or after `open` fall on the evening before, earlier ones on D.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionTemplate {
+    pub name: &'static str,
+    pub timezone: ExchangeTimezone,
+    pub open: Time,
+    pub close: Time,
+    /// Regular trading hours (the former pit session).
+    pub regular_hours: (Time, Time),
+    /// Intraday halts and maintenance windows inside the session.
+    pub breaks: &'static [SessionBreak],
+    /// Close on early-close days.
+    pub early_close: Time,
+    pub holiday_trading: HolidayTrading,
+}
+
+/// An intraday halt in exchange local time, possibly retired after some trading date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionBreak {
+    pub start: Time,
+    pub end: Time,
+    /// Last trading date the halt applied to; `None` while it is still in effect.
+    pub until: Option<Date>,
+}
+
+impl SessionBreak {
+    const fn new(start: Time, end: Time) -> Self {
+        SessionBreak { start, end, until: None }
+    }
+
+    const fn until(self, last_date: Date) -> Self {
+        SessionBreak { until: Some(last_date), ..self }
+    }
+
+    pub fn applies_to(&self, trading_date: Date) -> bool {
+        self.until.is_none_or(|last_date| trading_date <= last_date)
+    }
+}
+
+/// Built-in templates, referenced by name from the `session` key of the product registry.
+pub static SESSION_TEMPLATES: &[SessionTemplate] = &[
+    SessionTemplate {
+        name: DEFAULT_SESSION,
+        timezone: ExchangeTimezone::Chicago,
+        open: time!(17:00),
+        close: time!(16:00),
+        regular_hours: (time!(08:30), time!(15:00)),
+        breaks: &[],
+        early_close: time!(12:00),
+        holiday_trading: HolidayTrading::EarlyClose,
+    },
+    SessionTemplate {
+        name: "equity_index",
+        timezone: ExchangeTimezone::Chicago,
+        open: time!(17:00),
+        close: time!(16:00),
+        regular_hours: (time!(08:30), time!(15:00)),
+        // CME dropped the 15:15-15:30 halt from 2021-06-28
+        breaks: &[SessionBreak::new(time!(15:15), time!(15:30)).until(date!(2021 - 06 - 25))],
+        early_close: time!(12:00),
+        holiday_trading: HolidayTrading::EarlyClose,
+    },
+    SessionTemplate {
+        name: "energy",
+        timezone: ExchangeTimezone::Chicago,
+        open: time!(17:00),
+        close: time!(16:00),
+        regular_hours: (time!(08:00), time!(13:30)),
+        breaks: &[],
+        early_close: time!(12:45),
+        holiday_trading: HolidayTrading::EarlyClose,
+    },
+    SessionTemplate {
+        name: "metals",
+        timezone: ExchangeTimezone::Chicago,
+        open: time!(17:00),
+        close: time!(16:00),
+        regular_hours: (time!(07:20), time!(12:30)),
+        breaks: &[],
+        early_close: time!(12:45),
+        holiday_trading: HolidayTrading::EarlyClose,
+    },
+    SessionTemplate {
+        name: "interest_rates",
+        timezone: ExchangeTimezone::Chicago,
+        open: time!(17:00),
+        close: time!(16:00),
+        regular_hours: (time!(07:20), time!(14:00)),
+        breaks: &[],
+        early_close: time!(12:00),
+        holiday_trading: HolidayTrading::EarlyClose,
+    },
+    SessionTemplate {
+        name: "grains",
+        timezone: ExchangeTimezone::Chicago,
+        open: time!(19:00),
+        close: time!(13:20),
+        regular_hours: (time!(08:30), time!(13:20)),
+        breaks: &[SessionBreak::new(time!(07:45), time!(08:30))],
+        early_close: time!(12:05),
+        holiday_trading: HolidayTrading::Closed,
+    },
+    SessionTemplate {
+        name: "fx",
+        timezone: ExchangeTimezone::Chicago,
+        open: time!(17:00),
+        close: time!(16:00),
+        regular_hours: (time!(07:20), time!(14:00)),
+        breaks: &[],
+        early_close: time!(12:00),
+        holiday_trading: HolidayTrading::EarlyClose,
+    },
+];
+
+/// Built-in session template called `name`.
+pub fn session_template(name: &str) -> Option<&'static SessionTemplate> {
+    SESSION_TEMPLATES.iter().find(|template| template.name == name)
+}
+
+/// Where an instant falls relative to a product's trading hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionPhase {
+    /// Regular trading hours.
+    Regular,
+    /// Electronic trading outside regular hours.
+    Extended,
+    /// Intraday halt or maintenance window.
+    Break,
+    /// No session is open.
+    Closed,
+}
+
+/// One trading day of a product, as UTC instants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TradingSession {
+    pub trading_date: Date,
+    pub open: OffsetDateTime,
+    pub close: OffsetDateTime,
+    /// Regular trading hours, cut short on early-close days. `None` if the session closes before they start.
+    pub regular_hours: Option<(OffsetDateTime, OffsetDateTime)>,
+    pub breaks: Vec<(OffsetDateTime, OffsetDateTime)>,
+    pub early_close: bool,
+}
+
+impl TradingSession {
+    pub fn contains(&self, instant: OffsetDateTime) -> bool {
+        instant >= self.open && instant < self.close
+    }
+
+    pub fn phase(&self, instant: OffsetDateTime) -> SessionPhase {
+        if !self.contains(instant) {
+            SessionPhase::Closed
+        } else if self.breaks.iter().any(|&(start, end)| instant >= start && instant < end) {
+            SessionPhase::Break
+        } else if self.regular_hours.is_some_and(|(start, end)| instant >= start && instant < end) {
+            SessionPhase::Regular
+        } else {
+            SessionPhase::Extended
+        }
+    }
+
+    /// Minutes in which the product trades: the session less its breaks.
+    pub fn trading_minutes(&self) -> i64 {
+        let breaks = self
+            .breaks
+            .iter()
+            .map(|&(start, end)| (end.min(self.close) - start.max(self.open)).whole_minutes().max(0))
+            .sum::<i64>();
+        (self.close - self.open).whole_minutes() - breaks
+    }
+}
+
+impl SessionTemplate {
+    /// Whether the session for `trading_date` closes early: CME holidays the product trades through,
+    /// the day after Thanksgiving, Christmas Eve and July 3.
+    pub fn is_early_close(&self, trading_date: Date) -> bool {
+        let calendar = cme_calendar();
+        if let Some(name) = calendar.holiday_name(trading_date) {
+            return !FULL_CLOSURES.contains(&name) && self.holiday_trading == HolidayTrading::EarlyClose;
+        }
+
+        let year = trading_date.year();
+        let day_after_thanksgiving = calendar
+            .holiday_date("Thanksgiving Day", year)
+            .and_then(|thanksgiving| thanksgiving.next_day());
+        let eves = [(Month::December, 24), (Month::July, 3)];
+
+        Some(trading_date) == day_after_thanksgiving
+            || eves.iter().any(|&(month, day)| trading_date.month() == month && trading_date.day() == day)
+    }
+
+    /// Session trading for `trading_date`, or `None` on weekends and days the product does not trade.
+    pub fn session(&self, trading_date: Date) -> Option<TradingSession> {
+        if matches!(trading_date.weekday(), Weekday::Saturday | Weekday::Sunday) {
+            return None;
+        }
+        if let Some(name) = cme_calendar().holiday_name(trading_date)
+            && (FULL_CLOSURES.contains(&name) || name == "Exchange closure" || self.holiday_trading == HolidayTrading::Closed)
+        {
+            return None;
+        }
+
+        let evening = trading_date.previous_day()?;
+        let local = |time: Time| {
+            let date = if time >= self.open { evening } else { trading_date };
+            self.timezone.at(date, time)
+        };
+
+        let early_close = self.is_early_close(trading_date);
+        let open = local(self.open);
+        let close = local(if early_close { self.early_close.min(self.close) } else { self.close });
+
+        let (regular_start, regular_end) = (local(self.regular_hours.0), local(self.regular_hours.1).min(close));
+        let regular_hours = (regular_start < regular_end).then_some((regular_start, regular_end));
+
+        let breaks = self
+            .breaks
+            .iter()
+            .filter(|halt| halt.applies_to(trading_date))
+            .map(|halt| (local(halt.start), local(halt.end)))
+            .filter(|&(start, _)| start < close)
+            .collect();
+
+        Some(TradingSession { trading_date, open, close, regular_hours, breaks, early_close })
+    }
+
+    /// Session open at `instant`, if any.
+    pub fn session_at(&self, instant: OffsetDateTime) -> Option<TradingSession> {
+        let local_date = self.timezone.to_local(instant).date();
+        [Some(local_date), local_date.next_day()]
+            .into_iter()
+            .flatten()
+            .filter_map(|date| self.session(date))
+            .find(|session| session.contains(instant))
+    }
+
+    pub fn phase_at(&self, instant: OffsetDateTime) -> SessionPhase {
+        self.session_at(instant).map_or(SessionPhase::Closed, |session| session.phase(instant))
+    }
+
+    /// Sessions trading on `start..=end`.
+    pub fn sessions_between(&self, start: Date, end: Date) -> Vec<TradingSession> {
+        let mut sessions = Vec::new();
+        let mut date = start;
+        while date <= end {
+            sessions.extend(self.session(date));
+            date += Duration::days(1);
+        }
+        sessions
+    }
+}
+
+impl fmt::Display for SessionPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionPhase::Regular => write!(f, "regular"),
+            SessionPhase::Extended => write!(f, "extended"),
+            SessionPhase::Break => write!(f, "break"),
+            SessionPhase::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::{date, datetime};
+
+    fn template(name: &str) -> &'static SessionTemplate {
+        session_template(name).unwrap()
+    }
+
+    #[test]
+    fn test_equity_session_in_summer_and_winter() {
+        let summer = template("equity_index").session(date!(2024 - 07 - 09)).unwrap();
+        assert_eq!(summer.open, datetime!(2024 - 07 - 08 22:00 UTC));
+        assert_eq!(summer.close, datetime!(2024 - 07 - 09 21:00 UTC));
+        assert_eq!(summer.regular_hours, Some((datetime!(2024 - 07 - 09 13:30 UTC), datetime!(2024 - 07 - 09 20:00 UTC))));
+
+        let winter = template("equity_index").session(date!(2024 - 01 - 09)).unwrap();
+        assert_eq!(winter.open, datetime!(2024 - 01 - 08 23:00 UTC));
+        assert_eq!(winter.trading_minutes(), 23 * 60);
+
+        // Before the 15:15-15:30 halt was dropped
+        let halted = template("equity_index").session(date!(2021 - 01 - 12)).unwrap();
+        assert_eq!(halted.trading_minutes(), 23 * 60 - 15);
+    }
+
+    #[test]
+    fn test_phases() {
+        let equity = template("equity_index");
+        assert_eq!(equity.phase_at(datetime!(2024 - 07 - 09 14:00 UTC)), SessionPhase::Regular);
+        assert_eq!(equity.phase_at(datetime!(2024 - 07 - 09 02:00 UTC)), SessionPhase::Extended);
+        assert_eq!(equity.phase_at(datetime!(2024 - 07 - 09 20:20 UTC)), SessionPhase::Extended);
+        assert_eq!(equity.phase_at(datetime!(2021 - 06 - 25 20:20 UTC)), SessionPhase::Break);
+        assert_eq!(equity.phase_at(datetime!(2021 - 06 - 28 20:20 UTC)), SessionPhase::Extended);
+        assert_eq!(equity.phase_at(datetime!(2024 - 07 - 09 21:30 UTC)), SessionPhase::Closed);
+        // Saturday
+        assert_eq!(equity.phase_at(datetime!(2024 - 07 - 13 15:00 UTC)), SessionPhase::Closed);
+    }
+
+    #[test]
+    fn test_sunday_evening_belongs_to_monday() {
+        let session = template("energy").session_at(datetime!(2024 - 07 - 07 23:00 UTC)).unwrap();
+        assert_eq!(session.trading_date, date!(2024 - 07 - 08));
+    }
+
+    #[test]
+    fn test_holidays_close_early_or_not_at_all() {
+        let equity = template("equity_index");
+        let grains = template("grains");
+
+        // Independence Day: Globex closes early, grains do not trade
+        let july_4 = equity.session(date!(2024 - 07 - 04)).unwrap();
+        assert!(july_4.early_close);
+        assert_eq!(july_4.close, datetime!(2024 - 07 - 04 17:00 UTC));
+        assert!(grains.session(date!(2024 - 07 - 04)).is_none());
+
+        // Full closures and the day after Thanksgiving
+        assert!(equity.session(date!(2024 - 12 - 25)).is_none());
+        assert!(equity.session(date!(2024 - 03 - 29)).is_none());
+        assert!(equity.session(date!(2024 - 11 - 29)).unwrap().early_close);
+        assert!(grains.session(date!(2024 - 11 - 29)).unwrap().early_close);
+    }
+
+    #[test]
+    fn test_grains_have_an_overnight_break() {
+        let session = template("grains").session(date!(2024 - 01 - 09)).unwrap();
+        assert_eq!(session.open, datetime!(2024 - 01 - 09 01:00 UTC));
+        assert_eq!(session.breaks, vec![(datetime!(2024 - 01 - 09 13:45 UTC), datetime!(2024 - 01 - 09 14:30 UTC))]);
+        assert_eq!(session.phase(datetime!(2024 - 01 - 09 14:00 UTC)), SessionPhase::Break);
+        assert_eq!(session.phase(datetime!(2024 - 01 - 09 15:00 UTC)), SessionPhase::Regular);
+    }
+
+    #[test]
+    fn test_new_york_timezone() {
+        let ny = ExchangeTimezone::NewYork;
+        assert_eq!(ny.at(date!(2024 - 07 - 09), time!(09:30)), datetime!(2024 - 07 - 09 13:30 UTC));
+        assert_eq!(ny.to_local(datetime!(2024 - 01 - 09 14:30 UTC)).time(), time!(09:30));
+    }
+}
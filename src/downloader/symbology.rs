@@ -0,0 +1,159 @@
+use anyhow::{bail, Context, Result};
+use databento::{
+    dbn::{Metadata, Record, RecordRef, SType, TsSymbolMap},
+    historical::{
+        symbology::{ResolveParams, Resolution},
+        DateRange,
+    },
+    Symbols,
+};
+use std::collections::BTreeSet;
+use time::OffsetDateTime;
+
+use crate::client::DBClient;
+
+/// Raw symbols of the instruments in a DBN file: which contract each instrument ID stood for over which dates.
+/// Records are labelled by the mapping in force on their day rather than by the file they came from, so spread
+/// legs and mid-file symbology changes keep their own symbol.
+#[derive(Debug, Clone)]
+pub struct InstrumentSymbols {
+    map: TsSymbolMap,
+}
+
+impl InstrumentSymbols {
+    /// Symbols of the instruments of a file with `metadata`. The mappings of a raw-symbol request already name
+    /// each instrument's raw symbol; those of parent or continuous requests name the requested symbol instead, so
+    /// their instrument IDs are resolved to raw symbols through the Databento symbology API.
+    pub async fn load(metadata: &Metadata) -> Result<Self> {
+        if metadata.stype_in == Some(SType::RawSymbol) {
+            return Self::from_metadata(metadata);
+        }
+        let params = resolve_params(metadata)?;
+        let resolution = DBClient::new()
+            .get_mut()
+            .symbology()
+            .resolve(&params)
+            .await
+            .context("Failed to resolve instrument IDs to raw symbols")?;
+        Self::from_resolution(&resolution)
+    }
+
+    /// Symbols from the mappings of a raw-symbol request.
+    pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        if metadata.stype_in != Some(SType::RawSymbol) {
+            bail!("Symbology mappings of {:?} requests do not name raw symbols", metadata.stype_in);
+        }
+        let map = metadata.symbol_map().context("Invalid symbology mappings in DBN metadata")?;
+        Ok(Self { map })
+    }
+
+    /// Symbols from a resolution of instrument IDs to raw symbols.
+    pub fn from_resolution(resolution: &Resolution) -> Result<Self> {
+        if (resolution.stype_in, resolution.stype_out) != (SType::InstrumentId, SType::RawSymbol) {
+            bail!("Expected a resolution from instrument IDs to raw symbols");
+        }
+        let map = resolution.symbol_map().context("Invalid symbology resolution")?;
+        Ok(Self { map })
+    }
+
+    /// Symbol of the instrument of `record` on the UTC day of its `ts_event`.
+    pub fn symbol(&self, record: &RecordRef) -> Result<&str> {
+        self.symbol_at(record.header().instrument_id, record.header().ts_event)
+    }
+
+    /// Symbol of `instrument_id` on the UTC day of `ts_event`. Fails when no mapping covers that day, rather than
+    /// guessing a label.
+    pub fn symbol_at(&self, instrument_id: u32, ts_event: u64) -> Result<&str> {
+        let date = OffsetDateTime::from_unix_timestamp_nanos(ts_event as i128)
+            .with_context(|| format!("Invalid ts_event {ts_event}"))?
+            .date();
+        self.map
+            .get(date, instrument_id)
+            .map(|symbol| symbol.as_str())
+            .with_context(|| format!("Instrument {instrument_id} has no symbol mapping on {date}"))
+    }
+}
+
+/// Resolution of every instrument ID in the mappings of `metadata` to raw symbols, over the dates they cover.
+fn resolve_params(metadata: &Metadata) -> Result<ResolveParams> {
+    let intervals = metadata.mappings.iter().flat_map(|mapping| &mapping.intervals).collect::<Vec<_>>();
+    let ids = intervals
+        .iter()
+        .filter(|interval| !interval.symbol.is_empty())
+        .map(|interval| interval.symbol.parse::<u32>())
+        .collect::<Result<BTreeSet<_>, _>>()
+        .context("Symbology mappings in DBN metadata do not name instrument IDs")?;
+    let (Some(start), Some(end)) = (
+        intervals.iter().map(|interval| interval.start_date).min(),
+        intervals.iter().map(|interval| interval.end_date).max(),
+    ) else {
+        bail!("DBN metadata has no symbology mappings to resolve");
+    };
+    Ok(ResolveParams::builder()
+        .dataset(&metadata.dataset)
+        .symbols(Symbols::Ids(ids.into_iter().collect()))
+        .stype_in(SType::InstrumentId)
+        .stype_out(SType::RawSymbol)
+        .date_range(DateRange::from((start, end)))
+        .build())
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use databento::dbn::{MappingInterval, SymbolMapping};
+    use std::collections::HashMap;
+    use time::macros::{date, datetime};
+
+    fn interval(start_date: time::Date, end_date: time::Date, symbol: &str) -> MappingInterval {
+        MappingInterval { start_date, end_date, symbol: symbol.to_string() }
+    }
+
+    fn ts(at: OffsetDateTime) -> u64 {
+        at.unix_timestamp_nanos() as u64
+    }
+
+    #[test]
+    fn test_continuous_mappings_resolve_to_raw_symbols() {
+        // ES.c.0 pointed at ESH4 (id 10) until the March roll, then at ESM4 (id 20)
+        let metadata = Metadata::builder()
+            .dataset("GLBX.MDP3")
+            .schema(None)
+            .start(ts(datetime!(2024 - 03 - 01 00:00 UTC)))
+            .stype_in(Some(SType::Continuous))
+            .stype_out(SType::InstrumentId)
+            .mappings(vec![SymbolMapping {
+                raw_symbol: "ES.c.0".to_string(),
+                intervals: vec![
+                    interval(date!(2024 - 03 - 01), date!(2024 - 03 - 14), "10"),
+                    interval(date!(2024 - 03 - 14), date!(2024 - 04 - 01), "20"),
+                ],
+            }])
+            .build();
+        // The mappings name the requested symbol, so they cannot label records themselves
+        assert!(InstrumentSymbols::from_metadata(&metadata).is_err());
+
+        let params = resolve_params(&metadata).unwrap();
+        assert_eq!(params.symbols, Symbols::Ids(vec![10, 20]));
+        assert_eq!((params.stype_in, params.stype_out), (SType::InstrumentId, SType::RawSymbol));
+        assert_eq!(params.date_range, DateRange::from((date!(2024 - 03 - 01), date!(2024 - 04 - 01))));
+
+        let resolution = Resolution {
+            mappings: HashMap::from([
+                ("10".to_string(), vec![interval(date!(2024 - 03 - 01), date!(2024 - 04 - 01), "ESH4")]),
+                ("20".to_string(), vec![interval(date!(2024 - 03 - 01), date!(2024 - 04 - 01), "ESM4")]),
+            ]),
+            partial: Vec::new(),
+            not_found: Vec::new(),
+            stype_in: SType::InstrumentId,
+            stype_out: SType::RawSymbol,
+        };
+        let symbols = InstrumentSymbols::from_resolution(&resolution).unwrap();
+        assert_eq!(symbols.symbol_at(10, ts(datetime!(2024 - 03 - 13 20:00 UTC))).unwrap(), "ESH4");
+        assert_eq!(symbols.symbol_at(20, ts(datetime!(2024 - 03 - 14 14:00 UTC))).unwrap(), "ESM4");
+        // Unknown instruments and days outside the resolution are errors, not a guessed label
+        assert!(symbols.symbol_at(99, ts(datetime!(2024 - 03 - 13 20:00 UTC))).is_err());
+        assert!(symbols.symbol_at(10, ts(datetime!(2024 - 04 - 02 14:00 UTC))).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::{bail, Context, Result};
+use arrow::{
+    array::{Array, ArrayRef, AsArray},
+    datatypes::{
+        DataType, Float64Type, Int32Type, Int64Type, TimeUnit, TimestampNanosecondType, UInt16Type, UInt32Type,
+        UInt64Type, UInt8Type,
+    },
+};
+use databento::dbn::{Record, RecordRef, Schema};
+use serde_json::{Map, Value};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    str::FromStr,
+};
+use time::{format_description::well_known::Rfc3339, macros::format_description, OffsetDateTime, UtcOffset};
+
+use crate::downloader::columnar::{
+    columnar_sink, BatchBuilder, ColumnarRecord, ColumnarSinkFactory, RecordRows, BATCH_ROWS,
+};
+use crate::downloader::output::{DecodedFile, OutputFormat, RecordSink};
+use crate::downloader::schemas::schema_slug;
+use crate::downloader::sessions::ExchangeTimezone;
+use crate::processor::ContractFile;
+use crate::storage::FileEncoding;
+
+/// Clock the timestamps of CSV and pretty JSON output are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplayTimezone {
+    #[default]
+    Utc,
+    /// Exchange local time, following US daylight saving.
+    Exchange(ExchangeTimezone),
+    Fixed(UtcOffset),
+}
+
+impl DisplayTimezone {
+    /// Choices offered in the GUI.
+    pub const PRESETS: [DisplayTimezone; 3] = [
+        DisplayTimezone::Utc,
+        DisplayTimezone::Exchange(ExchangeTimezone::Chicago),
+        DisplayTimezone::Exchange(ExchangeTimezone::NewYork),
+    ];
+
+    /// `ts` (UNIX nanoseconds) as RFC 3339 in this timezone, e.g. `2024-01-05T08:30:00-06:00`.
+    pub fn format(&self, ts: u64) -> Result<String> {
+        let instant = OffsetDateTime::from_unix_timestamp_nanos(ts as i128)?;
+        let offset = match self {
+            DisplayTimezone::Utc => UtcOffset::UTC,
+            DisplayTimezone::Exchange(timezone) => timezone.offset_at(instant),
+            DisplayTimezone::Fixed(offset) => *offset,
+        };
+        Ok(instant.to_offset(offset).format(&Rfc3339)?)
+    }
+}
+
+/// CSV layout settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// Columns to write, in this order; every column of the schema when empty.
+    pub columns: Vec<String>,
+    /// Writes the column names as the first line.
+    pub header: bool,
+    pub delimiter: char,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self { columns: Vec::new(), header: true, delimiter: ',' }
+    }
+}
+
+/// `{base}_{schema}.csv` with `contract` and `symbol` columns followed by the columns of the schema's record type.
+/// Prices are decimals and timestamps RFC 3339 in `timezone`; undefined values are left empty.
+#[derive(Debug, Clone, Default)]
+pub struct CsvFormat {
+    options: CsvOptions,
+    timezone: DisplayTimezone,
+}
+
+impl CsvFormat {
+    pub fn new(options: CsvOptions, timezone: DisplayTimezone) -> Self {
+        Self { options, timezone }
+    }
+}
+
+impl ColumnarSinkFactory for CsvFormat {
+    fn sink<R: ColumnarRecord>(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        let path = format!("{}_{}.csv", input.base_path(), schema_slug(schema));
+        let layout = TextLayout::Csv { delimiter: self.options.delimiter };
+        let mut sink = TextSink::<R>::create(input, &path, layout, &self.options.columns, self.timezone)?;
+        if self.options.header {
+            let header = sink.columns.iter().map(|(_, name)| csv_field(name, self.options.delimiter)).collect::<Vec<_>>();
+            writeln!(sink.writer, "{}", header.join(&self.options.delimiter.to_string()))?;
+        }
+        Ok(Box::new(sink))
+    }
+}
+
+impl OutputFormat for CsvFormat {
+    fn encoding(&self) -> FileEncoding {
+        FileEncoding::Csv
+    }
+
+    fn create(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        columnar_sink(self, input, schema)
+    }
+}
+
+/// JSON lines in `{base}_{schema}_pretty.json` with the columns and conversions of `CsvFormat`. Kept apart
+/// from the plain `{base}_{schema}.json`, which the processors read.
+#[derive(Debug, Clone, Default)]
+pub struct PrettyJsonFormat {
+    timezone: DisplayTimezone,
+}
+
+impl PrettyJsonFormat {
+    pub fn new(timezone: DisplayTimezone) -> Self {
+        Self { timezone }
+    }
+}
+
+impl ColumnarSinkFactory for PrettyJsonFormat {
+    fn sink<R: ColumnarRecord>(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        let path = format!("{}_{}_pretty.json", input.base_path(), schema_slug(schema));
+        Ok(Box::new(TextSink::<R>::create(input, &path, TextLayout::Json, &[], self.timezone)?))
+    }
+}
+
+impl OutputFormat for PrettyJsonFormat {
+    fn encoding(&self) -> FileEncoding {
+        FileEncoding::Json
+    }
+
+    fn create(&self, input: &ContractFile, schema: Schema) -> Result<Box<dyn RecordSink>> {
+        columnar_sink(self, input, schema)
+    }
+}
+
+enum TextLayout {
+    Csv { delimiter: char },
+    Json,
+}
+
+struct TextSink<R> {
+    writer: BufWriter<File>,
+    layout: TextLayout,
+    timezone: DisplayTimezone,
+    contract: String,
+    /// Written columns as (index in the record batch, name).
+    columns: Vec<(usize, String)>,
+    rows: RecordRows<R>,
+    output: DecodedFile,
+}
+
+impl<R: ColumnarRecord> TextSink<R> {
+    fn create(
+        input: &ContractFile,
+        path: &str,
+        layout: TextLayout,
+        selected: &[String],
+        timezone: DisplayTimezone,
+    ) -> Result<Self> {
+        let names = RecordRows::<R>::column_names(true)?;
+        let columns = if selected.is_empty() {
+            names.into_iter().enumerate().collect()
+        } else {
+            selected
+                .iter()
+                .map(|name| match names.iter().position(|column| column == name) {
+                    Some(index) => Ok((index, name.clone())),
+                    None => bail!("Unknown column '{name}' (available: {})", names.join(", ")),
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let output = DecodedFile::new(path, input.start, input.end);
+        let writer = BufWriter::new(File::create(path).with_context(|| format!("Failed to create {path}"))?);
+        Ok(Self { writer, layout, timezone, contract: input.contract.clone(), columns, rows: RecordRows::default(), output })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.rows.buffered() == 0 {
+            return Ok(());
+        }
+        let batch = self.rows.take(Some(&self.contract))?;
+        for row in 0..batch.num_rows() {
+            match self.layout {
+                TextLayout::Csv { delimiter } => {
+                    let mut fields = Vec::with_capacity(self.columns.len());
+                    for (index, _) in &self.columns {
+                        fields.push(match cell(batch.column(*index), row, self.timezone)? {
+                            Value::Null => String::new(),
+                            Value::String(text) => csv_field(&text, delimiter),
+                            value => value.to_string(),
+                        });
+                    }
+                    writeln!(self.writer, "{}", fields.join(&delimiter.to_string()))?;
+                }
+                TextLayout::Json => {
+                    let mut object = Map::new();
+                    for (index, name) in &self.columns {
+                        object.insert(name.clone(), cell(batch.column(*index), row, self.timezone)?);
+                    }
+                    serde_json::to_writer(&mut self.writer, &object)?;
+                    writeln!(self.writer)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: ColumnarRecord> RecordSink for TextSink<R> {
+    fn write(&mut self, record: RecordRef, symbol: &str) -> Result<()> {
+        self.rows.push(record, symbol)?;
+        self.output.add(record.header().ts_event);
+        if self.rows.buffered() >= BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<Vec<DecodedFile>> {
+        self.flush()?;
+        self.writer.flush()?;
+        Ok(vec![self.output])
+    }
+
+    fn abort(self: Box<Self>) {
+        let path = self.output.path.clone();
+        drop(self);
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Row `row` of `column`: numbers as JSON numbers, timestamps as RFC 3339 strings in `timezone`.
+fn cell(column: &ArrayRef, row: usize, timezone: DisplayTimezone) -> Result<Value> {
+    if column.is_null(row) {
+        return Ok(Value::Null);
+    }
+    Ok(match column.data_type() {
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            Value::String(timezone.format(column.as_primitive::<TimestampNanosecondType>().value(row) as u64)?)
+        }
+        DataType::Float64 => Value::from(column.as_primitive::<Float64Type>().value(row)),
+        DataType::Utf8 => Value::from(column.as_string::<i32>().value(row)),
+        DataType::UInt8 => Value::from(column.as_primitive::<UInt8Type>().value(row)),
+        DataType::UInt16 => Value::from(column.as_primitive::<UInt16Type>().value(row)),
+        DataType::UInt32 => Value::from(column.as_primitive::<UInt32Type>().value(row)),
+        DataType::UInt64 => Value::from(column.as_primitive::<UInt64Type>().value(row)),
+        DataType::Int32 => Value::from(column.as_primitive::<Int32Type>().value(row)),
+        DataType::Int64 => Value::from(column.as_primitive::<Int64Type>().value(row)),
+        other => bail!("No text form for {other} columns"),
+    })
+}
+
+/// `text` quoted when it holds the delimiter, a quote or a line break.
+fn csv_field(text: &str, delimiter: char) -> String {
+    if text.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+impl FromStr for DisplayTimezone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "utc" | "z" => Ok(DisplayTimezone::Utc),
+            "chicago" | "america/chicago" => Ok(DisplayTimezone::Exchange(ExchangeTimezone::Chicago)),
+            "new_york" | "new-york" | "america/new_york" => Ok(DisplayTimezone::Exchange(ExchangeTimezone::NewYork)),
+            other => UtcOffset::parse(other, format_description!("[offset_hour sign:mandatory]:[offset_minute]"))
+                .map(DisplayTimezone::Fixed)
+                .with_context(|| format!("Unknown timezone '{other}' (expected utc, america/chicago, america/new_york or an offset like +02:00)")),
+        }
+    }
+}
+
+impl fmt::Display for DisplayTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayTimezone::Utc => write!(f, "utc"),
+            DisplayTimezone::Exchange(ExchangeTimezone::Chicago) => write!(f, "america/chicago"),
+            DisplayTimezone::Exchange(ExchangeTimezone::NewYork) => write!(f, "america/new_york"),
+            DisplayTimezone::Fixed(offset) => {
+                let (hours, minutes, _) = offset.as_hms();
+                let sign = if offset.is_negative() { '-' } else { '+' };
+                write!(f, "{sign}{:02}:{:02}", hours.abs(), minutes.abs())
+            }
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timezones() {
+        // 2024-01-05 14:30 UTC, standard time in the US
+        let ts = 1_704_465_000_000_000_000;
+        assert_eq!(DisplayTimezone::Utc.format(ts).unwrap(), "2024-01-05T14:30:00Z");
+        let chicago = "America/Chicago".parse::<DisplayTimezone>().unwrap();
+        assert_eq!(chicago.format(ts).unwrap(), "2024-01-05T08:30:00-06:00");
+        assert_eq!(chicago.format(ts + 123).unwrap(), "2024-01-05T08:30:00.000000123-06:00");
+
+        let fixed = "+05:30".parse::<DisplayTimezone>().unwrap();
+        assert_eq!(fixed.to_string(), "+05:30");
+        assert_eq!(fixed.format(ts).unwrap(), "2024-01-05T20:00:00+05:30");
+        assert!("mars".parse::<DisplayTimezone>().is_err());
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("ESH2024", ','), "ESH2024");
+        assert_eq!(csv_field("a,\"b\"", ','), "\"a,\"\"b\"\"\"");
+    }
+}
@@ -1,23 +1,35 @@
-use crate::commands::download::download_history;
+use crate::commands::budget::{budget_from_env, ApprovedEstimate, BudgetError};
+use crate::commands::download::{download_history_with_progress, pending_quote_requests};
 use crate::commands::get_quote::{
-    estimate_download_history_cost,
+    estimate_download_history_cost_with_progress,
+    estimate_requests_cost_with_progress,
+    HistoryQuoteEstimate,
     write_estimate_error_report,
     ERROR_REPORT_PATH,
 };
-use crate::downloader::decode::decode_all_in_dir;
+use crate::downloader::decode::{decode_all_in_dir, default_concurrency};
+use crate::downloader::output::{DecodeFormat, DecodeOptions};
+use crate::downloader::parquet_format::ParquetCompression;
+use crate::downloader::text_format::DisplayTimezone;
+use crate::downloader::contracts::PeriodOptions;
+use crate::downloader::products::{product_registry, ProductRegistry};
+use crate::downloader::roll::{ContractWindow, RollAnchor, RollPolicy};
+use crate::downloader::schemas::SUPPORTED_SCHEMAS;
+use crate::ledger::{load_ledger, SpendGrouping, SpendLine, SpendReport};
+use crate::progress::{is_cancelled, ProgressEvent, ProgressSink, ProgressTracker, TaskState, TaskStatus};
 use databento::dbn::Schema;
 use anyhow::{Context, Result};
 use eframe::{egui, App};
 //use egui_extras::DatePickerButton;
 use crate::custom_datepicker::CustomDatePickerButton as DatePickerButton;
 
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use time::{Date, Month};
 use chrono::{NaiveDate, Datelike};
 
-// ───── Constants ─────
-const SUPPORTED_SYMBOLS: &[&str] = &["CL", "NG", "ES", "NQ", "RTY", "YM"];
-
 // ───── Helper Functions ─────
 /// Convert `chrono::NaiveDate` to `time::Date`.
 fn naive_date_to_time(date: NaiveDate) -> Result<Date> {
@@ -25,32 +37,305 @@ fn naive_date_to_time(date: NaiveDate) -> Result<Date> {
         .context("Invalid NaiveDate to time::Date conversion")
 }
 
+/// Combo boxes and day counts for one product's roll policy.
+fn roll_policy_editor(ui: &mut egui::Ui, symbol: &str, policy: &mut RollPolicy) {
+    egui::ComboBox::from_id_salt(("roll_anchor", symbol))
+        .selected_text(match policy.anchor {
+            RollAnchor::LastTrade => "Last trade",
+            RollAnchor::FirstNotice => "First notice",
+        })
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut policy.anchor, RollAnchor::LastTrade, "Last trade");
+            ui.selectable_value(&mut policy.anchor, RollAnchor::FirstNotice, "First notice");
+        });
+
+    let is_overlap = matches!(policy.window, ContractWindow::Overlap { .. });
+    egui::ComboBox::from_id_salt(("roll_window", symbol))
+        .selected_text(if is_overlap { "Overlap" } else { "Before roll" })
+        .show_ui(ui, |ui| {
+            if ui.selectable_label(!is_overlap, "Before roll").clicked() && is_overlap {
+                policy.window = ContractWindow::BeforeExpiry { days_before: 40, days_after: 3 };
+            }
+            if ui.selectable_label(is_overlap, "Overlap").clicked() && !is_overlap {
+                policy.window = ContractWindow::Overlap { overlap_days: 10, first_span_days: 90 };
+            }
+        });
+
+    match &mut policy.window {
+        ContractWindow::BeforeExpiry { days_before, days_after } => {
+            ui.add(egui::DragValue::new(days_before).range(1..=365).suffix(" d before"));
+            ui.add(egui::DragValue::new(days_after).range(0..=30).suffix(" d after"));
+        }
+        ContractWindow::Overlap { overlap_days, first_span_days } => {
+            ui.add(egui::DragValue::new(overlap_days).range(0..=90).suffix(" d overlap"));
+            ui.add(egui::DragValue::new(first_span_days).range(1..=365).suffix(" d first span"));
+        }
+    }
+}
+
+/// Progress bar of one contract or file: full when done, animated while running with the bytes received so far.
+fn task_progress_bar(ui: &mut egui::Ui, task: &crate::progress::TaskProgress) {
+    ui.horizontal(|ui| {
+        ui.add_sized([140.0, 18.0], egui::Label::new(egui::RichText::new(&task.task).monospace()).truncate());
+        let megabytes = task.bytes as f64 / 1_000_000.0;
+        let bar = match task.state {
+            TaskState::Queued => egui::ProgressBar::new(0.0).text("queued"),
+            TaskState::Running => egui::ProgressBar::new(0.5).animate(true).text(format!("{megabytes:.1} MB")),
+            TaskState::Retrying => egui::ProgressBar::new(0.5).animate(true).text(format!("retry {}", task.attempts)),
+            TaskState::Finished => match (task.status, task.cost_usd) {
+                (Some(TaskStatus::Skipped), _) => egui::ProgressBar::new(1.0).text("already on disk"),
+                (Some(TaskStatus::Extended), Some(cost)) => {
+                    egui::ProgressBar::new(1.0).text(format!("extended, {megabytes:.1} MB, ${cost:.4}"))
+                }
+                (Some(TaskStatus::Extended), None) => egui::ProgressBar::new(1.0).text("extended"),
+                (_, Some(cost)) => egui::ProgressBar::new(1.0).text(format!("{megabytes:.1} MB, ${cost:.4}")),
+                (_, None) => egui::ProgressBar::new(1.0).text("done"),
+            },
+            TaskState::Failed => egui::ProgressBar::new(1.0).fill(egui::Color32::DARK_RED).text("failed"),
+            TaskState::Cancelled => egui::ProgressBar::new(0.0).text("cancelled"),
+        };
+        let response = ui.add(bar);
+        if let Some(error) = &task.error {
+            response.on_hover_text(error);
+        }
+    });
+}
+
+/// Folder the GUI downloads into and decodes from.
+const DATA_DIR: &str = "Hist_Fut_Data";
+
+/// What the "Download History" button asked for.
+#[derive(Debug, Clone)]
+struct DownloadRequest {
+    start: Date,
+    end: Date,
+    symbols: Vec<&'static str>,
+    schema: Schema,
+    options: PeriodOptions,
+}
+
+/// A download whose estimate failed the budget check, waiting for the user to confirm or drop it.
+#[derive(Debug, Clone)]
+struct PendingDownload {
+    request: DownloadRequest,
+    estimate: HistoryQuoteEstimate,
+    budget_usd: Option<f64>,
+    error: BudgetError,
+}
+
+/// Downloads under `approval`, which is recorded next to the data, and reports the summary in `status`.
+async fn download_approved(
+    request: DownloadRequest,
+    approval: ApprovedEstimate,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
+    status: Arc<Mutex<String>>,
+) {
+    *status.lock().unwrap() = format!("Downloading (estimated ${:.4})...", approval.total_cost_usd);
+
+    let result = download_history_with_progress(
+        &approval,
+        request.schema,
+        DATA_DIR,
+        &request.options,
+        progress,
+        cancel,
+    )
+    .await;
+
+    *status.lock().unwrap() = match result {
+        Ok(summary) if summary.is_success() => format!("Download complete: {summary}"),
+        Ok(summary) if summary.is_cancelled() => format!("Download cancelled: {summary}"),
+        Ok(summary) => format!("Download finished with failures: {summary}"),
+        Err(e) => format!("Error: {}", e),
+    };
+}
+
+/// One spend table: a row per month, root or schema.
+fn spend_grid(ui: &mut egui::Ui, id: &str, lines: &[SpendLine]) {
+    egui::Grid::new(id).striped(true).num_columns(4).show(ui, |ui| {
+        for header in ["", "Downloads", "Cost (USD)", "Billed (MB)"] {
+            ui.strong(header);
+        }
+        ui.end_row();
+        for line in lines {
+            ui.label(&line.key);
+            ui.label(line.downloads.to_string());
+            let cost = format!("{:.4}", line.cost_usd);
+            if line.unpriced > 0 {
+                ui.label(format!("{cost}*")).on_hover_text(format!("{} downloads without a quote", line.unpriced));
+            } else {
+                ui.label(cost);
+            }
+            ui.label(format!("{:.1}", line.billable_size as f64 / 1_000_000.0));
+            ui.end_row();
+        }
+    });
+}
+
 // ───── GUI App State ─────
-pub(crate) struct AppState {
+pub struct AppState {
     start_date: NaiveDate,
     end_date: NaiveDate,
+    symbols: Vec<&'static str>,
     selected_symbols: Vec<bool>,
+    roll_policies: Vec<RollPolicy>,
+    include_micros: bool,
+    schema: Schema,
+    decode: DecodeOptions,
+    /// Writes Parquet into date partitions under `DATA_DIR/parquet`.
+    partition_parquet: bool,
+    /// Comma-separated CSV columns; every column when empty.
+    csv_columns: String,
     task_status: Arc<Mutex<String>>,
     cost_estimate: Arc<Mutex<String>>,
+    progress: Arc<Mutex<ProgressTracker>>,
+    /// Running estimate, download or decode job and the token that cancels it.
+    job: Option<(JoinHandle<()>, CancellationToken)>,
+    budget_enabled: bool,
+    budget_usd: f64,
+    pending_download: Arc<Mutex<Option<PendingDownload>>>,
+    registry: &'static ProductRegistry,
+    /// Spend from the ledger of `DATA_DIR`, loaded when the panel is first opened.
+    spend: Option<Result<SpendReport, String>>,
     runtime: tokio::runtime::Runtime,
 }
 
-impl Default for AppState {
-    fn default() -> Self {
+impl AppState {
+    /// Fails when the configured product registry cannot be loaded.
+    pub fn new() -> Result<Self> {
+        let registry = product_registry()?;
         let runtime = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .expect("Failed to create runtime");
 
-        Self {
+        let symbols = registry.roots();
+        let budget = budget_from_env().unwrap_or_else(|e| {
+            eprintln!("{e:#}");
+            None
+        });
+
+        Ok(Self {
             start_date: NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(),
             end_date: NaiveDate::from_ymd_opt(2025, 12, 31).unwrap(),
-            selected_symbols: SUPPORTED_SYMBOLS.iter().map(|_| false).collect(),
+            selected_symbols: symbols.iter().map(|_| false).collect(),
+            roll_policies: registry.products().iter().map(|product| product.roll).collect(),
+            symbols,
+            include_micros: false,
+            schema: Schema::Ohlcv1M,
+            decode: DecodeOptions::default(),
+            partition_parquet: false,
+            csv_columns: String::new(),
             task_status: Arc::new(Mutex::new(String::new())),
             cost_estimate: Arc::new(Mutex::new("No estimate yet".to_string())),
+            progress: Arc::new(Mutex::new(ProgressTracker::new())),
+            job: None,
+            budget_enabled: budget.is_some(),
+            budget_usd: budget.unwrap_or(100.0),
+            pending_download: Arc::new(Mutex::new(None)),
+            spend: None,
+            registry,
             runtime,
+        })
+    }
+
+    /// Download/quote options from the GUI: micro siblings plus roll policies that differ from the registry.
+    fn period_options(&self) -> PeriodOptions {
+        let roll_overrides = self
+            .symbols
+            .iter()
+            .zip(&self.roll_policies)
+            .filter(|&(&symbol, policy)| self.registry.get(symbol).is_some_and(|product| product.roll != *policy))
+            .map(|(&symbol, &policy)| (symbol.to_string(), policy))
+            .collect();
+
+        PeriodOptions { include_siblings: self.include_micros, roll_overrides, ..Default::default() }
+    }
+
+    fn load_spend(&mut self) {
+        self.spend = Some(
+            load_ledger(DATA_DIR)
+                .map(|entries| SpendReport::new(&entries, &SpendGrouping::ALL))
+                .map_err(|e| format!("{e:#}")),
+        );
+    }
+
+    fn budget(&self) -> Option<f64> {
+        self.budget_enabled.then_some(self.budget_usd)
+    }
+
+    fn job_running(&self) -> bool {
+        self.job.as_ref().is_some_and(|(handle, _)| !handle.is_finished())
+    }
+
+    /// Asks whether to download despite a failed budget check; confirming starts the download and records
+    /// its estimate as overridden.
+    fn confirm_pending_download(&mut self, ctx: &egui::Context) {
+        let Some(pending) = self.pending_download.lock().unwrap().clone() else { return };
+
+        let mut decision = None;
+        egui::Window::new("Confirm download")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(pending.error.to_string());
+                ui.label(format!(
+                    "Quoted: ${:.4} for {} of {} contracts",
+                    pending.estimate.total_cost_usd, pending.estimate.successful_count, pending.estimate.total_count
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Download anyway").clicked() {
+                        decision = Some(true);
+                    }
+                    if ui.button("Don't download").clicked() {
+                        decision = Some(false);
+                    }
+                });
+            });
+
+        match decision {
+            Some(true) => {
+                *self.pending_download.lock().unwrap() = None;
+                let progress = self.progress_sink(ctx);
+                let cancel = CancellationToken::new();
+                let request = pending.request;
+                let approval = ApprovedEstimate::overridden(
+                    request.start,
+                    request.end,
+                    &request.symbols,
+                    request.schema,
+                    &pending.estimate,
+                    pending.budget_usd,
+                );
+                let handle = self.runtime.spawn(download_approved(
+                    request,
+                    approval,
+                    progress,
+                    cancel.clone(),
+                    self.task_status.clone(),
+                ));
+                self.job = Some((handle, cancel));
+            }
+            Some(false) => {
+                *self.pending_download.lock().unwrap() = None;
+                *self.task_status.lock().unwrap() = "Download not started".to_string();
+            }
+            None => {}
         }
     }
+
+    /// Clears the progress of the previous job and returns a sink that tracks the next one, redrawing on every event.
+    fn progress_sink(&self, ctx: &egui::Context) -> Arc<dyn ProgressSink> {
+        *self.progress.lock().unwrap() = ProgressTracker::new();
+        let progress = self.progress.clone();
+        let ctx = ctx.clone();
+        Arc::new(move |event: ProgressEvent| {
+            progress.lock().unwrap().apply(&event);
+            ctx.request_repaint();
+        })
+    }
 }
 
 impl App for AppState {
@@ -84,11 +369,62 @@ impl App for AppState {
 
             // Symbol selection checkboxes
             ui.label("Select Symbols:");
-            for (i, &symbol) in SUPPORTED_SYMBOLS.iter().enumerate() {
-                ui.horizontal(|ui| {
-                    ui.checkbox(&mut self.selected_symbols[i], symbol);
-                });
-            }
+            ui.horizontal_wrapped(|ui| {
+                for (i, &symbol) in self.symbols.iter().enumerate() {
+                    let response = ui.checkbox(&mut self.selected_symbols[i], symbol);
+                    if let Some(product) = self.registry.get(symbol) {
+                        response.on_hover_text(&product.name);
+                    }
+                }
+            });
+            ui.checkbox(&mut self.include_micros, "Include micro siblings")
+                .on_hover_text("Also fetch MES/MNQ/M2K/MYM/MCL over the same windows as their parent");
+
+            ui.horizontal(|ui| {
+                ui.label("Schema:");
+                egui::ComboBox::from_id_salt("schema")
+                    .selected_text(self.schema.as_str())
+                    .show_ui(ui, |ui| {
+                        for schema in SUPPORTED_SCHEMAS {
+                            ui.selectable_value(&mut self.schema, schema, schema.as_str());
+                        }
+                    });
+                if self.schema != Schema::Ohlcv1M {
+                    ui.small(format!("Saved under {DATA_DIR}/<root>/{}", self.schema));
+                }
+            });
+
+            egui::CollapsingHeader::new("Roll policy").show(ui, |ui| {
+                let mut any_selected = false;
+                for (i, &symbol) in self.symbols.iter().enumerate() {
+                    if !self.selected_symbols[i] {
+                        continue;
+                    }
+                    any_selected = true;
+                    ui.horizontal(|ui| {
+                        ui.label(symbol);
+                        roll_policy_editor(ui, symbol, &mut self.roll_policies[i]);
+                        if let Some(product) = self.registry.get(symbol)
+                            && product.roll != self.roll_policies[i]
+                            && ui.small_button("Reset").clicked()
+                        {
+                            self.roll_policies[i] = product.roll;
+                        }
+                    });
+                }
+                if !any_selected {
+                    ui.small("Select symbols to change their roll policy.");
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.budget_enabled, "Budget cap:");
+                ui.add_enabled(
+                    self.budget_enabled,
+                    egui::DragValue::new(&mut self.budget_usd).range(0.0..=f64::MAX).speed(1.0).prefix("$"),
+                );
+                ui.small("Downloads are quoted first and need confirmation above the cap.");
+            });
 
             let status_arc = self.task_status.clone();
             let cost_arc = self.cost_estimate.clone();
@@ -109,7 +445,8 @@ impl App for AppState {
                     }
                 };
 
-                let symbols: Vec<_> = SUPPORTED_SYMBOLS
+                let symbols: Vec<_> = self
+                    .symbols
                     .iter()
                     .zip(self.selected_symbols.iter())
                     .filter_map(|(&symbol, &checked)| if checked { Some(symbol) } else { None })
@@ -123,15 +460,23 @@ impl App for AppState {
                 *status_arc.lock().unwrap() = "Estimating cost...".to_string();
                 *cost_arc.lock().unwrap() = "Estimating...".to_string();
 
+                let options = self.period_options();
+                let schema = self.schema;
+                let progress = self.progress_sink(ctx);
+                let cancel = CancellationToken::new();
+                let cancel_inner = cancel.clone();
+
                 let status_arc_inner = status_arc.clone();
                 let cost_arc_inner = cost_arc.clone();
-                self.runtime.spawn(async move {
-                    let result = estimate_download_history_cost(
+                let handle = self.runtime.spawn(async move {
+                    let result = estimate_download_history_cost_with_progress(
                         start_date,
                         end_date,
                         &symbols,
-                        "GLBX.MDP3",
-                        Schema::Ohlcv1M,
+                        schema,
+                        &options,
+                        progress,
+                        cancel_inner,
                     )
                     .await;
 
@@ -178,12 +523,17 @@ impl App for AppState {
 
                             *status_arc_inner.lock().unwrap() = status;
                         }
+                        Err(e) if is_cancelled(&e) => {
+                            *cost_arc_inner.lock().unwrap() = "Estimate cancelled".to_string();
+                            *status_arc_inner.lock().unwrap() = "Cost estimate cancelled".to_string();
+                        }
                         Err(e) => {
                             *cost_arc_inner.lock().unwrap() = format!("Estimate error: {e}");
                             *status_arc_inner.lock().unwrap() = "Cost estimate failed".to_string();
                         }
                     }
                 });
+                self.job = Some((handle, cancel));
             }
 
             if ui.button("Download History").clicked() {
@@ -203,7 +553,8 @@ impl App for AppState {
                     }
                 };
 
-                let symbols: Vec<_> = SUPPORTED_SYMBOLS
+                let symbols: Vec<_> = self
+                    .symbols
                     .iter()
                     .zip(self.selected_symbols.iter())
                     .filter_map(|(&symbol, &checked)| if checked { Some(symbol) } else { None })
@@ -214,37 +565,149 @@ impl App for AppState {
                     return;
                 }
 
-                *status_arc.lock().unwrap() = "Downloading...".to_string();
+                *status_arc.lock().unwrap() = "Quoting before download...".to_string();
+
+                let request = DownloadRequest {
+                    start: start_date,
+                    end: end_date,
+                    symbols,
+                    schema: self.schema,
+                    options: self.period_options(),
+                };
+                let budget_usd = self.budget();
+                let progress = self.progress_sink(ctx);
+                let tracker = self.progress.clone();
+                let pending_download = self.pending_download.clone();
+                let cancel = CancellationToken::new();
+                let cancel_inner = cancel.clone();
+                let ctx_inner = ctx.clone();
 
                 let status_arc_inner = status_arc.clone();
-                self.runtime.spawn(async move {
-                    let result = download_history(
-                        start_date,
-                        end_date,
-                        &symbols.iter().map(AsRef::as_ref).collect::<Vec<_>>(),
-                        "Hist_Fut_Data",
-                    )
+                let handle = self.runtime.spawn(async move {
+                    // Nothing is downloaded before every range not on disk yet is quoted and the total checked
+                    // against the cap
+                    let estimate = async {
+                        let requests = pending_quote_requests(
+                            request.start,
+                            request.end,
+                            &request.symbols,
+                            request.schema,
+                            DATA_DIR,
+                            &request.options,
+                        )?;
+                        estimate_requests_cost_with_progress(requests, progress.clone(), cancel_inner.clone()).await
+                    }
                     .await;
-
-                    let mut status = status_arc_inner.lock().unwrap();
-                    *status = match result {
-                        Ok(_) => "Download complete".to_string(),
-                        Err(e) => format!("Error: {}", e),
+                    let estimate = match estimate {
+                        Ok(estimate) => estimate,
+                        Err(e) => {
+                            *status_arc_inner.lock().unwrap() = if is_cancelled(&e) {
+                                "Download cancelled".to_string()
+                            } else {
+                                format!("Quote before download failed: {e}")
+                            };
+                            return;
+                        }
                     };
+
+                    let approval = ApprovedEstimate::approve(
+                        request.start,
+                        request.end,
+                        &request.symbols,
+                        request.schema,
+                        &estimate,
+                        budget_usd,
+                    );
+                    match approval {
+                        Ok(approval) => {
+                            *tracker.lock().unwrap() = ProgressTracker::new();
+                            download_approved(request, approval, progress, cancel_inner, status_arc_inner).await;
+                        }
+                        Err(error) => {
+                            *status_arc_inner.lock().unwrap() = format!("Download needs confirmation: {error}");
+                            *pending_download.lock().unwrap() = Some(PendingDownload { request, estimate, budget_usd, error });
+                            ctx_inner.request_repaint();
+                        }
+                    }
                 });
+                self.job = Some((handle, cancel));
             }
 
+            egui::CollapsingHeader::new("Decode output").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Format:");
+                    for format in DecodeFormat::ALL {
+                        ui.radio_value(&mut self.decode.format, format, format.to_string());
+                    }
+                });
+                if self.decode.format == DecodeFormat::Ndjson {
+                    ui.checkbox(&mut self.decode.pretty, "Pretty: decimal prices and RFC 3339 timestamps");
+                }
+                if self.decode.format == DecodeFormat::Csv {
+                    ui.horizontal(|ui| {
+                        ui.label("Columns:");
+                        ui.add(egui::TextEdit::singleline(&mut self.csv_columns).hint_text("all, or e.g. ts_event,price,size"));
+                        ui.checkbox(&mut self.decode.csv.header, "Header");
+                    });
+                }
+                if self.decode.format == DecodeFormat::Csv || (self.decode.format == DecodeFormat::Ndjson && self.decode.pretty) {
+                    ui.horizontal(|ui| {
+                        ui.label("Timezone:");
+                        egui::ComboBox::from_id_salt("decode_timezone")
+                            .selected_text(self.decode.timezone.to_string())
+                            .show_ui(ui, |ui| {
+                                for timezone in DisplayTimezone::PRESETS {
+                                    ui.selectable_value(&mut self.decode.timezone, timezone, timezone.to_string());
+                                }
+                            });
+                    });
+                }
+                if self.decode.format == DecodeFormat::Parquet {
+                    let parquet = &mut self.decode.parquet;
+                    ui.horizontal(|ui| {
+                        ui.label("Compression:");
+                        egui::ComboBox::from_id_salt("parquet_compression")
+                            .selected_text(parquet.compression.to_string())
+                            .show_ui(ui, |ui| {
+                                for compression in ParquetCompression::ALL {
+                                    ui.selectable_value(&mut parquet.compression, compression, compression.to_string());
+                                }
+                            });
+                        ui.label("Row group:");
+                        ui.add(egui::DragValue::new(&mut parquet.row_group_size).range(1..=usize::MAX).speed(10_000.0));
+                    });
+                    ui.checkbox(&mut self.partition_parquet, format!("Partition by root, contract and date under {DATA_DIR}/parquet"));
+                }
+            });
+
             if ui.button("Decode Files").clicked() {
                 *status_arc.lock().unwrap() = "Decoding...".to_string();
+                self.decode.parquet.partition_dir = self.partition_parquet.then(|| Path::new(DATA_DIR).join("parquet"));
+                self.decode.csv.columns =
+                    self.csv_columns.split(',').map(str::trim).filter(|column| !column.is_empty()).map(String::from).collect();
+                let format = self.decode.output_format();
+                let progress = self.progress_sink(ctx);
+                let cancel = CancellationToken::new();
+                let cancel_inner = cancel.clone();
                 let status_arc_inner = status_arc.clone();
-                self.runtime.spawn(async move {
-                    let result = decode_all_in_dir("Hist_Fut_Data").await;
+                let handle = self.runtime.spawn(async move {
+                    let result = decode_all_in_dir(DATA_DIR, format, default_concurrency(), progress, cancel_inner).await;
                     let mut status = status_arc_inner.lock().unwrap();
                     *status = match result {
-                        Ok(_) => "Decoding complete".to_string(),
+                        Ok(summary) if summary.is_success() => format!("Decoding complete: {summary}"),
+                        Ok(summary) if summary.is_cancelled() => format!("Decoding cancelled: {summary}"),
+                        Ok(summary) => format!("Decoding finished with failures: {summary}"),
                         Err(e) => format!("Decode error: {}", e),
                     };
                 });
+                self.job = Some((handle, cancel));
+            }
+
+            if ui.add_enabled(self.job_running(), egui::Button::new("Cancel")).clicked() {
+                if let Some((_, cancel)) = &self.job {
+                    cancel.cancel();
+                }
+                *status_arc.lock().unwrap() = "Cancelling...".to_string();
             }
 
             ui.separator();
@@ -270,6 +733,49 @@ impl App for AppState {
             ui.small("Estimate only. This does not start a download.");
 
             ui.label(&*self.task_status.lock().unwrap());
+
+            egui::CollapsingHeader::new("Spend").id_salt("spend_panel").show(ui, |ui| {
+                let refresh = ui.button("Refresh").clicked();
+                if refresh || self.spend.is_none() {
+                    self.load_spend();
+                }
+                match &self.spend {
+                    Some(Ok(report)) if report.total.downloads == 0 => {
+                        ui.label("No downloads recorded yet.");
+                    }
+                    Some(Ok(report)) => {
+                        ui.label(format!(
+                            "Total: ${:.4} over {} downloads",
+                            report.total.cost_usd, report.total.downloads
+                        ));
+                        for (grouping, lines) in &report.groups {
+                            ui.label(format!("By {grouping}:"));
+                            spend_grid(ui, &format!("spend_{grouping}"), lines);
+                        }
+                    }
+                    Some(Err(e)) => {
+                        ui.label(format!("Failed to read the spend ledger: {e}"));
+                    }
+                    None => {}
+                }
+            });
+
+            let progress = self.progress.lock().unwrap().clone();
+            if !progress.tasks().is_empty() {
+                ui.separator();
+                ui.add(egui::ProgressBar::new(progress.fraction()).show_percentage());
+                ui.label(progress.to_string());
+                egui::ScrollArea::vertical()
+                    .id_salt("task_progress")
+                    .max_height(240.0)
+                    .show(ui, |ui| {
+                        for task in progress.tasks() {
+                            task_progress_bar(ui, task);
+                        }
+                    });
+            }
         });
+
+        self.confirm_pending_download(ctx);
     }
 }
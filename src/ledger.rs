@@ -0,0 +1,263 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use time::{Date, OffsetDateTime};
+
+/// JSON lines file in the data folder with one entry per fetched request.
+pub const LEDGER_FILE: &str = "spend_ledger.jsonl";
+/// Environment variable naming who is downloading; falls back to the login name.
+pub const USER_ENV: &str = "DATABENTO_USER";
+
+/// One billable request: a contract range fetched from Databento and the file it went into.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+    pub user: String,
+    pub dataset: String,
+    pub schema: String,
+    pub root: String,
+    pub contract: String,
+    pub symbol: String,
+    pub start: Date,
+    pub end: Date,
+    /// Quoted before the request; `None` when the quote failed.
+    pub estimated_cost_usd: Option<f64>,
+    /// Uncompressed bytes billed, when the size quote succeeded.
+    pub billable_size: Option<u64>,
+    /// File the data ended up in, relative to the data folder. For a failed request, the file it was meant
+    /// for or the `.rejected` file it was kept in.
+    pub file: PathBuf,
+    /// Why the request failed after its data was delivered; `None` when the data was stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Who to record as downloading: `DATABENTO_USER`, else the login name.
+pub fn current_user() -> String {
+    [USER_ENV, "USER", "USERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|user| !user.trim().is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Appends `entry` to the ledger of `base_path`.
+pub fn append_entry(base_path: impl AsRef<Path>, entry: &LedgerEntry) -> Result<()> {
+    let path = base_path.as_ref().join(LEDGER_FILE);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    // One write per line so concurrent downloads do not interleave entries
+    let line = format!("{}\n", serde_json::to_string(entry)?);
+    file.write_all(line.as_bytes()).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Every entry of the ledger of `base_path`, oldest first; empty when nothing was downloaded yet.
+pub fn load_ledger(base_path: impl AsRef<Path>) -> Result<Vec<LedgerEntry>> {
+    let path = base_path.as_ref().join(LEDGER_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    fs::read_to_string(&path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).with_context(|| format!("Invalid ledger entry in {}", path.display())))
+        .collect()
+}
+
+/// What spend is totalled by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendGrouping {
+    /// Calendar month (UTC) of the download.
+    Month,
+    Root,
+    Schema,
+}
+
+impl SpendGrouping {
+    pub const ALL: [SpendGrouping; 3] = [SpendGrouping::Month, SpendGrouping::Root, SpendGrouping::Schema];
+
+    fn key(&self, entry: &LedgerEntry) -> String {
+        match self {
+            SpendGrouping::Month => format!("{}-{:02}", entry.timestamp.year(), entry.timestamp.month() as u8),
+            SpendGrouping::Root => entry.root.clone(),
+            SpendGrouping::Schema => entry.schema.clone(),
+        }
+    }
+}
+
+/// Spend of one month, root or schema.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpendLine {
+    pub key: String,
+    pub downloads: usize,
+    pub cost_usd: f64,
+    /// Downloads whose cost is unknown and therefore missing from `cost_usd`.
+    pub unpriced: usize,
+    pub billable_size: u64,
+}
+
+impl SpendLine {
+    fn add(&mut self, entry: &LedgerEntry) {
+        self.downloads += 1;
+        match entry.estimated_cost_usd {
+            Some(cost) => self.cost_usd += cost,
+            None => self.unpriced += 1,
+        }
+        self.billable_size += entry.billable_size.unwrap_or(0);
+    }
+}
+
+/// Totals of `entries` per key of `grouping`, in key order.
+pub fn summarize_spend(entries: &[LedgerEntry], grouping: SpendGrouping) -> Vec<SpendLine> {
+    let mut lines = BTreeMap::<String, SpendLine>::new();
+    for entry in entries {
+        let key = grouping.key(entry);
+        lines.entry(key.clone()).or_insert_with(|| SpendLine { key, ..Default::default() }).add(entry);
+    }
+    lines.into_values().collect()
+}
+
+/// Overall spend plus one table per requested grouping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpendReport {
+    pub total: SpendLine,
+    pub groups: Vec<(SpendGrouping, Vec<SpendLine>)>,
+}
+
+impl SpendReport {
+    pub fn new(entries: &[LedgerEntry], groupings: &[SpendGrouping]) -> Self {
+        let mut total = SpendLine { key: "total".to_string(), ..Default::default() };
+        entries.iter().for_each(|entry| total.add(entry));
+        let groups = groupings.iter().map(|&grouping| (grouping, summarize_spend(entries, grouping))).collect();
+        Self { total, groups }
+    }
+}
+
+impl fmt::Display for SpendLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<10} {:>10} {:>12.4} {:>12.1}",
+            self.key,
+            self.downloads,
+            self.cost_usd,
+            self.billable_size as f64 / 1_000_000.0
+        )?;
+        if self.unpriced > 0 {
+            write!(f, "  ({} without a quote)", self.unpriced)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SpendReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<10} {:>10} {:>12} {:>12}", "", "downloads", "cost (USD)", "billed (MB)")?;
+        write!(f, "{}", self.total)?;
+        for (grouping, lines) in &self.groups {
+            write!(f, "\n\nBy {grouping}:")?;
+            for line in lines {
+                write!(f, "\n{line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SpendGrouping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "month" => Ok(SpendGrouping::Month),
+            "root" => Ok(SpendGrouping::Root),
+            "schema" => Ok(SpendGrouping::Schema),
+            other => bail!("Unknown grouping '{other}' (expected month, root or schema)"),
+        }
+    }
+}
+
+impl fmt::Display for SpendGrouping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpendGrouping::Month => write!(f, "month"),
+            SpendGrouping::Root => write!(f, "root"),
+            SpendGrouping::Schema => write!(f, "schema"),
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::{date, datetime};
+
+    fn entry(timestamp: OffsetDateTime, root: &str, cost: Option<f64>) -> LedgerEntry {
+        LedgerEntry {
+            timestamp,
+            user: "analyst".to_string(),
+            dataset: "GLBX.MDP3".to_string(),
+            schema: "ohlcv-1m".to_string(),
+            root: root.to_string(),
+            contract: format!("{root}H2024"),
+            symbol: format!("{root}H4"),
+            start: date!(2024 - 01 - 05),
+            end: date!(2024 - 03 - 15),
+            estimated_cost_usd: cost,
+            billable_size: Some(2_000_000),
+            file: PathBuf::from(format!("{root}/2024-01-05_2024-03-15_{root}H2024.dbn.zst")),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_ledger_round_trip_and_report() {
+        let dir = "test_output_ledger";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+
+        let entries = [
+            entry(datetime!(2024 - 04 - 02 10:00 UTC), "ES", Some(1.5)),
+            entry(datetime!(2024 - 04 - 20 10:00 UTC), "CL", Some(0.25)),
+            entry(datetime!(2024 - 05 - 01 10:00 UTC), "ES", None),
+        ];
+        for entry in &entries {
+            append_entry(dir, entry).unwrap();
+        }
+        let loaded = load_ledger(dir).unwrap();
+        assert_eq!(loaded, entries);
+
+        let report = SpendReport::new(&loaded, &SpendGrouping::ALL);
+        assert_eq!(report.total.downloads, 3);
+        assert_eq!(report.total.cost_usd, 1.75);
+        assert_eq!(report.total.unpriced, 1);
+
+        let months = &report.groups[0].1;
+        assert_eq!(months.iter().map(|line| line.key.as_str()).collect::<Vec<_>>(), ["2024-04", "2024-05"]);
+        assert_eq!(months[0].cost_usd, 1.75);
+        let roots = &report.groups[1].1;
+        assert_eq!((roots[0].key.as_str(), roots[0].downloads), ("CL", 1));
+        assert_eq!((roots[1].key.as_str(), roots[1].billable_size), ("ES", 4_000_000));
+        assert!(report.to_string().contains("By schema:\nohlcv-1m"));
+
+        assert!(load_ledger("test_output_missing_ledger").unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_grouping() {
+        assert_eq!("Month".parse::<SpendGrouping>().unwrap(), SpendGrouping::Month);
+        assert!("contract".parse::<SpendGrouping>().is_err());
+    }
+}
@@ -2,10 +2,20 @@ pub mod downloader;
 pub mod commands;
 pub mod client;
 pub mod gui;
+pub mod ledger;
 pub mod processor;
+pub mod progress;
+pub mod storage;
 pub mod types;
 
 pub mod custom_datepicker;
 
-pub use downloader::contracts::{generate_contract_periods};
-pub use commands::download::{download_history};
\ No newline at end of file
+pub use downloader::contracts::{
+    calculate_expiration_date, full_contract_symbol, generate_contract_periods,
+    generate_contract_periods_with_policy, generate_product_periods, raw_contract_symbol,
+    sibling_contract_periods, ContractError, ContractPeriod, ExpiryRule, PeriodOptions,
+};
+pub use downloader::roll::{ContractWindow, RollAnchor, RollPolicy};
+pub use downloader::roll_calendar::{RollCalendar, RollEntry, RollSignal};
+pub use downloader::products::{product_registry, ProductRegistry, ProductSpec};
+pub use commands::download::{download_history, download_history_with_progress, pending_quote_requests};
\ No newline at end of file
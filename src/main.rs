@@ -1,17 +1,29 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use clap::Parser;
+use databento::dbn::Schema;
 use eframe::egui;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio_util::sync::CancellationToken;
 
 mod cli;
-mod client;
-mod commands;
-mod downloader;
-mod types;
-mod gui;
-mod custom_datepicker;
 
-use crate::cli::{Cli, Commands};
-use crate::commands::get_quote::estimate_quote_cost;
+use crate::cli::{Cli, Commands, DownloadArgs};
+use databento_toolkit::commands::budget::{ApprovedEstimate, APPROVALS_FILE};
+use databento_toolkit::commands::get_quote::{estimate_quote_cost, estimate_requests_cost_with_progress};
+use databento_toolkit::commands::detect_rolls::{detect_roll_calendar, OPEN_INTEREST_SCHEMA};
+use databento_toolkit::downloader::decode::decode_all_in_dir;
+use databento_toolkit::downloader::roll_calendar::RollSignal;
+use databento_toolkit::processor::continuous::{build_continuous_series, write_continuous_series};
+use databento_toolkit::processor::resample::resample_contract_file;
+use databento_toolkit::processor::sessions::session_contract_file;
+use databento_toolkit::processor::validate::{report_path, summarize, validate_contract_file};
+use databento_toolkit::ledger::{load_ledger, SpendReport, LEDGER_FILE};
+use databento_toolkit::storage::Catalog;
+use databento_toolkit::progress::{NoProgress, ProgressEvent, ProgressTracker};
+use databento_toolkit::{download_history_with_progress, gui, pending_quote_requests};
 
 fn main() -> Result<()> {
     dotenvy::dotenv().ok();
@@ -25,18 +37,84 @@ fn main() -> Result<()> {
 }
 
 fn run_gui() -> Result<()> {
+    let app = gui::AppState::new()?;
     let options = eframe::NativeOptions::default();
     eframe::run_native(
         "Databento Toolkit",
         options,
         Box::new(|cc| {
             cc.egui_ctx.set_theme(egui::Theme::Dark);
-            Ok(Box::new(gui::AppState::default()))}),
+            Ok(Box::new(app))}),
     )
     .map_err(|e| anyhow!("GUI startup failed: {e}"))?;
     Ok(())
 }
 
+/// Cancels `cancel` on the first Ctrl-C so running downloads stop and clean up; a second Ctrl-C exits at once.
+async fn cancel_on_ctrl_c(cancel: CancellationToken) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    eprintln!("Cancelling, press Ctrl-C again to exit immediately...");
+    cancel.cancel();
+    if tokio::signal::ctrl_c().await.is_ok() {
+        std::process::exit(130);
+    }
+}
+
+/// Quotes the ranges of `args` not on disk yet, records the approval when the total fits `budget`, then
+/// downloads them, failing when any contract window fails or the download is cancelled.
+fn download_within_budget(
+    runtime: &tokio::runtime::Runtime,
+    args: &DownloadArgs,
+    schema: Schema,
+    budget: Option<f64>,
+    cancel: CancellationToken,
+) -> Result<()> {
+    let (start, end) = args.dates()?;
+    let options = args.period_options()?;
+    let symbols = args.symbols.iter().map(String::as_str).collect::<Vec<_>>();
+
+    // Nothing is downloaded unless every range not on disk yet is quoted and the total fits the budget
+    println!("Quoting {} {schema} from {start} to {end}...", symbols.join(", "));
+    let requests = pending_quote_requests(start, end, &symbols, schema, &args.output, &options)?;
+    let estimate =
+        runtime.block_on(estimate_requests_cost_with_progress(requests, Arc::new(NoProgress), cancel.clone()))?;
+    let approval = ApprovedEstimate::approve(start, end, &symbols, schema, &estimate, budget)?;
+    println!(
+        "Estimated cost ${:.4} for {} ranges not on disk is within budget; recording it in {}",
+        estimate.total_cost_usd,
+        estimate.total_count,
+        Path::new(&args.output).join(APPROVALS_FILE).display()
+    );
+
+    // One aggregate line whenever a contract finishes or fails
+    let tracker = Mutex::new(ProgressTracker::new());
+    let progress = move |event: ProgressEvent| {
+        let mut tracker = tracker.lock().unwrap();
+        tracker.apply(&event);
+        if matches!(event, ProgressEvent::Finished { .. } | ProgressEvent::Failed { .. }) {
+            println!("Progress: {tracker}");
+        }
+    };
+    let summary = runtime.block_on(download_history_with_progress(
+        &approval,
+        schema,
+        &args.output,
+        &options,
+        Arc::new(progress),
+        cancel,
+    ))?;
+    println!("{summary}");
+    if summary.is_cancelled() {
+        bail!("Download cancelled; rerun the same command to resume");
+    }
+    if !summary.is_success() {
+        bail!("{} contract windows failed to download", summary.failed.len());
+    }
+    Ok(())
+}
+
 fn run_cli_command(command: Commands) -> Result<()> {
     match command {
         Commands::Quote(args) => {
@@ -52,6 +130,167 @@ fn run_cli_command(command: Commands) -> Result<()> {
                 request.symbol, request.start, request.end, cost
             );
         }
+        Commands::Download(command) => {
+            let budget = command.budget()?;
+            let args = command.download;
+
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+            let cancel = CancellationToken::new();
+            runtime.spawn(cancel_on_ctrl_c(cancel.clone()));
+
+            download_within_budget(&runtime, &args, command.schema, budget, cancel)?;
+            println!("Download complete: {}", args.output);
+        }
+        Commands::Decode(args) => {
+            let format = args.options().output_format();
+
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+            let cancel = CancellationToken::new();
+            runtime.spawn(cancel_on_ctrl_c(cancel.clone()));
+
+            let summary = runtime.block_on(decode_all_in_dir(&args.data, format, args.jobs, Arc::new(NoProgress), cancel))?;
+            println!("{summary}");
+            if summary.is_cancelled() {
+                bail!("Decoding cancelled");
+            }
+            if !summary.is_success() {
+                bail!("{} files failed to decode", summary.failed.len());
+            }
+            println!("Decoded files in {} to {}", args.data, args.format);
+        }
+        Commands::DetectRolls(args) => {
+            let (start, end) = args.download.dates()?;
+            let options = args.download.period_options()?;
+            let symbols = args.download.symbols.iter().map(String::as_str).collect::<Vec<_>>();
+
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+
+            // Open interest comes from statistics files, which are quoted and budgeted like any other download
+            if args.signal == RollSignal::OpenInterest {
+                let cancel = CancellationToken::new();
+                runtime.spawn(cancel_on_ctrl_c(cancel.clone()));
+                download_within_budget(&runtime, &args.download, OPEN_INTEREST_SCHEMA, args.budget()?, cancel)?;
+            }
+
+            let calendar = runtime.block_on(detect_roll_calendar(
+                start,
+                end,
+                &symbols,
+                &args.download.output,
+                &options,
+                args.signal,
+                args.confirm_days,
+            ))?;
+            calendar.save(&args.calendar)?;
+
+            for entry in &calendar.entries {
+                let source = if entry.detected {
+                    format!("{} crossover, scheduled {}", args.signal, entry.scheduled)
+                } else {
+                    "scheduled, no crossover found".to_string()
+                };
+                println!("{} -> {}: {} ({source})", entry.from, entry.to, entry.roll_date);
+            }
+            println!("Roll calendar written to {}", args.calendar);
+        }
+        Commands::Continuous(args) => {
+            let options = args.options()?;
+
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+
+            let series = runtime.block_on(build_continuous_series(args.symbol_dir(), &args.symbol, &options))?;
+            let output = args.output_path();
+            write_continuous_series(&output, &series)?;
+            println!("Wrote {} {} bars to {output}", series.len(), args.adjustment);
+        }
+        Commands::Resample(args) => {
+            let files = args.contract_files()?;
+
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+
+            for file in &files {
+                let output = runtime.block_on(resample_contract_file(file, args.interval, args.align))?;
+                println!("Resampled {} → {output}", file.path.display());
+            }
+        }
+        Commands::Sessions(args) => {
+            let files = args.contract_files()?;
+
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+
+            for file in &files {
+                let output = runtime.block_on(session_contract_file(file, args.filter, args.daily))?;
+                println!("Filtered {} ({}) → {output}", file.path.display(), args.filter);
+            }
+        }
+        Commands::Validate(args) => {
+            let files = args.contract_files()?;
+            let options = args.options();
+
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+
+            let mut reports = Vec::new();
+            for file in &files {
+                let report = runtime.block_on(validate_contract_file(file, &options))?;
+                report.save(report_path(file))?;
+                reports.push(report);
+            }
+            println!("{}", summarize(&reports));
+        }
+        Commands::Catalog(args) => {
+            let query = args.query()?;
+            let mut catalog = Catalog::open(&args.data)?;
+
+            if args.index {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+
+                let removed = catalog.prune();
+                let added = runtime.block_on(catalog.index_existing())?;
+                catalog.save()?;
+                println!("Catalog updated: {added} added, {removed} removed");
+            }
+
+            let entries = catalog.query(&query);
+            for entry in &entries {
+                let cost = entry.cost_usd.map_or_else(|| "-".to_string(), |cost| format!("${cost:.4}"));
+                println!(
+                    "{}  {} {} {} to {}  {} records  {} bytes  {cost}",
+                    entry.path.display(),
+                    entry.contract,
+                    entry.schema,
+                    entry.start,
+                    entry.end,
+                    entry.record_count,
+                    entry.byte_size
+                );
+            }
+            let total_cost = entries.iter().filter_map(|entry| entry.cost_usd).fold(0.0, |total, cost| total + cost);
+            println!("{} files, ${total_cost:.4} paid", entries.len());
+        }
+        Commands::Spend(args) => {
+            let entries = load_ledger(&args.data)?;
+            if entries.is_empty() {
+                println!("No downloads recorded in {}/{LEDGER_FILE}", args.data);
+                return Ok(());
+            }
+            println!("{}", SpendReport::new(&entries, &args.by));
+        }
     }
 
     Ok(())
@@ -0,0 +1,411 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+use time::Date;
+
+use crate::downloader::contracts::{parse_full_contract_symbol, roll_date_on, scheduled_roll_date};
+use crate::downloader::roll::RollPolicy;
+use crate::downloader::roll_calendar::{trading_date, RollCalendar};
+use crate::processor::{find_contract_files, load_bars, scale_price};
+use crate::types::JsonOhlcv;
+
+/// How prices before each roll are adjusted to line up with the contract rolled into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjustmentMode {
+    /// Raw prices; the series jumps at every roll.
+    #[default]
+    Unadjusted,
+    /// Back-adjusted: the roll gap is added to all earlier prices, so the latest contract is unchanged.
+    Difference,
+    /// Ratio-adjusted: earlier prices are multiplied by the roll ratio, preserving percentage returns.
+    Ratio,
+}
+
+/// Bars of one contract, ordered by `ts_event`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractBars {
+    /// Contract name with a four-digit year, e.g. `ESH2024`.
+    pub contract: String,
+    pub bars: Vec<JsonOhlcv>,
+}
+
+/// One bar of a continuous series.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContinuousBar {
+    pub ts_event: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64,
+    /// Contract the bar was taken from.
+    pub contract: String,
+    /// Amount added to (difference) or factor applied to (ratio) the contract's raw prices.
+    /// `0` for difference and unadjusted series, `1` for ratio series, on the latest contract.
+    pub adjustment: f64,
+}
+
+/// Where roll dates come from when stitching.
+#[derive(Debug, Clone, Default)]
+pub struct ContinuousOptions {
+    pub adjustment: AdjustmentMode,
+    /// Detected roll dates (see `detect-rolls`). Contracts it does not cover, or every contract
+    /// when `None`, roll on the scheduled date of the product's roll policy.
+    pub roll_calendar: Option<RollCalendar>,
+    /// Roll policies replacing the registry default, keyed by product root. Only the anchor moves roll dates.
+    pub roll_overrides: HashMap<String, RollPolicy>,
+}
+
+/// Roll out of one contract into the next at the first bar of `roll_date`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractRoll {
+    pub from: String,
+    pub to: String,
+    pub roll_date: Date,
+}
+
+impl fmt::Display for AdjustmentMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdjustmentMode::Unadjusted => write!(f, "unadjusted"),
+            AdjustmentMode::Difference => write!(f, "difference"),
+            AdjustmentMode::Ratio => write!(f, "ratio"),
+        }
+    }
+}
+
+impl FromStr for AdjustmentMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "unadjusted" | "none" => Ok(AdjustmentMode::Unadjusted),
+            "difference" | "back" | "back_adjusted" => Ok(AdjustmentMode::Difference),
+            "ratio" => Ok(AdjustmentMode::Ratio),
+            _ => bail!("Unknown adjustment '{value}', expected unadjusted, difference or ratio"),
+        }
+    }
+}
+
+/// Roll dates between consecutive contracts: the calendar's where it has one, otherwise scheduled
+/// under the product's override in `options`, or its registry policy.
+pub fn contract_rolls(contracts: &[String], options: &ContinuousOptions) -> Result<Vec<ContractRoll>> {
+    contracts
+        .windows(2)
+        .map(|pair| {
+            let (from, to) = (&pair[0], &pair[1]);
+            let roll_date = match options.roll_calendar.as_ref().and_then(|calendar| calendar.roll_from(from)) {
+                Some(entry) if entry.to == *to => entry.roll_date,
+                _ => {
+                    let (root, month, year) = parse_full_contract_symbol(from)
+                        .with_context(|| format!("Invalid contract name: {from}"))?;
+                    match options.roll_overrides.get(root) {
+                        Some(policy) => roll_date_on(root, year, month, policy.anchor)?,
+                        None => scheduled_roll_date(root, year, month)?,
+                    }
+                }
+            };
+            Ok(ContractRoll { from: from.clone(), to: to.clone(), roll_date })
+        })
+        .collect()
+}
+
+/// Stitches consecutive contracts into one series. Each contract supplies the bars from the previous
+/// roll date up to (not including) its own, by trading date. Contracts must be in expiry order.
+pub fn stitch(contracts: &[ContractBars], rolls: &[ContractRoll], mode: AdjustmentMode) -> Result<Vec<ContinuousBar>> {
+    if rolls.len() + 1 != contracts.len().max(1) {
+        bail!("Expected {} rolls for {} contracts, got {}", contracts.len().saturating_sub(1), contracts.len(), rolls.len());
+    }
+
+    // Bars each contract contributes
+    let segments = contracts
+        .iter()
+        .enumerate()
+        .map(|(i, contract)| {
+            let from = i.checked_sub(1).map(|prev| rolls[prev].roll_date);
+            let until = rolls.get(i).map(|roll| roll.roll_date);
+            contract
+                .bars
+                .iter()
+                .filter(|bar| {
+                    let Some(date) = trading_date(bar.ts_event) else { return false };
+                    from.is_none_or(|from| date >= from) && until.is_none_or(|until| date < until)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // Adjustment of each segment, accumulated backwards from the latest contract
+    let mut adjustments = vec![neutral_adjustment(mode); contracts.len()];
+    for i in (0..rolls.len()).rev() {
+        let gap = match segments[i].last() {
+            Some(last_front) => roll_gap(last_front, &contracts[i + 1].bars, mode)
+                .with_context(|| format!("No {} price to adjust the roll from {}", rolls[i].to, rolls[i].from))?,
+            None => neutral_adjustment(mode),
+        };
+        adjustments[i] = match mode {
+            AdjustmentMode::Unadjusted => 0.0,
+            AdjustmentMode::Difference => adjustments[i + 1] + gap,
+            AdjustmentMode::Ratio => adjustments[i + 1] * gap,
+        };
+    }
+
+    let series = segments
+        .iter()
+        .zip(contracts)
+        .zip(&adjustments)
+        .flat_map(|((segment, contract), &adjustment)| {
+            segment.iter().map(move |bar| {
+                let adjust = |price: i64| match mode {
+                    AdjustmentMode::Unadjusted => scale_price(price),
+                    AdjustmentMode::Difference => scale_price(price) + adjustment,
+                    AdjustmentMode::Ratio => scale_price(price) * adjustment,
+                };
+                ContinuousBar {
+                    ts_event: bar.ts_event,
+                    open: adjust(bar.open),
+                    high: adjust(bar.high),
+                    low: adjust(bar.low),
+                    close: adjust(bar.close),
+                    volume: bar.volume,
+                    contract: contract.contract.clone(),
+                    adjustment,
+                }
+            })
+        })
+        .collect();
+
+    Ok(series)
+}
+
+fn neutral_adjustment(mode: AdjustmentMode) -> f64 {
+    match mode {
+        AdjustmentMode::Ratio => 1.0,
+        AdjustmentMode::Unadjusted | AdjustmentMode::Difference => 0.0,
+    }
+}
+
+/// Gap between the next contract and the front at the front's last bar before the roll.
+/// Uses the next contract's latest close at or before that bar, or its first close after it.
+fn roll_gap(last_front: &JsonOhlcv, next_bars: &[JsonOhlcv], mode: AdjustmentMode) -> Option<f64> {
+    let at_or_before = next_bars.partition_point(|bar| bar.ts_event <= last_front.ts_event);
+    let reference = at_or_before.checked_sub(1).map(|i| &next_bars[i]).or_else(|| next_bars.get(at_or_before))?;
+
+    let front_close = scale_price(last_front.close);
+    let next_close = scale_price(reference.close);
+    match mode {
+        AdjustmentMode::Unadjusted => Some(0.0),
+        AdjustmentMode::Difference => Some(next_close - front_close),
+        AdjustmentMode::Ratio if front_close != 0.0 => Some(next_close / front_close),
+        AdjustmentMode::Ratio => None,
+    }
+}
+
+/// Builds the continuous series of `root` from the contract files in `dir` (e.g. `Hist_Fut_Data/ES`).
+pub async fn build_continuous_series(
+    dir: impl AsRef<Path>,
+    root: &str,
+    options: &ContinuousOptions,
+) -> Result<Vec<ContinuousBar>> {
+    let files = find_contract_files(dir, root)?;
+    if files.is_empty() {
+        bail!("No contract files for {root}");
+    }
+
+    // Merge overlapping downloads of the same contract, ordered by expiry
+    let mut by_contract: BTreeMap<(i32, u8), ContractBars> = BTreeMap::new();
+    for file in &files {
+        let (_, month, year) = parse_full_contract_symbol(&file.contract)
+            .with_context(|| format!("Invalid contract name: {}", file.contract))?;
+        let entry = by_contract.entry((year, month as u8)).or_insert_with(|| ContractBars {
+            contract: file.contract.clone(),
+            bars: Vec::new(),
+        });
+        entry.bars.extend(load_bars(file).await?);
+    }
+
+    let contracts = by_contract
+        .into_values()
+        .map(|mut contract| {
+            contract.bars.sort_by_key(|bar| bar.ts_event);
+            contract.bars.dedup_by_key(|bar| bar.ts_event);
+            contract
+        })
+        .collect::<Vec<_>>();
+
+    let names = contracts.iter().map(|contract| contract.contract.clone()).collect::<Vec<_>>();
+    let rolls = contract_rolls(&names, options)?;
+    stitch(&contracts, &rolls, options.adjustment)
+}
+
+/// Writes the series as JSON lines, one bar per line, like the decoder's output.
+pub fn write_continuous_series(path: impl AsRef<Path>, series: &[ContinuousBar]) -> Result<()> {
+    let path = path.as_ref();
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    for bar in series {
+        serde_json::to_writer(&mut writer, bar)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use databento::dbn::FIXED_PRICE_SCALE;
+    use time::macros::{date, datetime};
+
+    /// One bar per day at 15:00 UTC with every price at `close`.
+    fn contract(name: &str, first: Date, closes: &[f64]) -> ContractBars {
+        let bars = closes
+            .iter()
+            .enumerate()
+            .map(|(i, &close)| {
+                let day = first + time::Duration::days(i as i64);
+                let ts = day.with_hms(15, 0, 0).unwrap().assume_utc().unix_timestamp_nanos() as u64;
+                let price = (close * FIXED_PRICE_SCALE as f64) as i64;
+                JsonOhlcv {
+                    instrument_name: name.to_string(),
+                    instrument_id: 0,
+                    ts_event: ts,
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    volume: 1,
+                }
+            })
+            .collect();
+        ContractBars { contract: name.to_string(), bars }
+    }
+
+    fn sample() -> (Vec<ContractBars>, Vec<ContractRoll>) {
+        let contracts = vec![
+            contract("ESH2024", date!(2024 - 03 - 10), &[100.0, 101.0, 102.0, 103.0]),
+            contract("ESM2024", date!(2024 - 03 - 10), &[110.0, 111.0, 112.0, 113.0]),
+        ];
+        let rolls = vec![ContractRoll {
+            from: "ESH2024".to_string(),
+            to: "ESM2024".to_string(),
+            roll_date: date!(2024 - 03 - 12),
+        }];
+        (contracts, rolls)
+    }
+
+    #[test]
+    fn test_unadjusted_switches_contract_on_roll_date() {
+        let (contracts, rolls) = sample();
+        let series = stitch(&contracts, &rolls, AdjustmentMode::Unadjusted).unwrap();
+
+        let closes = series.iter().map(|bar| bar.close).collect::<Vec<_>>();
+        assert_eq!(closes, vec![100.0, 101.0, 112.0, 113.0]);
+        assert_eq!(series[1].contract, "ESH2024");
+        assert_eq!(series[2].contract, "ESM2024");
+        assert!(series.iter().all(|bar| bar.adjustment == 0.0));
+    }
+
+    #[test]
+    fn test_difference_adjustment_removes_roll_gap() {
+        let (contracts, rolls) = sample();
+        let series = stitch(&contracts, &rolls, AdjustmentMode::Difference).unwrap();
+
+        // Gap measured on 2024-03-11: 111 - 101 = 10
+        let closes = series.iter().map(|bar| bar.close).collect::<Vec<_>>();
+        assert_eq!(closes, vec![110.0, 111.0, 112.0, 113.0]);
+        assert_eq!(series[0].adjustment, 10.0);
+        assert_eq!(series[3].adjustment, 0.0);
+    }
+
+    #[test]
+    fn test_ratio_adjustment_preserves_returns() {
+        let (contracts, rolls) = sample();
+        let series = stitch(&contracts, &rolls, AdjustmentMode::Ratio).unwrap();
+
+        let ratio = 111.0 / 101.0;
+        assert!((series[0].close - 100.0 * ratio).abs() < 1e-9);
+        assert!((series[1].close - 111.0).abs() < 1e-9);
+        assert_eq!(series[0].adjustment, ratio);
+        assert_eq!(series[2].adjustment, 1.0);
+    }
+
+    #[test]
+    fn test_adjustments_accumulate_over_several_rolls() {
+        let contracts = vec![
+            contract("ESH2024", date!(2024 - 03 - 10), &[100.0, 100.0]),
+            contract("ESM2024", date!(2024 - 03 - 10), &[105.0, 105.0, 105.0, 105.0]),
+            contract("ESU2024", date!(2024 - 03 - 12), &[107.0, 107.0]),
+        ];
+        let rolls = vec![
+            ContractRoll { from: "ESH2024".into(), to: "ESM2024".into(), roll_date: date!(2024 - 03 - 11) },
+            ContractRoll { from: "ESM2024".into(), to: "ESU2024".into(), roll_date: date!(2024 - 03 - 13) },
+        ];
+
+        let series = stitch(&contracts, &rolls, AdjustmentMode::Difference).unwrap();
+        let adjustments = series.iter().map(|bar| (bar.contract.as_str(), bar.adjustment)).collect::<Vec<_>>();
+        assert_eq!(adjustments, vec![("ESH2024", 7.0), ("ESM2024", 2.0), ("ESM2024", 2.0), ("ESU2024", 0.0)]);
+        assert!(series.iter().all(|bar| bar.close == 107.0));
+    }
+
+    #[test]
+    fn test_contract_rolls_prefer_calendar_entries() {
+        use crate::downloader::roll_calendar::{RollEntry, RollSignal};
+
+        let contracts = vec!["ESH2024".to_string(), "ESM2024".to_string(), "ESU2024".to_string()];
+        let calendar = RollCalendar {
+            signal: RollSignal::Volume,
+            entries: vec![RollEntry {
+                root: "ES".to_string(),
+                from: "ESH2024".to_string(),
+                to: "ESM2024".to_string(),
+                scheduled: date!(2024 - 03 - 15),
+                roll_date: date!(2024 - 03 - 08),
+                detected: true,
+            }],
+        };
+
+        let options = ContinuousOptions { roll_calendar: Some(calendar), ..Default::default() };
+        let rolls = contract_rolls(&contracts, &options).unwrap();
+        assert_eq!(rolls[0].roll_date, date!(2024 - 03 - 08));
+        assert_eq!(rolls[1].roll_date, date!(2024 - 06 - 21));
+    }
+
+    #[test]
+    fn test_contract_rolls_apply_overrides() {
+        let contracts = vec!["ZNH2024".to_string(), "ZNM2024".to_string()];
+        let zn = crate::downloader::products::product_registry().unwrap().get("ZN").unwrap();
+
+        // ZN rolls on first notice by default, the last business day of February
+        let rolls = contract_rolls(&contracts, &ContinuousOptions::default()).unwrap();
+        assert_eq!(rolls[0].roll_date, date!(2024 - 02 - 29));
+
+        let last_trade = zn.roll.with_override("@last_trade").unwrap();
+        let options =
+            ContinuousOptions { roll_overrides: HashMap::from([("ZN".to_string(), last_trade)]), ..Default::default() };
+        let rolls = contract_rolls(&contracts, &options).unwrap();
+        assert_eq!(rolls[0].roll_date, date!(2024 - 03 - 19));
+    }
+
+    #[test]
+    fn test_evening_session_belongs_to_next_trading_day() {
+        let mut front = contract("ESH2024", date!(2024 - 03 - 10), &[100.0]);
+        let next = contract("ESM2024", date!(2024 - 03 - 10), &[110.0, 111.0]);
+        // Sunday evening bar trades for Monday 2024-03-11, the roll date
+        front.bars[0].ts_event = datetime!(2024 - 03 - 10 23:00 UTC).unix_timestamp_nanos() as u64;
+        let rolls = vec![ContractRoll { from: "ESH2024".into(), to: "ESM2024".into(), roll_date: date!(2024 - 03 - 11) }];
+
+        let series = stitch(&[front, next], &rolls, AdjustmentMode::Unadjusted).unwrap();
+        assert!(series.iter().all(|bar| bar.contract == "ESM2024"));
+    }
+}
@@ -0,0 +1,243 @@
+pub mod continuous;
+pub mod resample;
+pub mod sessions;
+pub mod validate;
+
+use anyhow::{Context, Result};
+use databento::dbn::{decode::AsyncDbnDecoder, OhlcvMsg, Schema, FIXED_PRICE_SCALE};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+use time::{macros::format_description, Date};
+
+use crate::downloader::contracts::parse_full_contract_symbol;
+use crate::downloader::schemas::{decoded_schema, decoded_suffix};
+use crate::storage::{Catalog, CatalogQuery};
+use crate::types::JsonOhlcv;
+
+const DBN_EXT: &str = ".dbn.zst";
+
+/// A downloaded per-contract file, `{start}_{end}_{contract}.dbn.zst` or its decoded `_ohlcv1m.json`
+/// (`_trades.json`, `_mbp10.json`, ... in the folder of other schemas).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractFile {
+    pub path: PathBuf,
+    /// Contract name with a four-digit year, e.g. `ESH2024`.
+    pub contract: String,
+    pub start: Date,
+    pub end: Date,
+}
+
+impl ContractFile {
+    /// Parses a file name written by the downloader or the decoder. Returns `None` for anything else.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let stem = match name.strip_suffix(DBN_EXT) {
+            Some(stem) => stem,
+            None => name.strip_suffix(&decoded_suffix(decoded_file_schema(path)?))?,
+        };
+
+        let mut parts = stem.splitn(3, '_');
+        let date_format = format_description!("[year]-[month]-[day]");
+        let start = Date::parse(parts.next()?, date_format).ok()?;
+        let end = Date::parse(parts.next()?, date_format).ok()?;
+        let contract = parts.next()?;
+        parse_full_contract_symbol(contract)?;
+
+        Some(Self { path: path.to_path_buf(), contract: contract.to_string(), start, end })
+    }
+
+    pub fn is_decoded(&self) -> bool {
+        self.decoded_schema().is_some()
+    }
+
+    /// Schema of a decoded file; `None` for raw files, whose schema is in their DBN metadata.
+    pub fn decoded_schema(&self) -> Option<Schema> {
+        decoded_file_schema(&self.path)
+    }
+
+    /// Path without the `.dbn.zst` or `_ohlcv1m.json` suffix, e.g. `Hist_Fut_Data/ES/2024-01-05_2024-03-15_ESH2024`.
+    pub fn base_path(&self) -> String {
+        let path = self.path.to_string_lossy();
+        let suffix = self.decoded_schema().map_or_else(|| DBN_EXT.to_string(), decoded_suffix);
+        path.strip_suffix(suffix.as_str()).unwrap_or(&path).to_string()
+    }
+}
+
+/// Schema of a decoder output file. Only one-minute bars are decoded next to the raw files; other
+/// schemas count only inside their own folder, so resampled `_ohlcv1h.json` bars are not taken for downloads.
+fn decoded_file_schema(path: &Path) -> Option<Schema> {
+    let schema = decoded_schema(path.file_name()?.to_str()?)?;
+    let folder = path.parent().and_then(Path::file_name).and_then(|folder| folder.to_str());
+    (schema == Schema::Ohlcv1M || folder == Some(schema.as_str())).then_some(schema)
+}
+
+/// Every contract file in `dir`, raw and decoded, ordered by window.
+pub fn list_contract_files(dir: impl AsRef<Path>) -> Result<Vec<ContractFile>> {
+    let dir = dir.as_ref();
+    let mut files = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read data directory: {}", dir.display()))?
+        .filter_map(|entry| ContractFile::from_path(&entry.ok()?.path()))
+        .collect::<Vec<_>>();
+
+    sort_contract_files(&mut files);
+    Ok(files)
+}
+
+fn sort_contract_files(files: &mut [ContractFile]) {
+    files.sort_by(|a, b| {
+        (a.start, a.end, &a.contract, a.is_decoded()).cmp(&(b.start, b.end, &b.contract, b.is_decoded()))
+    });
+}
+
+fn dedup_contract_files(files: &mut Vec<ContractFile>) {
+    files.dedup_by(|later, earlier| {
+        later.contract == earlier.contract && later.start == earlier.start && later.end == earlier.end
+    });
+}
+
+/// One file per contract window in `dir`. Raw `.dbn.zst` files are preferred; decoded JSON is used
+/// for windows whose raw file is gone.
+pub fn unique_contract_files(dir: impl AsRef<Path>) -> Result<Vec<ContractFile>> {
+    let mut files = list_contract_files(dir)?;
+    dedup_contract_files(&mut files);
+    Ok(files)
+}
+
+/// One-minute bar files of `root` in `dir` (usually `Hist_Fut_Data/{root}`), as in `unique_contract_files`.
+/// Files are looked up in the data catalog when `dir` has one; the folder is scanned otherwise.
+pub fn find_contract_files(dir: impl AsRef<Path>, root: &str) -> Result<Vec<ContractFile>> {
+    let dir = dir.as_ref();
+    if let Some(catalog) = Catalog::locate(dir)? {
+        let query = CatalogQuery {
+            root: Some(root.to_string()),
+            schema: Some(Schema::Ohlcv1M),
+            ..CatalogQuery::default()
+        };
+        let mut files = catalog_contract_files(&catalog, &query);
+        files.retain(|file| file.path.starts_with(dir));
+        if !files.is_empty() {
+            return Ok(files);
+        }
+    }
+
+    let mut files = unique_contract_files(dir)?;
+    files.retain(|file| parse_full_contract_symbol(&file.contract).is_some_and(|(r, _, _)| r == root));
+    Ok(files)
+}
+
+/// Catalogued contract files matching `query` that are still on disk, ordered by window, preferring
+/// raw files over decoded ones.
+pub fn catalog_contract_files(catalog: &Catalog, query: &CatalogQuery) -> Vec<ContractFile> {
+    let mut files = catalog
+        .resolve(query)
+        .into_iter()
+        .filter(|path| path.exists())
+        .filter_map(|path| ContractFile::from_path(&path))
+        .collect::<Vec<_>>();
+    sort_contract_files(&mut files);
+    dedup_contract_files(&mut files);
+    files
+}
+
+/// Reads every 1-minute bar of a contract file, sorted by `ts_event`.
+pub async fn load_bars(file: &ContractFile) -> Result<Vec<JsonOhlcv>> {
+    let mut bars = read_bars(file).await?;
+    bars.sort_by_key(|bar| bar.ts_event);
+    Ok(bars)
+}
+
+/// Reads every 1-minute bar of a contract file in file order.
+pub async fn read_bars(file: &ContractFile) -> Result<Vec<JsonOhlcv>> {
+    let bars = if file.is_decoded() {
+        let reader = BufReader::new(
+            File::open(&file.path).with_context(|| format!("Failed to open {}", file.path.display()))?,
+        );
+        reader
+            .lines()
+            .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str::<JsonOhlcv>(&line?)?))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid bar in {}", file.path.display()))?
+    } else {
+        let mut decoder = AsyncDbnDecoder::from_zstd_file(&file.path)
+            .await
+            .with_context(|| format!("Failed to open {}", file.path.display()))?;
+        let mut bars = Vec::new();
+        while let Some(msg) = decoder.decode_record::<OhlcvMsg>().await? {
+            bars.push(JsonOhlcv {
+                instrument_name: file.contract.clone(),
+                instrument_id: msg.hd.instrument_id,
+                ts_event: msg.hd.ts_event,
+                open: msg.open,
+                high: msg.high,
+                low: msg.low,
+                close: msg.close,
+                volume: msg.volume,
+            });
+        }
+        bars
+    };
+
+    Ok(bars)
+}
+
+/// Converts a Databento fixed-point price (1e-9 units) to a decimal price.
+pub fn scale_price(price: i64) -> f64 {
+    price as f64 / FIXED_PRICE_SCALE as f64
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    #[test]
+    fn test_contract_file_from_path() {
+        let raw = ContractFile::from_path(Path::new("Hist_Fut_Data/ES/2024-01-05_2024-03-15_ESH2024.dbn.zst")).unwrap();
+        assert_eq!(raw.contract, "ESH2024");
+        assert_eq!((raw.start, raw.end), (date!(2024 - 01 - 05), date!(2024 - 03 - 15)));
+        assert!(!raw.is_decoded());
+
+        let decoded = ContractFile::from_path(Path::new("2024-01-05_2024-03-15_ESH2024_ohlcv1m.json")).unwrap();
+        assert!(decoded.is_decoded());
+
+        let trades = ContractFile::from_path(Path::new("ES/trades/2024-01-05_2024-03-15_ESH2024_trades.json")).unwrap();
+        assert_eq!(trades.decoded_schema(), Some(Schema::Trades));
+        assert_eq!(trades.base_path(), "ES/trades/2024-01-05_2024-03-15_ESH2024");
+        // Resampled bars next to the downloads are not contract files
+        assert!(ContractFile::from_path(Path::new("ES/2024-01-05_2024-03-15_ESH2024_ohlcv1h.json")).is_none());
+
+        assert!(ContractFile::from_path(Path::new("2024-01-05_2024-03-15_ESH4.dbn.zst")).is_none());
+        assert!(ContractFile::from_path(Path::new("notes.txt")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_decoded_bars_sorted() {
+        let dir = "test_output_processor_load";
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{dir}/2024-01-05_2024-03-15_ESH2024_ohlcv1m.json");
+        let bar = |ts_event| JsonOhlcv {
+            instrument_name: "ESH2024".to_string(),
+            instrument_id: 1,
+            ts_event,
+            open: 1,
+            high: 2,
+            low: 1,
+            close: 2,
+            volume: 3,
+        };
+        let lines = [bar(2), bar(1)].iter().map(|b| serde_json::to_string(b).unwrap()).collect::<Vec<_>>();
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+
+        let files = find_contract_files(dir, "ES").unwrap();
+        assert_eq!(files.len(), 1);
+        let bars = load_bars(&files[0]).await.unwrap();
+        assert_eq!(bars.iter().map(|b| b.ts_event).collect::<Vec<_>>(), vec![1, 2]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
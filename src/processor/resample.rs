@@ -0,0 +1,357 @@
+use anyhow::{bail, Context, Result};
+use databento::dbn::OhlcvMsg;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::downloader::calendar::globex_session_open;
+use crate::downloader::roll_calendar::trading_date;
+use crate::processor::{load_bars, ContractFile};
+use crate::types::JsonOhlcv;
+
+const NANOS_PER_MINUTE: u64 = 60_000_000_000;
+
+/// Read access to a 1-minute bar, so Databento records and decoded JSON resample the same way.
+pub trait OhlcvRecord {
+    fn instrument_id(&self) -> u32;
+    fn ts_event(&self) -> u64;
+    fn open(&self) -> i64;
+    fn high(&self) -> i64;
+    fn low(&self) -> i64;
+    fn close(&self) -> i64;
+    fn volume(&self) -> u64;
+}
+
+impl OhlcvRecord for OhlcvMsg {
+    fn instrument_id(&self) -> u32 {
+        self.hd.instrument_id
+    }
+    fn ts_event(&self) -> u64 {
+        self.hd.ts_event
+    }
+    fn open(&self) -> i64 {
+        self.open
+    }
+    fn high(&self) -> i64 {
+        self.high
+    }
+    fn low(&self) -> i64 {
+        self.low
+    }
+    fn close(&self) -> i64 {
+        self.close
+    }
+    fn volume(&self) -> u64 {
+        self.volume
+    }
+}
+
+impl OhlcvRecord for JsonOhlcv {
+    fn instrument_id(&self) -> u32 {
+        self.instrument_id
+    }
+    fn ts_event(&self) -> u64 {
+        self.ts_event
+    }
+    fn open(&self) -> i64 {
+        self.open
+    }
+    fn high(&self) -> i64 {
+        self.high
+    }
+    fn low(&self) -> i64 {
+        self.low
+    }
+    fn close(&self) -> i64 {
+        self.close
+    }
+    fn volume(&self) -> u64 {
+        self.volume
+    }
+}
+
+/// Fixed bar length, a whole number of minutes. Parsed from `5m`, `1h`, `1d` and the like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BarInterval {
+    minutes: u64,
+}
+
+/// Where bar boundaries are anchored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BarAlignment {
+    /// Multiples of the interval since midnight UTC; daily bars are UTC calendar days.
+    #[default]
+    Midnight,
+    /// Multiples of the interval since the CME Globex session open (17:00 Central, DST-aware).
+    /// Bars never span two sessions and daily bars are whole sessions.
+    SessionOpen,
+}
+
+impl BarInterval {
+    pub fn from_minutes(minutes: u64) -> Result<Self> {
+        if minutes == 0 {
+            bail!("Bar interval must be at least one minute");
+        }
+        Ok(Self { minutes })
+    }
+
+    pub fn minutes(&self) -> u64 {
+        self.minutes
+    }
+
+    fn nanos(&self) -> u64 {
+        self.minutes * NANOS_PER_MINUTE
+    }
+
+    /// Start of the bar containing `ts` (nanoseconds since the UNIX epoch).
+    pub fn bucket_start(&self, ts: u64, alignment: BarAlignment) -> u64 {
+        let anchor = match alignment {
+            BarAlignment::Midnight => 0,
+            BarAlignment::SessionOpen => trading_date(ts)
+                .map(|date| globex_session_open(date).unix_timestamp_nanos() as u64)
+                .filter(|&open| open <= ts)
+                .unwrap_or(0),
+        };
+        anchor + (ts - anchor) / self.nanos() * self.nanos()
+    }
+}
+
+impl fmt::Display for BarInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.minutes {
+            m if m % 1440 == 0 => write!(f, "{}d", m / 1440),
+            m if m % 60 == 0 => write!(f, "{}h", m / 60),
+            m => write!(f, "{m}m"),
+        }
+    }
+}
+
+impl FromStr for BarInterval {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+        let (count, unit) = value.split_at(split);
+        let count = count.parse::<u64>().with_context(|| format!("Invalid bar interval '{value}'"))?;
+        let minutes = match unit {
+            "m" | "min" => count,
+            "h" => count * 60,
+            "d" => count * 1440,
+            _ => bail!("Unknown bar interval unit in '{value}', expected m, h or d"),
+        };
+        Self::from_minutes(minutes)
+    }
+}
+
+impl fmt::Display for BarAlignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BarAlignment::Midnight => write!(f, "midnight"),
+            BarAlignment::SessionOpen => write!(f, "session"),
+        }
+    }
+}
+
+impl FromStr for BarAlignment {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().replace('-', "_").as_str() {
+            "midnight" | "utc" => Ok(BarAlignment::Midnight),
+            "session" | "session_open" => Ok(BarAlignment::SessionOpen),
+            _ => bail!("Unknown bar alignment '{value}', expected midnight or session"),
+        }
+    }
+}
+
+/// Streaming aggregator. Feed 1-minute bars in `ts_event` order; a finished bar is returned as soon as
+/// a bar from a later bucket of the same instrument arrives. Call `finish` for the bars still open.
+pub struct Resampler {
+    interval: BarInterval,
+    alignment: BarAlignment,
+    instrument_name: String,
+    open_bars: HashMap<u32, JsonOhlcv>,
+}
+
+impl Resampler {
+    pub fn new(interval: BarInterval, alignment: BarAlignment, instrument_name: impl Into<String>) -> Self {
+        Self { interval, alignment, instrument_name: instrument_name.into(), open_bars: HashMap::new() }
+    }
+
+    pub fn push<B: OhlcvRecord>(&mut self, bar: &B) -> Option<JsonOhlcv> {
+        let bucket = self.interval.bucket_start(bar.ts_event(), self.alignment);
+
+        if let Some(current) = self.open_bars.get_mut(&bar.instrument_id())
+            && current.ts_event == bucket
+        {
+            current.high = current.high.max(bar.high());
+            current.low = current.low.min(bar.low());
+            current.close = bar.close();
+            current.volume += bar.volume();
+            return None;
+        }
+
+        let started = JsonOhlcv {
+            instrument_name: self.instrument_name.clone(),
+            instrument_id: bar.instrument_id(),
+            ts_event: bucket,
+            open: bar.open(),
+            high: bar.high(),
+            low: bar.low(),
+            close: bar.close(),
+            volume: bar.volume(),
+        };
+        self.open_bars.insert(bar.instrument_id(), started)
+    }
+
+    /// Bars still being built, in `ts_event` order.
+    pub fn finish(self) -> Vec<JsonOhlcv> {
+        let mut bars = self.open_bars.into_values().collect::<Vec<_>>();
+        bars.sort_by_key(|bar| (bar.ts_event, bar.instrument_id));
+        bars
+    }
+}
+
+/// Aggregates sorted 1-minute bars into `interval` bars. Each output bar is stamped with its start time,
+/// opens at the first bar's open, closes at the last bar's close and sums volume.
+pub fn resample<'a, B: OhlcvRecord + 'a>(
+    bars: impl IntoIterator<Item = &'a B>,
+    interval: BarInterval,
+    alignment: BarAlignment,
+    instrument_name: &str,
+) -> Vec<JsonOhlcv> {
+    let mut resampler = Resampler::new(interval, alignment, instrument_name);
+    let mut output = bars.into_iter().filter_map(|bar| resampler.push(bar)).collect::<Vec<_>>();
+    output.extend(resampler.finish());
+    output.sort_by_key(|bar| (bar.ts_event, bar.instrument_id));
+    output
+}
+
+/// Resamples one downloaded contract file and writes `{base}_ohlcv{interval}.json` next to it.
+/// Returns the output path.
+pub async fn resample_contract_file(
+    file: &ContractFile,
+    interval: BarInterval,
+    alignment: BarAlignment,
+) -> Result<String> {
+    let bars = load_bars(file).await?;
+    let resampled = resample(&bars, interval, alignment, &file.contract);
+    let output_path = format!("{}_ohlcv{interval}.json", file.base_path());
+    write_bars(&output_path, &resampled)?;
+    Ok(output_path)
+}
+
+/// Writes bars as JSON lines, the decoder's format.
+pub fn write_bars<T: Serialize>(path: impl AsRef<Path>, bars: &[T]) -> Result<()> {
+    let path = path.as_ref();
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    for bar in bars {
+        serde_json::to_writer(&mut writer, bar)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{macros::datetime, OffsetDateTime};
+
+    fn ts(at: OffsetDateTime) -> u64 {
+        at.unix_timestamp_nanos() as u64
+    }
+
+    fn bar(at: OffsetDateTime, open: i64, high: i64, low: i64, close: i64, volume: u64) -> JsonOhlcv {
+        JsonOhlcv {
+            instrument_name: "ESH2024".to_string(),
+            instrument_id: 1,
+            ts_event: ts(at),
+            open,
+            high,
+            low,
+            close,
+            volume,
+        }
+    }
+
+    #[test]
+    fn test_interval_parses_and_displays() {
+        assert_eq!("5m".parse::<BarInterval>().unwrap().minutes(), 5);
+        assert_eq!("1h".parse::<BarInterval>().unwrap().minutes(), 60);
+        assert_eq!("1D".parse::<BarInterval>().unwrap().to_string(), "1d");
+        assert_eq!("90m".parse::<BarInterval>().unwrap().to_string(), "90m");
+        assert!("0m".parse::<BarInterval>().is_err());
+        assert!("5s".parse::<BarInterval>().is_err());
+    }
+
+    #[test]
+    fn test_resample_ohlcv_semantics() {
+        let bars = vec![
+            bar(datetime!(2024 - 01 - 08 14:30 UTC), 10, 12, 9, 11, 5),
+            bar(datetime!(2024 - 01 - 08 14:31 UTC), 11, 15, 10, 14, 7),
+            bar(datetime!(2024 - 01 - 08 14:34 UTC), 14, 14, 8, 9, 1),
+            bar(datetime!(2024 - 01 - 08 14:35 UTC), 9, 10, 9, 10, 2),
+        ];
+
+        let five = resample(&bars, "5m".parse().unwrap(), BarAlignment::Midnight, "ESH2024");
+        assert_eq!(five.len(), 2);
+        assert_eq!(five[0].ts_event, ts(datetime!(2024 - 01 - 08 14:30 UTC)));
+        assert_eq!((five[0].open, five[0].high, five[0].low, five[0].close, five[0].volume), (10, 15, 8, 9, 13));
+        assert_eq!((five[1].open, five[1].close, five[1].volume), (9, 10, 2));
+    }
+
+    #[test]
+    fn test_session_aligned_daily_bars_follow_globex_sessions() {
+        // Session for Tuesday 2024-01-09 opens Monday 17:00 CST (23:00 UTC)
+        let bars = vec![
+            bar(datetime!(2024 - 01 - 08 20:59 UTC), 1, 1, 1, 1, 1),
+            bar(datetime!(2024 - 01 - 08 23:00 UTC), 2, 2, 2, 2, 1),
+            bar(datetime!(2024 - 01 - 09 20:59 UTC), 3, 3, 3, 3, 1),
+        ];
+
+        let daily = resample(&bars, "1d".parse().unwrap(), BarAlignment::SessionOpen, "ESH2024");
+        assert_eq!(daily.len(), 2);
+        assert_eq!(daily[1].ts_event, ts(datetime!(2024 - 01 - 08 23:00 UTC)));
+        assert_eq!((daily[1].open, daily[1].close, daily[1].volume), (2, 3, 2));
+
+        let utc_daily = resample(&bars, "1d".parse().unwrap(), BarAlignment::Midnight, "ESH2024");
+        assert_eq!(utc_daily.iter().map(|b| b.volume).collect::<Vec<_>>(), vec![2, 1]);
+    }
+
+    #[test]
+    fn test_session_alignment_offsets_hourly_bars() {
+        // Summer session opens 22:00 UTC, so hourly bars start on the hour; a 90m bar starts at 23:30
+        let bar_at = bar(datetime!(2024 - 07 - 08 23:45 UTC), 1, 1, 1, 1, 1);
+        let interval: BarInterval = "90m".parse().unwrap();
+        assert_eq!(
+            interval.bucket_start(bar_at.ts_event, BarAlignment::SessionOpen),
+            ts(datetime!(2024 - 07 - 08 23:30 UTC))
+        );
+        assert_eq!(
+            interval.bucket_start(bar_at.ts_event, BarAlignment::Midnight),
+            ts(datetime!(2024 - 07 - 08 22:30 UTC))
+        );
+    }
+
+    #[test]
+    fn test_instruments_are_resampled_separately() {
+        let mut other = bar(datetime!(2024 - 01 - 08 14:31 UTC), 50, 50, 50, 50, 4);
+        other.instrument_id = 2;
+        let bars = vec![bar(datetime!(2024 - 01 - 08 14:30 UTC), 10, 10, 10, 10, 1), other];
+
+        let five = resample(&bars, "5m".parse().unwrap(), BarAlignment::Midnight, "mixed");
+        assert_eq!(five.iter().map(|b| (b.instrument_id, b.volume)).collect::<Vec<_>>(), vec![(1, 1), (2, 4)]);
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use time::{Date, OffsetDateTime};
+
+use crate::downloader::contracts::parse_full_contract_symbol;
+use crate::downloader::products::product_registry;
+use crate::downloader::sessions::{SessionPhase, SessionTemplate, TradingSession};
+use crate::processor::{load_bars, resample::write_bars, ContractFile};
+use crate::types::JsonOhlcv;
+
+/// Which part of each trading session to keep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionFilter {
+    /// Every bar inside a session.
+    #[default]
+    All,
+    /// Regular trading hours only.
+    Regular,
+    /// Electronic hours outside the regular session. Intraday breaks are not trading hours and are left out.
+    Extended,
+}
+
+impl SessionFilter {
+    pub fn matches(&self, phase: SessionPhase) -> bool {
+        match self {
+            SessionFilter::All => phase != SessionPhase::Closed,
+            SessionFilter::Regular => phase == SessionPhase::Regular,
+            SessionFilter::Extended => phase == SessionPhase::Extended,
+        }
+    }
+}
+
+/// A bar with the session it traded in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionBar {
+    #[serde(flatten)]
+    pub bar: JsonOhlcv,
+    /// Trading date of the enclosing session; `None` for bars outside every session.
+    pub trading_date: Option<Date>,
+    pub session: SessionPhase,
+}
+
+/// One bar per trading session, stamped with the open of the filtered part of the session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyBar {
+    pub trading_date: Date,
+    #[serde(flatten)]
+    pub bar: JsonOhlcv,
+}
+
+/// Session template of the product `contract` (e.g. `ESH2024`) belongs to.
+pub fn contract_session(contract: &str) -> Result<&'static SessionTemplate> {
+    let (root, _, _) =
+        parse_full_contract_symbol(contract).with_context(|| format!("Invalid contract name: {contract}"))?;
+    let product = product_registry()?.get(root).with_context(|| format!("Unknown product: {root}"))?;
+    Ok(product.session_template())
+}
+
+/// Tags sorted bars with their trading date and session phase.
+pub fn tag_bars(bars: &[JsonOhlcv], template: &SessionTemplate) -> Vec<SessionBar> {
+    // Consecutive bars almost always share a session, so only look one up when leaving the current one
+    let mut current: Option<TradingSession> = None;
+
+    bars.iter()
+        .map(|bar| {
+            let at = bar_time(bar.ts_event);
+            if !current.as_ref().is_some_and(|session| session.contains(at)) {
+                current = template.session_at(at);
+            }
+            let session = current.as_ref().filter(|session| session.contains(at));
+
+            SessionBar {
+                bar: bar.clone(),
+                trading_date: session.map(|session| session.trading_date),
+                session: session.map_or(SessionPhase::Closed, |session| session.phase(at)),
+            }
+        })
+        .collect()
+}
+
+/// Bars falling in the part of the session `filter` selects.
+pub fn filter_bars(bars: &[JsonOhlcv], template: &SessionTemplate, filter: SessionFilter) -> Vec<JsonOhlcv> {
+    tag_bars(bars, template)
+        .into_iter()
+        .filter(|tagged| filter.matches(tagged.session))
+        .map(|tagged| tagged.bar)
+        .collect()
+}
+
+/// Aggregates sorted bars into one bar per trading date, keeping only the part of the session `filter`
+/// selects. Regular-hours bars are stamped with the regular open, the others with the session open.
+pub fn session_daily_bars(bars: &[JsonOhlcv], template: &SessionTemplate, filter: SessionFilter) -> Vec<DailyBar> {
+    let mut daily: Vec<DailyBar> = Vec::new();
+
+    for tagged in tag_bars(bars, template) {
+        let Some(trading_date) = tagged.trading_date.filter(|_| filter.matches(tagged.session)) else {
+            continue;
+        };
+        let bar = tagged.bar;
+
+        if let Some(current) = daily.last_mut()
+            && current.trading_date == trading_date
+            && current.bar.instrument_id == bar.instrument_id
+        {
+            current.bar.high = current.bar.high.max(bar.high);
+            current.bar.low = current.bar.low.min(bar.low);
+            current.bar.close = bar.close;
+            current.bar.volume += bar.volume;
+            continue;
+        }
+
+        let session = template.session(trading_date).expect("Tagged bars belong to a session");
+        let opened = match (filter, session.regular_hours) {
+            (SessionFilter::Regular, Some((regular_open, _))) => regular_open,
+            _ => session.open,
+        };
+        daily.push(DailyBar { trading_date, bar: JsonOhlcv { ts_event: opened.unix_timestamp_nanos() as u64, ..bar } });
+    }
+
+    daily
+}
+
+/// Writes the session-filtered bars of one contract file next to it, as `{base}_{filter}.json`,
+/// or one bar per session as `{base}_{filter}_daily.json`. Returns the output path.
+pub async fn session_contract_file(file: &ContractFile, filter: SessionFilter, daily: bool) -> Result<String> {
+    let template = contract_session(&file.contract)?;
+    let bars = load_bars(file).await?;
+
+    if daily {
+        let output_path = format!("{}_{filter}_daily.json", file.base_path());
+        write_bars(&output_path, &session_daily_bars(&bars, template, filter))?;
+        Ok(output_path)
+    } else {
+        let output_path = format!("{}_{filter}.json", file.base_path());
+        let tagged = tag_bars(&bars, template)
+            .into_iter()
+            .filter(|tagged| filter.matches(tagged.session))
+            .collect::<Vec<_>>();
+        write_bars(&output_path, &tagged)?;
+        Ok(output_path)
+    }
+}
+
+fn bar_time(ts_event: u64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp_nanos(ts_event as i128).expect("Valid timestamp")
+}
+
+impl fmt::Display for SessionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionFilter::All => write!(f, "all"),
+            SessionFilter::Regular => write!(f, "rth"),
+            SessionFilter::Extended => write!(f, "eth"),
+        }
+    }
+}
+
+impl FromStr for SessionFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "all" => Ok(SessionFilter::All),
+            "rth" | "regular" => Ok(SessionFilter::Regular),
+            "eth" | "extended" => Ok(SessionFilter::Extended),
+            _ => bail!("Unknown session filter '{value}', expected all, rth or eth"),
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::{date, datetime};
+
+    fn bar(at: OffsetDateTime, price: i64, volume: u64) -> JsonOhlcv {
+        JsonOhlcv {
+            instrument_name: "ESU2024".to_string(),
+            instrument_id: 1,
+            ts_event: at.unix_timestamp_nanos() as u64,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume,
+        }
+    }
+
+    fn sample_bars() -> Vec<JsonOhlcv> {
+        vec![
+            bar(datetime!(2024 - 07 - 08 22:00 UTC), 1, 1), // Globex open for Tuesday
+            bar(datetime!(2024 - 07 - 09 13:30 UTC), 2, 10), // RTH open
+            bar(datetime!(2024 - 07 - 09 19:59 UTC), 3, 10), // last RTH minute
+            bar(datetime!(2024 - 07 - 09 20:45 UTC), 4, 1), // after the RTH close
+            bar(datetime!(2024 - 07 - 09 21:30 UTC), 5, 1), // daily maintenance window
+        ]
+    }
+
+    #[test]
+    fn test_tag_bars() {
+        let template = contract_session("ESU2024").unwrap();
+        let tagged = tag_bars(&sample_bars(), template);
+
+        assert_eq!(
+            tagged.iter().map(|t| t.session).collect::<Vec<_>>(),
+            vec![
+                SessionPhase::Extended,
+                SessionPhase::Regular,
+                SessionPhase::Regular,
+                SessionPhase::Extended,
+                SessionPhase::Closed
+            ]
+        );
+        assert_eq!(tagged[0].trading_date, Some(date!(2024 - 07 - 09)));
+        assert_eq!(tagged[4].trading_date, None);
+    }
+
+    #[test]
+    fn test_filter_and_daily_bars() {
+        let template = contract_session("ESU2024").unwrap();
+        let bars = sample_bars();
+
+        assert_eq!(filter_bars(&bars, template, SessionFilter::Regular).len(), 2);
+        assert_eq!(filter_bars(&bars, template, SessionFilter::Extended).len(), 2);
+        assert_eq!(filter_bars(&bars, template, SessionFilter::All).len(), 4);
+
+        // Stamped inside the 15:15-15:30 Chicago halt, which ended in June 2021
+        let halted = [bar(datetime!(2021 - 06 - 22 20:20 UTC), 6, 1)];
+        assert!(filter_bars(&halted, template, SessionFilter::Extended).is_empty());
+        assert!(filter_bars(&halted, template, SessionFilter::Regular).is_empty());
+        let traded = [bar(datetime!(2024 - 07 - 09 20:20 UTC), 6, 1)];
+        assert_eq!(filter_bars(&traded, template, SessionFilter::Extended).len(), 1);
+
+        let rth = session_daily_bars(&bars, template, SessionFilter::Regular);
+        assert_eq!(rth.len(), 1);
+        assert_eq!(rth[0].bar.ts_event, datetime!(2024 - 07 - 09 13:30 UTC).unix_timestamp_nanos() as u64);
+        assert_eq!((rth[0].bar.open, rth[0].bar.close, rth[0].bar.volume), (2, 3, 20));
+
+        let full = session_daily_bars(&bars, template, SessionFilter::All);
+        assert_eq!(full[0].bar.ts_event, datetime!(2024 - 07 - 08 22:00 UTC).unix_timestamp_nanos() as u64);
+        assert_eq!((full[0].bar.open, full[0].bar.close, full[0].bar.volume), (1, 4, 22));
+    }
+
+    #[test]
+    fn test_filter_parses() {
+        assert_eq!("RTH".parse::<SessionFilter>().unwrap(), SessionFilter::Regular);
+        assert_eq!("eth".parse::<SessionFilter>().unwrap().to_string(), "eth");
+        assert!("pit".parse::<SessionFilter>().is_err());
+    }
+}
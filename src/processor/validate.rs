@@ -0,0 +1,409 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+use time::{Date, OffsetDateTime};
+
+use crate::downloader::range::download_time_range;
+use crate::downloader::sessions::{SessionTemplate, TradingSession};
+use crate::processor::sessions::{contract_session, tag_bars};
+use crate::processor::{read_bars, scale_price, unique_contract_files, ContractFile};
+use crate::types::JsonOhlcv;
+
+const NANOS_PER_MINUTE: i64 = 60_000_000_000;
+
+/// Thresholds of the validation pass.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ValidationOptions {
+    /// Trading minutes without a bar, inside one session, before the hole is reported as a gap.
+    /// Quiet markets skip minutes without trades, so short holes are normal.
+    pub min_gap_minutes: i64,
+    /// Close-to-close change between consecutive bars, as a fraction of the earlier close, reported as a jump.
+    pub jump_threshold: f64,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        Self { min_gap_minutes: 30, jump_threshold: 0.03 }
+    }
+}
+
+/// Problem found in a contract file. Timestamps are `ts_event` nanoseconds, like the bars.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// A session inside the file window without a single bar.
+    MissingSession { trading_date: Date },
+    /// Trading minutes without bars between two bars of the same session.
+    Gap { from: u64, to: u64, missing_minutes: i64 },
+    /// More than one bar with the same timestamp.
+    Duplicate { ts_event: u64 },
+    /// A bar stored after a later one.
+    OutOfOrder { ts_event: u64, previous: u64 },
+    /// High below low, or open/close outside the high-low range.
+    OhlcInconsistent { ts_event: u64, reason: String },
+    /// A bar without volume.
+    ZeroVolume { ts_event: u64 },
+    /// A bar while the product's session is closed.
+    OutsideSession { ts_event: u64 },
+    /// Close-to-close move above `jump_threshold`.
+    Jump { ts_event: u64, from_price: f64, to_price: f64, change: f64 },
+}
+
+impl Issue {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Issue::MissingSession { .. } => "missing_session",
+            Issue::Gap { .. } => "gap",
+            Issue::Duplicate { .. } => "duplicate",
+            Issue::OutOfOrder { .. } => "out_of_order",
+            Issue::OhlcInconsistent { .. } => "ohlc_inconsistent",
+            Issue::ZeroVolume { .. } => "zero_volume",
+            Issue::OutsideSession { .. } => "outside_session",
+            Issue::Jump { .. } => "jump",
+        }
+    }
+}
+
+/// Validation result of one contract file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub contract: String,
+    pub path: PathBuf,
+    pub start: Date,
+    pub end: Date,
+    /// Session template the bars were checked against.
+    pub session: String,
+    pub bars: usize,
+    /// Sessions entirely inside the file window.
+    pub expected_sessions: usize,
+    pub sessions_with_data: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Number of issues of each kind, by `Issue::kind`.
+    pub fn issue_counts(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for issue in &self.issues {
+            *counts.entry(issue.kind()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Writes the report as pretty JSON to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents).with_context(|| format!("Failed to write validation report {}", path.display()))
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} to {}): {} bars, {}/{} sessions with data",
+            self.contract, self.start, self.end, self.bars, self.sessions_with_data, self.expected_sessions
+        )?;
+        if self.is_clean() {
+            return write!(f, ", clean");
+        }
+        for (kind, count) in self.issue_counts() {
+            write!(f, ", {count} {kind}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks bars of `contract` covering the inclusive `start..=end` window against `template`.
+/// `bars` must be in file order so out-of-order records can be found.
+pub fn validate_bars(
+    bars: &[JsonOhlcv],
+    template: &SessionTemplate,
+    start: Date,
+    end: Date,
+    options: &ValidationOptions,
+) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    // Record order as stored
+    for pair in bars.windows(2) {
+        if pair[1].ts_event < pair[0].ts_event {
+            issues.push(Issue::OutOfOrder { ts_event: pair[1].ts_event, previous: pair[0].ts_event });
+        }
+    }
+
+    let mut sorted = bars.to_vec();
+    sorted.sort_by_key(|bar| (bar.instrument_id, bar.ts_event));
+    sorted.dedup_by(|later, earlier| {
+        let duplicate = later.instrument_id == earlier.instrument_id && later.ts_event == earlier.ts_event;
+        if duplicate {
+            issues.push(Issue::Duplicate { ts_event: later.ts_event });
+        }
+        duplicate
+    });
+
+    for bar in &sorted {
+        if let Some(reason) = ohlc_inconsistency(bar) {
+            issues.push(Issue::OhlcInconsistent { ts_event: bar.ts_event, reason });
+        }
+        if bar.volume == 0 {
+            issues.push(Issue::ZeroVolume { ts_event: bar.ts_event });
+        }
+    }
+
+    let mut last_close: HashMap<u32, i64> = HashMap::new();
+    for bar in &sorted {
+        if let Some(previous) = last_close.insert(bar.instrument_id, bar.close)
+            && previous != 0
+        {
+            let change = (bar.close - previous) as f64 / previous.abs() as f64;
+            if change.abs() > options.jump_threshold {
+                issues.push(Issue::Jump {
+                    ts_event: bar.ts_event,
+                    from_price: scale_price(previous),
+                    to_price: scale_price(bar.close),
+                    change,
+                });
+            }
+        }
+    }
+
+    let tagged = tag_bars(&sorted, template);
+    let mut traded_dates = HashSet::new();
+    for tagged in &tagged {
+        match tagged.trading_date {
+            Some(date) => {
+                traded_dates.insert(date);
+            }
+            None => issues.push(Issue::OutsideSession { ts_event: tagged.bar.ts_event }),
+        }
+    }
+
+    for pair in tagged.windows(2) {
+        let (earlier, later) = (&pair[0], &pair[1]);
+        if earlier.bar.instrument_id != later.bar.instrument_id
+            || earlier.trading_date.is_none()
+            || earlier.trading_date != later.trading_date
+        {
+            continue;
+        }
+
+        let missing_minutes = (later.bar.ts_event - earlier.bar.ts_event) as i64 / NANOS_PER_MINUTE - 1;
+        if missing_minutes <= options.min_gap_minutes {
+            continue;
+        }
+        // Scheduled breaks are not gaps
+        let session = earlier.trading_date.and_then(|date| template.session(date));
+        let break_minutes = session.map_or(0, |session| {
+            let (from, to) = (bar_time(earlier.bar.ts_event), bar_time(later.bar.ts_event));
+            session
+                .breaks
+                .iter()
+                .map(|&(start, end)| (end.min(to) - start.max(from)).whole_minutes().max(0))
+                .sum::<i64>()
+        });
+        let missing_minutes = missing_minutes - break_minutes;
+        if missing_minutes > options.min_gap_minutes {
+            issues.push(Issue::Gap { from: earlier.bar.ts_event, to: later.bar.ts_event, missing_minutes });
+        }
+    }
+
+    for session in expected_sessions(template, start, end) {
+        if !traded_dates.contains(&session.trading_date) {
+            issues.push(Issue::MissingSession { trading_date: session.trading_date });
+        }
+    }
+
+    issues.sort_by_key(issue_time);
+    issues
+}
+
+/// Validates one contract file against the session template of its product.
+pub async fn validate_contract_file(file: &ContractFile, options: &ValidationOptions) -> Result<ValidationReport> {
+    let template = contract_session(&file.contract)?;
+    let bars = read_bars(file).await?;
+    let issues = validate_bars(&bars, template, file.start, file.end, options);
+
+    let expected = expected_sessions(template, file.start, file.end).len();
+    let missing = issues.iter().filter(|issue| matches!(issue, Issue::MissingSession { .. })).count();
+
+    Ok(ValidationReport {
+        contract: file.contract.clone(),
+        path: file.path.clone(),
+        start: file.start,
+        end: file.end,
+        session: template.name.to_string(),
+        bars: bars.len(),
+        expected_sessions: expected,
+        sessions_with_data: expected - missing,
+        issues,
+    })
+}
+
+/// Validates every contract file in `dir` and writes `{base}_validation.json` next to each one.
+pub async fn validate_directory(dir: impl AsRef<Path>, options: &ValidationOptions) -> Result<Vec<ValidationReport>> {
+    let mut reports = Vec::new();
+    for file in unique_contract_files(dir)? {
+        let report = validate_contract_file(&file, options).await?;
+        report.save(report_path(&file))?;
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Path of the JSON report written for `file`.
+pub fn report_path(file: &ContractFile) -> String {
+    format!("{}_validation.json", file.base_path())
+}
+
+/// Human-readable summary: one line per contract and a total.
+pub fn summarize(reports: &[ValidationReport]) -> String {
+    let mut lines = reports.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let flagged = reports.iter().filter(|report| !report.is_clean()).count();
+    let issues = reports.iter().map(|report| report.issues.len()).sum::<usize>();
+    lines.push(format!("{} files checked, {flagged} with issues, {issues} issues in total", reports.len()));
+    lines.join("\n")
+}
+
+/// Sessions that open and close inside the downloaded `start..=end` window.
+fn expected_sessions(template: &SessionTemplate, start: Date, end: Date) -> Vec<TradingSession> {
+    let (window_start, window_end) = download_time_range(start, end);
+    template
+        .sessions_between(start, end)
+        .into_iter()
+        .filter(|session| session.open >= window_start && session.close <= window_end)
+        .collect()
+}
+
+fn ohlc_inconsistency(bar: &JsonOhlcv) -> Option<String> {
+    if bar.high < bar.low {
+        Some("high below low".to_string())
+    } else if bar.open < bar.low || bar.open > bar.high {
+        Some("open outside high-low range".to_string())
+    } else if bar.close < bar.low || bar.close > bar.high {
+        Some("close outside high-low range".to_string())
+    } else {
+        None
+    }
+}
+
+fn issue_time(issue: &Issue) -> u64 {
+    match issue {
+        Issue::MissingSession { trading_date } => {
+            trading_date.midnight().assume_utc().unix_timestamp_nanos() as u64
+        }
+        Issue::Gap { from: ts_event, .. }
+        | Issue::Duplicate { ts_event }
+        | Issue::OutOfOrder { ts_event, .. }
+        | Issue::OhlcInconsistent { ts_event, .. }
+        | Issue::ZeroVolume { ts_event }
+        | Issue::OutsideSession { ts_event }
+        | Issue::Jump { ts_event, .. } => *ts_event,
+    }
+}
+
+fn bar_time(ts_event: u64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp_nanos(ts_event as i128).expect("Valid timestamp")
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::sessions::session_template;
+    use time::macros::{date, datetime};
+
+    fn bar(at: OffsetDateTime, open: i64, high: i64, low: i64, close: i64, volume: u64) -> JsonOhlcv {
+        JsonOhlcv {
+            instrument_name: "ESU2024".to_string(),
+            instrument_id: 1,
+            ts_event: at.unix_timestamp_nanos() as u64,
+            open,
+            high,
+            low,
+            close,
+            volume,
+        }
+    }
+
+    fn kinds(issues: &[Issue]) -> Vec<&'static str> {
+        issues.iter().map(Issue::kind).collect()
+    }
+
+    #[test]
+    fn test_clean_bars_have_no_issues() {
+        let template = session_template("equity_index").unwrap();
+        let bars = vec![
+            bar(datetime!(2024 - 07 - 09 13:30 UTC), 100, 101, 99, 100, 5),
+            bar(datetime!(2024 - 07 - 09 13:31 UTC), 100, 102, 100, 101, 5),
+        ];
+        // A single-day window has no complete session, so nothing is missing
+        let issues = validate_bars(&bars, template, date!(2024 - 07 - 09), date!(2024 - 07 - 09), &ValidationOptions::default());
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+
+    #[test]
+    fn test_record_level_issues() {
+        let template = session_template("equity_index").unwrap();
+        let bars = vec![
+            bar(datetime!(2024 - 07 - 09 13:31 UTC), 100, 101, 99, 100, 5),
+            bar(datetime!(2024 - 07 - 09 13:30 UTC), 100, 101, 99, 100, 5),
+            bar(datetime!(2024 - 07 - 09 13:30 UTC), 100, 101, 99, 100, 5),
+            bar(datetime!(2024 - 07 - 09 13:32 UTC), 100, 99, 101, 100, 5),
+            bar(datetime!(2024 - 07 - 09 13:33 UTC), 100, 101, 99, 105, 0),
+            bar(datetime!(2024 - 07 - 09 21:30 UTC), 105, 105, 105, 105, 1),
+        ];
+        let issues = validate_bars(&bars, template, date!(2024 - 07 - 09), date!(2024 - 07 - 09), &ValidationOptions::default());
+        assert_eq!(
+            kinds(&issues),
+            vec!["out_of_order", "duplicate", "ohlc_inconsistent", "ohlc_inconsistent", "zero_volume", "jump", "outside_session"]
+        );
+    }
+
+    #[test]
+    fn test_gaps_skip_breaks_and_missing_sessions_are_reported() {
+        let template = session_template("equity_index").unwrap();
+        let options = ValidationOptions { min_gap_minutes: 10, ..ValidationOptions::default() };
+        let bars = vec![
+            // Tuesday: 15:14 to 15:31 CT, no longer a halt since June 2021
+            bar(datetime!(2024 - 07 - 09 20:14 UTC), 100, 100, 100, 100, 1),
+            bar(datetime!(2024 - 07 - 09 20:31 UTC), 100, 100, 100, 100, 1),
+            // Wednesday: an hour without bars
+            bar(datetime!(2024 - 07 - 10 14:00 UTC), 100, 100, 100, 100, 1),
+            bar(datetime!(2024 - 07 - 10 15:01 UTC), 100, 100, 100, 100, 1),
+        ];
+        let issues = validate_bars(&bars, template, date!(2024 - 07 - 08), date!(2024 - 07 - 11), &options);
+        assert_eq!(
+            issues,
+            vec![
+                Issue::Gap {
+                    from: datetime!(2024 - 07 - 09 20:14 UTC).unix_timestamp_nanos() as u64,
+                    to: datetime!(2024 - 07 - 09 20:31 UTC).unix_timestamp_nanos() as u64,
+                    missing_minutes: 16
+                },
+                Issue::Gap {
+                    from: datetime!(2024 - 07 - 10 14:00 UTC).unix_timestamp_nanos() as u64,
+                    to: datetime!(2024 - 07 - 10 15:01 UTC).unix_timestamp_nanos() as u64,
+                    missing_minutes: 60
+                },
+                Issue::MissingSession { trading_date: date!(2024 - 07 - 11) },
+            ]
+        );
+
+        // While the 15:15-15:30 halt was in effect the same span is 16 minutes less the 15-minute break
+        let halted = vec![
+            bar(datetime!(2021 - 06 - 22 20:14 UTC), 100, 100, 100, 100, 1),
+            bar(datetime!(2021 - 06 - 22 20:31 UTC), 100, 100, 100, 100, 1),
+        ];
+        assert!(validate_bars(&halted, template, date!(2021 - 06 - 22), date!(2021 - 06 - 22), &options).is_empty());
+    }
+}
@@ -0,0 +1,289 @@
+use std::{fmt, sync::Mutex, time::Duration};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+/// One step of a download, cost estimate or decode run. `task` names the contract (or, when decoding,
+/// the file) the event is about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// The task is waiting for a free slot.
+    Queued { task: String },
+    Started { task: String },
+    /// Size of the file the current request is writing so far.
+    BytesReceived { task: String, bytes: u64 },
+    /// A transient failure; the request is repeated after `delay`.
+    Retrying { task: String, attempt: u32, delay: Duration, error: String },
+    /// The task succeeded; `cost_usd` is what it cost or is quoted at, when known. `status` says how a
+    /// download window was completed and is `None` for estimates and decodes.
+    Finished { task: String, cost_usd: Option<f64>, status: Option<TaskStatus> },
+    Failed { task: String, error: String },
+    /// The job was cancelled before the task finished; anything it had written is removed.
+    Cancelled { task: String },
+    /// Running total of the cost of finished tasks.
+    CostUpdated { total_usd: f64 },
+}
+
+impl ProgressEvent {
+    /// Task the event belongs to; `None` for run-wide events.
+    pub fn task(&self) -> Option<&str> {
+        match self {
+            ProgressEvent::Queued { task }
+            | ProgressEvent::Started { task }
+            | ProgressEvent::BytesReceived { task, .. }
+            | ProgressEvent::Retrying { task, .. }
+            | ProgressEvent::Finished { task, .. }
+            | ProgressEvent::Failed { task, .. }
+            | ProgressEvent::Cancelled { task } => Some(task),
+            ProgressEvent::CostUpdated { .. } => None,
+        }
+    }
+}
+
+/// How a download window was completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskStatus {
+    Downloaded,
+    /// A missing head or tail was fetched and merged into an existing file.
+    Extended,
+    /// Already on disk.
+    Skipped,
+}
+
+/// Receives progress events. Implemented for channels, closures and a shared `ProgressTracker`;
+/// events are emitted from worker tasks, so implementations must not block for long.
+pub trait ProgressSink: Send + Sync {
+    fn emit(&self, event: ProgressEvent);
+}
+
+/// Discards every event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn emit(&self, _event: ProgressEvent) {}
+}
+
+impl ProgressSink for UnboundedSender<ProgressEvent> {
+    fn emit(&self, event: ProgressEvent) {
+        // A dropped receiver only means nobody is watching any more
+        let _ = self.send(event);
+    }
+}
+
+impl<F: Fn(ProgressEvent) + Send + Sync> ProgressSink for F {
+    fn emit(&self, event: ProgressEvent) {
+        self(event)
+    }
+}
+
+impl ProgressSink for Mutex<ProgressTracker> {
+    fn emit(&self, event: ProgressEvent) {
+        if let Ok(mut tracker) = self.lock() {
+            tracker.apply(&event);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Queued,
+    Running,
+    /// Waiting out the backoff delay before another attempt.
+    Retrying,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+/// Latest known state of one task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskProgress {
+    pub task: String,
+    pub state: TaskState,
+    pub bytes: u64,
+    /// Tries so far, 0 while queued.
+    pub attempts: u32,
+    pub cost_usd: Option<f64>,
+    /// How a finished download window was completed.
+    pub status: Option<TaskStatus>,
+    pub error: Option<String>,
+}
+
+/// Folds progress events into per-task state and run totals, for progress bars and status lines.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProgressTracker {
+    tasks: Vec<TaskProgress>,
+    total_cost_usd: f64,
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&mut self, event: &ProgressEvent) {
+        if let ProgressEvent::CostUpdated { total_usd } = event {
+            self.total_cost_usd = *total_usd;
+            return;
+        }
+        let Some(name) = event.task() else { return };
+        let task = match self.tasks.iter().position(|task| task.task == name) {
+            Some(index) => &mut self.tasks[index],
+            None => {
+                self.tasks.push(TaskProgress {
+                    task: name.to_string(),
+                    state: TaskState::Queued,
+                    bytes: 0,
+                    attempts: 0,
+                    cost_usd: None,
+                    status: None,
+                    error: None,
+                });
+                self.tasks.last_mut().unwrap()
+            }
+        };
+
+        match event {
+            ProgressEvent::Queued { .. } => task.state = TaskState::Queued,
+            ProgressEvent::Started { .. } => {
+                task.state = TaskState::Running;
+                task.attempts = task.attempts.max(1);
+            }
+            ProgressEvent::BytesReceived { bytes, .. } => {
+                task.state = TaskState::Running;
+                task.bytes = *bytes;
+            }
+            ProgressEvent::Retrying { attempt, error, .. } => {
+                task.state = TaskState::Retrying;
+                task.attempts = attempt + 1;
+                task.error = Some(error.clone());
+            }
+            ProgressEvent::Finished { cost_usd, status, .. } => {
+                task.state = TaskState::Finished;
+                task.cost_usd = *cost_usd;
+                task.status = *status;
+                task.error = None;
+            }
+            ProgressEvent::Failed { error, .. } => {
+                task.state = TaskState::Failed;
+                task.error = Some(error.clone());
+            }
+            ProgressEvent::Cancelled { .. } => task.state = TaskState::Cancelled,
+            ProgressEvent::CostUpdated { .. } => {}
+        }
+    }
+
+    /// Tasks in the order they were first seen.
+    pub fn tasks(&self) -> &[TaskProgress] {
+        &self.tasks
+    }
+
+    pub fn count(&self, state: TaskState) -> usize {
+        self.tasks.iter().filter(|task| task.state == state).count()
+    }
+
+    /// Tasks that finished, failed or were cancelled.
+    pub fn done(&self) -> usize {
+        self.count(TaskState::Finished) + self.count(TaskState::Failed) + self.count(TaskState::Cancelled)
+    }
+
+    /// Fraction of tasks done, 0 before any task is known.
+    pub fn fraction(&self) -> f32 {
+        if self.tasks.is_empty() { 0.0 } else { self.done() as f32 / self.tasks.len() as f32 }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.tasks.iter().map(|task| task.bytes).sum()
+    }
+
+    pub fn total_cost_usd(&self) -> f64 {
+        self.total_cost_usd
+    }
+}
+
+impl fmt::Display for ProgressTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}/{}] {} running, {} failed, {:.1} MB, ${:.4}",
+            self.done(),
+            self.tasks.len(),
+            self.count(TaskState::Running) + self.count(TaskState::Retrying),
+            self.count(TaskState::Failed),
+            self.bytes() as f64 / 1_000_000.0,
+            self.total_cost_usd
+        )
+    }
+}
+
+/// Error of a job stopped through its cancellation token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Whether `error` (or its cause) is a cancellation rather than a failure.
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Cancelled>())
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn task(name: &str) -> String {
+        name.to_string()
+    }
+
+    #[test]
+    fn test_tracker_follows_task_lifecycle() {
+        let mut tracker = ProgressTracker::new();
+        for name in ["ESH2024", "ESM2024", "ESU2024"] {
+            tracker.apply(&ProgressEvent::Queued { task: task(name) });
+        }
+        tracker.apply(&ProgressEvent::Started { task: task("ESH2024") });
+        tracker.apply(&ProgressEvent::BytesReceived { task: task("ESH2024"), bytes: 1_500_000 });
+        tracker.apply(&ProgressEvent::Finished {
+            task: task("ESH2024"),
+            cost_usd: Some(1.25),
+            status: Some(TaskStatus::Downloaded),
+        });
+        tracker.apply(&ProgressEvent::CostUpdated { total_usd: 1.25 });
+        tracker.apply(&ProgressEvent::Started { task: task("ESM2024") });
+        tracker.apply(&ProgressEvent::Retrying {
+            task: task("ESM2024"),
+            attempt: 1,
+            delay: Duration::from_secs(1),
+            error: "502 Bad Gateway".to_string(),
+        });
+        tracker.apply(&ProgressEvent::Failed { task: task("ESU2024"), error: "symbol not found".to_string() });
+
+        assert_eq!(tracker.tasks().len(), 3);
+        assert_eq!(tracker.done(), 2);
+        assert_eq!(tracker.tasks()[1].state, TaskState::Retrying);
+        assert_eq!(tracker.tasks()[1].attempts, 2);
+        assert_eq!(tracker.to_string(), "[2/3] 1 running, 1 failed, 1.5 MB, $1.2500");
+    }
+
+    #[tokio::test]
+    async fn test_sinks() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let sink: Arc<dyn ProgressSink> = Arc::new(sender);
+        sink.emit(ProgressEvent::Started { task: task("CLZ2024") });
+        assert_eq!(receiver.recv().await.unwrap().task(), Some("CLZ2024"));
+
+        let tracker = Arc::new(Mutex::new(ProgressTracker::new()));
+        let sink: Arc<dyn ProgressSink> = tracker.clone();
+        sink.emit(ProgressEvent::Finished { task: task("CLZ2024"), cost_usd: None, status: Some(TaskStatus::Skipped) });
+        assert_eq!(tracker.lock().unwrap().count(TaskState::Finished), 1);
+        assert_eq!(tracker.lock().unwrap().tasks()[0].status, Some(TaskStatus::Skipped));
+    }
+}
@@ -0,0 +1,472 @@
+use anyhow::{Context, Result};
+use databento::dbn::{
+    decode::{AsyncDbnDecoder, DbnMetadata},
+    Record, Schema,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+use time::Date;
+
+use crate::downloader::contracts::parse_full_contract_symbol;
+use crate::downloader::output::DecodedFile;
+use crate::downloader::products::product_dataset;
+use crate::downloader::schemas::SUPPORTED_SCHEMAS;
+use crate::processor::ContractFile;
+
+/// Catalog file kept at the top of a download folder, e.g. `Hist_Fut_Data/catalog.json`.
+pub const CATALOG_FILE: &str = "catalog.json";
+
+/// How a catalogued file is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileEncoding {
+    /// Databento `.dbn.zst` as downloaded.
+    Dbn,
+    /// JSON lines written by the decoder.
+    Json,
+    /// Typed columns written by the decoder.
+    Parquet,
+    /// Comma-separated text written by the decoder.
+    Csv,
+    /// Arrow IPC (Feather v2) written by the decoder.
+    Arrow,
+}
+
+/// One downloaded or decoded file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    /// Path relative to the catalog folder, e.g. `ES/2024-01-05_2024-03-15_ESH2024.dbn.zst`.
+    pub path: PathBuf,
+    pub dataset: String,
+    /// Databento schema name, e.g. `ohlcv-1m`.
+    pub schema: String,
+    pub encoding: FileEncoding,
+    pub root: String,
+    /// Contract name with a four-digit year, e.g. `ESH2024`.
+    pub contract: String,
+    /// Raw symbol sent to Databento, e.g. `ESH4`; empty when the file was indexed after the fact.
+    #[serde(default)]
+    pub symbol: String,
+    /// Requested window, inclusive.
+    pub start: Date,
+    pub end: Date,
+    /// `ts_event` of the first and last record, `None` for an empty file.
+    pub first_ts: Option<u64>,
+    pub last_ts: Option<u64>,
+    pub record_count: u64,
+    pub byte_size: u64,
+    /// Hex SHA-256 of the file contents.
+    pub sha256: String,
+    /// Cost of the request in USD, when known.
+    pub cost_usd: Option<f64>,
+}
+
+/// Files to look up. Unset fields match everything; the date range matches entries whose
+/// requested window overlaps it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CatalogQuery {
+    pub root: Option<String>,
+    pub contract: Option<String>,
+    pub schema: Option<Schema>,
+    pub encoding: Option<FileEncoding>,
+    pub start: Option<Date>,
+    pub end: Option<Date>,
+}
+
+impl CatalogQuery {
+    /// Files of `root` in `schema` overlapping `start..=end`, e.g. ES 1m bars for March 2024.
+    pub fn bars(root: &str, schema: Schema, start: Date, end: Date) -> Self {
+        Self { root: Some(root.to_string()), schema: Some(schema), start: Some(start), end: Some(end), ..Self::default() }
+    }
+
+    pub fn matches(&self, entry: &CatalogEntry) -> bool {
+        self.root.as_ref().is_none_or(|root| *root == entry.root)
+            && self.contract.as_ref().is_none_or(|contract| *contract == entry.contract)
+            && self.schema.is_none_or(|schema| schema.as_str() == entry.schema)
+            && self.encoding.is_none_or(|encoding| encoding == entry.encoding)
+            && self.start.is_none_or(|start| entry.end >= start)
+            && self.end.is_none_or(|end| entry.start <= end)
+    }
+}
+
+/// Index of the files under one download folder, saved as `{base}/catalog.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Catalog {
+    #[serde(skip)]
+    base: PathBuf,
+    entries: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    /// Loads the catalog of `base`, or an empty one if the folder has none yet.
+    pub fn open(base: impl AsRef<Path>) -> Result<Self> {
+        let base = base.as_ref().to_path_buf();
+        let path = base.join(CATALOG_FILE);
+        if !path.exists() {
+            return Ok(Self { base, entries: Vec::new() });
+        }
+
+        let contents = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut catalog: Catalog =
+            serde_json::from_str(&contents).with_context(|| format!("Invalid catalog {}", path.display()))?;
+        catalog.base = base;
+        Ok(catalog)
+    }
+
+    /// Catalog of the folder holding `dir`: `dir` itself or one of its two parents, so a symbol folder
+    /// such as `Hist_Fut_Data/ES` finds `Hist_Fut_Data/catalog.json`.
+    pub fn locate(dir: impl AsRef<Path>) -> Result<Option<Self>> {
+        for base in dir.as_ref().ancestors().take(3) {
+            if base.join(CATALOG_FILE).exists() {
+                return Self::open(base).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Writes the catalog through a temporary file so a crash never leaves it half written.
+    pub fn save(&self) -> Result<()> {
+        fs::create_dir_all(&self.base).with_context(|| format!("Failed to create {}", self.base.display()))?;
+        let path = self.base.join(CATALOG_FILE);
+        let temp = self.base.join(format!("{CATALOG_FILE}.tmp"));
+        fs::write(&temp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, &path).with_context(|| format!("Failed to replace {}", path.display()))
+    }
+
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    pub fn entries(&self) -> &[CatalogEntry] {
+        &self.entries
+    }
+
+    /// Adds `entry`, replacing any entry for the same file.
+    pub fn upsert(&mut self, entry: CatalogEntry) {
+        match self.entries.iter_mut().find(|existing| existing.path == entry.path) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+        self.entries.sort_by(|a, b| (&a.root, a.start, &a.contract, &a.path).cmp(&(&b.root, b.start, &b.contract, &b.path)));
+    }
+
+    /// Removes the entry for `path` (relative to the catalog folder) and returns it.
+    pub fn remove(&mut self, path: impl AsRef<Path>) -> Option<CatalogEntry> {
+        let index = self.entries.iter().position(|entry| entry.path == path.as_ref())?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Drops entries whose file no longer exists. Returns how many were removed.
+    pub fn prune(&mut self) -> usize {
+        let before = self.entries.len();
+        let base = self.base.clone();
+        self.entries.retain(|entry| base.join(&entry.path).exists());
+        before - self.entries.len()
+    }
+
+    /// Entries matching `query`, ordered by root and window.
+    pub fn query(&self, query: &CatalogQuery) -> Vec<&CatalogEntry> {
+        self.entries.iter().filter(|entry| query.matches(entry)).collect()
+    }
+
+    /// Paths of the files matching `query`, ready to open.
+    pub fn resolve(&self, query: &CatalogQuery) -> Vec<PathBuf> {
+        self.query(query).into_iter().map(|entry| self.absolute_path(entry)).collect()
+    }
+
+    pub fn absolute_path(&self, entry: &CatalogEntry) -> PathBuf {
+        self.base.join(&entry.path)
+    }
+
+    /// Describes the file at `path` (under the catalog folder) and records it.
+    pub async fn record(&mut self, path: impl AsRef<Path>, details: FileDetails) -> Result<&CatalogEntry> {
+        let entry = describe_file(&self.base, path.as_ref(), details).await?;
+        let path = entry.path.clone();
+        self.upsert(entry);
+        Ok(self.entries.iter().find(|entry| entry.path == path).expect("Entry was just inserted"))
+    }
+
+    /// Indexes every contract file in the catalog folder, its symbol folders and their schema folders
+    /// that is not catalogued yet. Returns how many files were added.
+    pub async fn index_existing(&mut self) -> Result<usize> {
+        let mut dirs = vec![self.base.clone()];
+        if self.base.is_dir() {
+            for entry in fs::read_dir(&self.base)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    for schema in SUPPORTED_SCHEMAS {
+                        let schema_dir = path.join(schema.as_str());
+                        if schema_dir.is_dir() {
+                            dirs.push(schema_dir);
+                        }
+                    }
+                    dirs.push(path);
+                }
+            }
+        }
+
+        let mut added = 0;
+        for dir in dirs {
+            for file in crate::processor::list_contract_files(&dir)? {
+                let relative = file.path.strip_prefix(&self.base).unwrap_or(&file.path).to_path_buf();
+                if self.entries.iter().any(|entry| entry.path == relative) {
+                    continue;
+                }
+                self.record(&file.path, FileDetails::default()).await?;
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+}
+
+/// What the caller knows about a file that cannot be read back from it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileDetails {
+    /// Defaults to the product's dataset.
+    pub dataset: Option<String>,
+    pub symbol: String,
+    pub cost_usd: Option<f64>,
+}
+
+/// Builds the catalog entry of a contract file under `base`: window and contract from the file name,
+/// records, size and checksum from its contents.
+pub async fn describe_file(base: &Path, path: &Path, details: FileDetails) -> Result<CatalogEntry> {
+    let file = ContractFile::from_path(path)
+        .with_context(|| format!("'{}' is not a contract file", path.display()))?;
+    let (root, _, _) = parse_full_contract_symbol(&file.contract).expect("Contract file names a valid contract");
+
+    let (encoding, schema, record_count, first_ts, last_ts) = if let Some(schema) = file.decoded_schema() {
+        let (count, first, last) = scan_json_lines(path)?;
+        (FileEncoding::Json, schema.as_str().to_string(), count, first, last)
+    } else {
+        let (schema, count, first, last) = scan_dbn(path).await?;
+        (FileEncoding::Dbn, schema, count, first, last)
+    };
+
+    let dataset = match details.dataset {
+        Some(dataset) => dataset,
+        None => product_dataset(root)?,
+    };
+
+    Ok(CatalogEntry {
+        path: path.strip_prefix(base).unwrap_or(path).to_path_buf(),
+        dataset,
+        schema,
+        encoding,
+        root: root.to_string(),
+        contract: file.contract.clone(),
+        symbol: details.symbol,
+        start: file.start,
+        end: file.end,
+        first_ts,
+        last_ts,
+        record_count,
+        byte_size: fs::metadata(path)?.len(),
+        sha256: sha256_file(path)?,
+        cost_usd: details.cost_usd,
+    })
+}
+
+/// Builds the catalog entry of a file the decoder wrote from the raw file `raw`, from what the decoder
+/// counted rather than by reading the file back.
+pub fn describe_decoded(
+    base: &Path,
+    raw: &ContractFile,
+    schema: Schema,
+    encoding: FileEncoding,
+    output: &DecodedFile,
+    details: FileDetails,
+) -> Result<CatalogEntry> {
+    let (root, _, _) = parse_full_contract_symbol(&raw.contract).expect("Contract file names a valid contract");
+    let dataset = match details.dataset {
+        Some(dataset) => dataset,
+        None => product_dataset(root)?,
+    };
+
+    Ok(CatalogEntry {
+        path: output.path.strip_prefix(base).unwrap_or(&output.path).to_path_buf(),
+        dataset,
+        schema: schema.as_str().to_string(),
+        encoding,
+        root: root.to_string(),
+        contract: raw.contract.clone(),
+        symbol: details.symbol,
+        start: output.start,
+        end: output.end,
+        first_ts: output.first_ts,
+        last_ts: output.last_ts,
+        record_count: output.record_count,
+        byte_size: fs::metadata(&output.path)?.len(),
+        sha256: sha256_file(&output.path)?,
+        cost_usd: details.cost_usd,
+    })
+}
+
+/// Schema, record count and first/last `ts_event` of a `.dbn.zst` file.
+async fn scan_dbn(path: &Path) -> Result<(String, u64, Option<u64>, Option<u64>)> {
+    let mut decoder = AsyncDbnDecoder::from_zstd_file(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let schema = decoder.metadata().schema.map_or_else(|| "mixed".to_string(), |schema| schema.as_str().to_string());
+
+    let (mut count, mut first, mut last) = (0, None, None);
+    while let Some(record) = decoder.decode_record_ref().await? {
+        let ts_event = record.header().ts_event;
+        first = first.or(Some(ts_event));
+        last = Some(ts_event);
+        count += 1;
+    }
+    Ok((schema, count, first, last))
+}
+
+/// Record count and first/last `ts_event` of a decoded JSON lines file.
+fn scan_json_lines(path: &Path) -> Result<(u64, Option<u64>, Option<u64>)> {
+    let reader = BufReader::new(File::open(path).with_context(|| format!("Failed to open {}", path.display()))?);
+    let (mut count, mut first, mut last) = (0, None, None);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let ts_event = serde_json::from_str::<serde_json::Value>(&line)
+            .ok()
+            .and_then(|record| json_ts_event(&record))
+            .with_context(|| format!("Invalid record in {}", path.display()))?;
+        first = first.or(Some(ts_event));
+        last = Some(ts_event);
+        count += 1;
+    }
+    Ok((count, first, last))
+}
+
+/// `ts_event` of a decoded record: a top-level number in `JsonOhlcv` bars, a string in the record
+/// header of Databento's JSON for other schemas.
+fn json_ts_event(record: &serde_json::Value) -> Option<u64> {
+    let ts_event = record.get("ts_event").or_else(|| record.get("hd")?.get("ts_event"))?;
+    ts_event.as_u64().or_else(|| ts_event.as_str()?.parse().ok())
+}
+
+/// Hex SHA-256 of a file.
+pub fn sha256_file(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    let mut hex = String::with_capacity(64);
+    for byte in hasher.finalize().iter() {
+        write!(hex, "{byte:02x}").expect("Writing to a String cannot fail");
+    }
+    Ok(hex)
+}
+
+//-----------------------------------------------------------------------------------------------------------------//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::JsonOhlcv;
+    use time::macros::date;
+
+    fn entry(path: &str, root: &str, start: Date, end: Date) -> CatalogEntry {
+        CatalogEntry {
+            path: PathBuf::from(path),
+            dataset: "GLBX.MDP3".to_string(),
+            schema: "ohlcv-1m".to_string(),
+            encoding: FileEncoding::Dbn,
+            root: root.to_string(),
+            contract: format!("{root}H2024"),
+            symbol: format!("{root}H4"),
+            start,
+            end,
+            first_ts: None,
+            last_ts: None,
+            record_count: 0,
+            byte_size: 0,
+            sha256: String::new(),
+            cost_usd: None,
+        }
+    }
+
+    #[test]
+    fn test_query_by_root_schema_and_overlap() {
+        let mut catalog = Catalog::default();
+        catalog.upsert(entry("ES/a.dbn.zst", "ES", date!(2024 - 01 - 05), date!(2024 - 03 - 15)));
+        catalog.upsert(entry("ES/b.dbn.zst", "ES", date!(2024 - 03 - 05), date!(2024 - 06 - 14)));
+        catalog.upsert(entry("ES/c.dbn.zst", "ES", date!(2024 - 06 - 04), date!(2024 - 09 - 13)));
+        catalog.upsert(entry("NQ/a.dbn.zst", "NQ", date!(2024 - 03 - 05), date!(2024 - 06 - 14)));
+
+        let query = CatalogQuery::bars("ES", Schema::Ohlcv1M, date!(2024 - 03 - 01), date!(2024 - 04 - 01));
+        let paths = catalog.query(&query).iter().map(|e| e.path.to_string_lossy().into_owned()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["ES/a.dbn.zst", "ES/b.dbn.zst"]);
+
+        let trades = CatalogQuery { schema: Some(Schema::Trades), ..query };
+        assert!(catalog.query(&trades).is_empty());
+    }
+
+    #[test]
+    fn test_upsert_replaces_and_save_round_trips() {
+        let dir = "test_output_catalog";
+        let mut catalog = Catalog::open(dir).unwrap();
+        catalog.upsert(entry("ES/a.dbn.zst", "ES", date!(2024 - 01 - 05), date!(2024 - 03 - 15)));
+        let mut paid = entry("ES/a.dbn.zst", "ES", date!(2024 - 01 - 05), date!(2024 - 03 - 15));
+        paid.cost_usd = Some(0.25);
+        catalog.upsert(paid);
+        catalog.save().unwrap();
+
+        let reloaded = Catalog::open(dir).unwrap();
+        assert_eq!(reloaded.entries().len(), 1);
+        assert_eq!(reloaded.entries()[0].cost_usd, Some(0.25));
+        assert!(Catalog::locate(format!("{dir}/ES")).unwrap().is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record_decoded_file() {
+        let dir = "test_output_catalog_record";
+        fs::create_dir_all(format!("{dir}/ES")).unwrap();
+        let path = format!("{dir}/ES/2024-01-05_2024-03-15_ESH2024_ohlcv1m.json");
+        let bar = |ts_event| JsonOhlcv {
+            instrument_name: "ESH2024".to_string(),
+            instrument_id: 1,
+            ts_event,
+            open: 1,
+            high: 1,
+            low: 1,
+            close: 1,
+            volume: 1,
+        };
+        let lines = [bar(10), bar(20)].iter().map(|b| serde_json::to_string(b).unwrap()).collect::<Vec<_>>();
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        fs::create_dir_all(format!("{dir}/ES/trades")).unwrap();
+        let trade = r#"{"ts_recv":"31","hd":{"ts_event":"30","rtype":0,"publisher_id":1,"instrument_id":1},"price":"1"}"#;
+        fs::write(format!("{dir}/ES/trades/2024-01-05_2024-03-15_ESH2024_trades.json"), format!("{trade}\n")).unwrap();
+
+        let mut catalog = Catalog::open(dir).unwrap();
+        assert_eq!(catalog.index_existing().await.unwrap(), 2);
+        let trades = &catalog.entries()[1];
+        assert_eq!((trades.schema.as_str(), trades.record_count, trades.first_ts), ("trades", 1, Some(30)));
+        let entry = &catalog.entries()[0];
+        assert_eq!(entry.path, PathBuf::from("ES/2024-01-05_2024-03-15_ESH2024_ohlcv1m.json"));
+        assert_eq!((entry.encoding, entry.record_count, entry.first_ts, entry.last_ts), (FileEncoding::Json, 2, Some(10), Some(20)));
+        assert_eq!(entry.dataset, "GLBX.MDP3");
+        assert_eq!(entry.sha256.len(), 64);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
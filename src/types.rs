@@ -1,8 +1,9 @@
+use databento::dbn::Schema;
 use serde::{Deserialize, Serialize};
 use time::Date;
 use crate::client::DBClient;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct JsonOhlcv {
     pub instrument_name: String,
     pub instrument_id: u32,
@@ -18,7 +19,13 @@ pub struct JsonOhlcv {
 #[derive(Clone)]
 pub struct DownloadTask {
     pub client: DBClient,
+    pub dataset: String,
+    /// Raw symbol sent to Databento, e.g. `CLZ5`.
     pub symbol: String,
+    /// Contract name with a four-digit year, e.g. `CLZ2025`, used in file names.
+    pub contract: String,
+    pub schema: Schema,
+    /// Folder of the contract's files in `schema`, e.g. `Hist_Fut_Data/CL` or `Hist_Fut_Data/CL/trades`.
     pub base_path: String,
     pub start: Date,
     pub end: Date,